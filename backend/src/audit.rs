@@ -0,0 +1,99 @@
+//! The append-only audit log: who changed, downloaded or shared what, and from where.
+//!
+//! Entries are written by the frontends (HTTP handlers, SFTP) after an action
+//! succeeded, and can be queried with `GET /admin/audit`.
+
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::client::Client;
+
+/// What an entry is about, e.g. `file.delete` on a file.
+#[derive(Debug)]
+pub struct Event {
+    action: &'static str,
+    target_type: &'static str,
+    target_id: Uuid,
+    old_value: Option<Value>,
+    new_value: Option<Value>,
+}
+
+impl Event {
+    pub fn file(action: &'static str, file_id: Uuid) -> Self {
+        Self::new(action, "file", file_id)
+    }
+
+    pub fn folder(action: &'static str, folder_id: Uuid) -> Self {
+        Self::new(action, "folder", folder_id)
+    }
+
+    pub fn vault(action: &'static str, vault_id: Uuid) -> Self {
+        Self::new(action, "vault", vault_id)
+    }
+
+    pub fn user(action: &'static str, user_id: Uuid) -> Self {
+        Self::new(action, "user", user_id)
+    }
+
+    pub fn token(action: &'static str, token_id: Uuid) -> Self {
+        Self::new(action, "token", token_id)
+    }
+
+    pub fn team(action: &'static str, team_id: Uuid) -> Self {
+        Self::new(action, "team", team_id)
+    }
+
+    fn new(action: &'static str, target_type: &'static str, target_id: Uuid) -> Self {
+        Self {
+            action,
+            target_type,
+            target_id,
+            old_value: None,
+            new_value: None,
+        }
+    }
+
+    /// The target's state before the action.
+    pub fn before(mut self, value: impl Serialize) -> Self {
+        self.old_value = Some(to_value(value));
+        self
+    }
+
+    /// The target's state after the action, or details of a read.
+    pub fn after(mut self, value: impl Serialize) -> Self {
+        self.new_value = Some(to_value(value));
+        self
+    }
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("audit values serialize to JSON")
+}
+
+/// Appends `event`, done by `actor_id` from `client`, to the log.
+pub async fn record(
+    db: impl PgExecutor<'_>,
+    actor_id: Uuid,
+    client: &Client,
+    event: Event,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (actor_id, action, target_type, target_id, old_value, new_value, ip, user_agent)
+        VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::INET, $8)
+        "#,
+        actor_id,
+        event.action,
+        event.target_type,
+        event.target_id,
+        event.old_value,
+        event.new_value,
+        client.ip.map(|ip| ip.to_string()),
+        client.user_agent
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
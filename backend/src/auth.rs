@@ -0,0 +1,441 @@
+//! Passwords, and who a request comes from.
+
+use std::{net::IpAddr, time::Duration};
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    client, integrity,
+    routes::{Error, team::Role},
+    session,
+    state::App,
+};
+
+/// Start of every API token, so they are easy to recognize (e.g. by secret scanners).
+pub const TOKEN_PREFIX: &str = "cloud_";
+/// How much of a token is kept in the clear, to tell tokens apart.
+pub const TOKEN_DISPLAY_LEN: usize = TOKEN_PREFIX.len() + 6;
+/// `last_used_at` of a token is updated at most this often.
+const LAST_USED_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Who an identity provider or LDAP directory says logged in.
+#[derive(Debug)]
+pub struct Identity {
+    /// Lowercase.
+    pub email: String,
+    pub name: Option<String>,
+    /// Lowercase, as the configured groups.
+    pub groups: Vec<String>,
+    pub subject: Subject,
+}
+
+/// Who the user is to the identity provider, unlike their email for good.
+#[derive(Debug)]
+pub enum Subject {
+    /// The `sub` claim, unique for the issuer.
+    Oidc { issuer: String, subject: String },
+    /// The distinguished name of the directory entry.
+    Ldap { dn: String },
+}
+
+impl Identity {
+    /// Whether the user is in any of `groups`.
+    pub fn in_any(&self, groups: &[String]) -> bool {
+        self.groups.iter().any(|group| groups.contains(group))
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// What an API token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// List, search and download.
+    Read,
+    /// Upload, rename, move and delete; includes `upload`.
+    Write,
+    /// Only upload new files.
+    Upload,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Upload => "upload",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "upload" => Some(Scope::Upload),
+            _ => None,
+        }
+    }
+}
+
+/// The API token a request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub scopes: Vec<Scope>,
+    /// The token only reaches this folder and what is below it.
+    pub folder_id: Option<Uuid>,
+}
+
+/// The user a request is from.
+///
+/// Requests with `Authorization: Bearer <token>` are authenticated with an API
+/// token and limited to its scopes; handlers check them with [`User::require`]
+/// and friends. Requests with a [session](crate::session) cookie have full access.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: Uuid,
+    /// `None` for browser sessions.
+    pub token: Option<Token>,
+}
+
+impl FromRequestParts<App> for User {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &App) -> Result<Self, Self::Rejection> {
+        // authenticated before, e.g. by the rate limiter
+        if let Some(user) = parts.extensions.get::<User>() {
+            return Ok(user.clone());
+        }
+
+        let user = if let Some(value) = parts.headers.get(header::AUTHORIZATION) {
+            let token = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or(Error::Unauthorized)?;
+            let ip = client::ip(&parts.headers, &parts.extensions, state.trust_proxy);
+            authenticate(&state.db, token.trim(), ip).await?
+        } else if let Some(token) = session::token(&parts.headers) {
+            let id = session::authenticate(&state.db, token)
+                .await?
+                .ok_or(Error::Unauthorized)?;
+            User { id, token: None }
+        } else if state.sessions.require_login || local_logins(&state.db).await? {
+            return Err(Error::Unauthorized);
+        } else {
+            // without logins, everything belongs to the default user
+            User {
+                id: Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(), // placeholder
+                token: None,
+            }
+        };
+        parts.extensions.insert(user.clone());
+        Ok(user)
+    }
+}
+
+impl User {
+    /// Checks that a token has `scope`; sessions may do anything.
+    pub fn require(&self, scope: Scope) -> Result<(), Error> {
+        let Some(token) = &self.token else {
+            return Ok(());
+        };
+        let granted = token.scopes.contains(&scope)
+            || (scope == Scope::Upload && token.scopes.contains(&Scope::Write));
+        if granted {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// For account settings and the like, which tokens must not change.
+    pub fn require_session(&self) -> Result<(), Error> {
+        match self.token {
+            Some(_) => Err(Error::Forbidden),
+            None => Ok(()),
+        }
+    }
+
+    /// For views across the whole account, which a folder-restricted token must not see.
+    pub fn require_unrestricted(&self) -> Result<(), Error> {
+        match self.folder() {
+            Some(_) => Err(Error::Forbidden),
+            None => Ok(()),
+        }
+    }
+
+    /// The folder a token is restricted to; requests without a folder default to it.
+    pub fn folder(&self) -> Option<Uuid> {
+        self.token.as_ref().and_then(|token| token.folder_id)
+    }
+
+    /// Checks that a folder-restricted token reaches `folder_id`: it is the token's
+    /// folder or somewhere below it.
+    pub async fn ensure_folder(&self, db: &PgPool, folder_id: Uuid) -> Result<(), Error> {
+        let Some(allowed) = self.folder() else {
+            return Ok(());
+        };
+        let inside = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent_id FROM folders WHERE id = $1 AND user_id = $2
+                -- UNION, not UNION ALL: a cycle in `parent_id` must not loop forever
+                UNION
+                SELECT f.id, f.parent_id FROM folders f
+                JOIN ancestors a ON f.id = a.parent_id
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $3) AS "inside!"
+            "#,
+            folder_id,
+            self.id,
+            allowed
+        )
+        .fetch_one(db)
+        .await?;
+        if inside {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// `folder_id`, defaulting to the token's folder, checked with
+    /// [`ensure_folder`](Self::ensure_folder). `None` is still the root folder.
+    pub async fn folder_or_default(
+        &self,
+        db: &PgPool,
+        folder_id: Option<Uuid>,
+    ) -> Result<Option<Uuid>, Error> {
+        let folder_id = folder_id.or(self.folder());
+        if let Some(folder_id) = folder_id {
+            self.ensure_folder(db, folder_id).await?;
+        }
+        Ok(folder_id)
+    }
+
+    /// [`ensure_folder`](Self::ensure_folder) for the folder of a file.
+    pub async fn ensure_file(&self, db: &PgPool, file_id: Uuid) -> Result<(), Error> {
+        if self.folder().is_none() {
+            return Ok(());
+        }
+        let folder_id = sqlx::query_scalar!(
+            r#"
+            SELECT folder_id FROM files WHERE id = $1 AND user_id = $2
+            "#,
+            file_id,
+            self.id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+        self.ensure_folder(db, folder_id).await
+    }
+
+    /// The drive `folder_id` is in, i.e. the owner to pass on to the folder and file
+    /// helpers: the user themselves, or a [team](crate::routes::team) they have at
+    /// least `role` in. `None` is the user's own drive.
+    ///
+    /// Drive ids are user or team ids, kept in the `user_id` columns of folders,
+    /// files and photo metadata.
+    pub async fn drive(
+        &self,
+        db: &PgPool,
+        folder_id: Option<Uuid>,
+        role: Role,
+    ) -> Result<Uuid, Error> {
+        let Some(folder_id) = folder_id else {
+            return Ok(self.id);
+        };
+        let owner = sqlx::query!(
+            r#"
+            SELECT f.user_id, m.role AS "role?" FROM folders f
+            LEFT JOIN team_members m ON m.team_id = f.user_id AND m.user_id = $2
+            WHERE f.id = $1
+            "#,
+            folder_id,
+            self.id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+        self.check_drive(owner.user_id, owner.role.as_deref(), role)
+    }
+
+    /// [`drive`](Self::drive) for the folder of a file.
+    pub async fn file_drive(&self, db: &PgPool, file_id: Uuid, role: Role) -> Result<Uuid, Error> {
+        let owner = sqlx::query!(
+            r#"
+            SELECT f.user_id, m.role AS "role?" FROM files f
+            LEFT JOIN team_members m ON m.team_id = f.user_id AND m.user_id = $2
+            WHERE f.id = $1
+            "#,
+            file_id,
+            self.id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+        self.check_drive(owner.user_id, owner.role.as_deref(), role)
+    }
+
+    /// The drive of a team the user has at least `role` in; `None` is the user's own drive.
+    pub async fn team_drive(
+        &self,
+        db: &PgPool,
+        team_id: Option<Uuid>,
+        role: Role,
+    ) -> Result<Uuid, Error> {
+        let Some(team_id) = team_id else {
+            return Ok(self.id);
+        };
+        let member = sqlx::query_scalar!(
+            r#"
+            SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2
+            "#,
+            team_id,
+            self.id
+        )
+        .fetch_optional(db)
+        .await?;
+        self.check_drive(team_id, member.as_deref(), role)
+    }
+
+    fn check_drive(&self, owner_id: Uuid, member: Option<&str>, role: Role) -> Result<Uuid, Error> {
+        if owner_id == self.id {
+            return Ok(owner_id);
+        }
+        // someone else's drive doesn't exist as far as the user is concerned
+        let member = member.and_then(Role::parse).ok_or(Error::NotFound)?;
+        // folder-restricted tokens stay inside their folder in the user's own drive
+        self.require_unrestricted()?;
+        if member < role {
+            return Err(Error::Forbidden);
+        }
+        Ok(owner_id)
+    }
+}
+
+/// A new random API token; only its hash is stored.
+pub fn generate_token() -> String {
+    format!("{TOKEN_PREFIX}{}", generate_secret())
+}
+
+/// 32 random bytes, base64-encoded so they fit in URLs, headers and cookies.
+pub fn generate_secret() -> String {
+    let secret: [u8; 32] = rand::random();
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+pub fn hash_token(token: &str) -> integrity::Checksum {
+    // tokens are long and random, a fast hash is as good as a password hash
+    integrity::sha256(token.as_bytes())
+}
+
+/// Whether any account has a password to log in with; from then on nobody acts as
+/// the default user without logging in.
+async fn local_logins(db: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM users WHERE password_hash IS NOT NULL) AS "exists!"
+        "#
+    )
+    .fetch_one(db)
+    .await
+}
+
+async fn authenticate(db: &PgPool, token: &str, ip: Option<IpAddr>) -> Result<User, Error> {
+    let token_hash = hash_token(token);
+    let token = sqlx::query!(
+        r#"
+        SELECT t.id, t.user_id, t.scopes, t.folder_id,
+               COALESCE(t.last_used_at < now() - make_interval(secs => $2), true) AS "stale!"
+        FROM api_tokens t JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1 AND (t.expires_at IS NULL OR t.expires_at > now())
+          AND u.disabled_at IS NULL
+        "#,
+        token_hash.as_slice(),
+        LAST_USED_RESOLUTION.as_secs_f64()
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    if token.stale {
+        sqlx::query!(
+            r#"
+            UPDATE api_tokens SET last_used_at = now(), last_used_ip = $2::TEXT::INET WHERE id = $1
+            "#,
+            token.id,
+            ip.map(|ip| ip.to_string())
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(User {
+        id: token.user_id,
+        token: Some(Token {
+            scopes: token
+                .scopes
+                .iter()
+                .filter_map(|scope| Scope::parse(scope))
+                .collect(),
+            folder_id: token.folder_id,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{config::Config, state::AppState};
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn anonymous_requests_need_a_login_once_passwords_exist(db: PgPool) {
+        let state: App = Arc::new(AppState::new(&Config::from_env(), db.clone()));
+        let anonymous = async || {
+            let (mut parts, ()) = axum::http::Request::new(()).into_parts();
+            User::from_request_parts(&mut parts, &state).await
+        };
+
+        // without any way to log in, everything belongs to the default user
+        assert!(anonymous().await.is_ok());
+
+        let hash = hash_password("correct horse").unwrap();
+        sqlx::query!(
+            "INSERT INTO users (email, password_hash) VALUES ('local@example.com', $1)",
+            hash
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert!(matches!(anonymous().await, Err(Error::Unauthorized)));
+    }
+}
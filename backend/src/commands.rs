@@ -0,0 +1,72 @@
+//! Maintenance commands, run as `backend <command>` instead of starting the server.
+//! They share the server's configuration and can run while it is up.
+
+use std::process::ExitCode;
+
+use crate::{fsck, integrity, state::AppState};
+
+const USAGE: &str = "usage: backend [verify | fsck [--repair]]";
+
+pub async fn run(state: &AppState, args: &[String]) -> ExitCode {
+    match args.first().map(String::as_str) {
+        Some("verify") => verify(state).await,
+        Some("fsck") => fsck(state, args[1..].iter().any(|arg| arg == "--repair")).await,
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Re-hashes all blobs; fails if any file is corrupt, missing or unreadable.
+async fn verify(state: &AppState) -> ExitCode {
+    let report = match integrity::verify(state).await {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("verify failed: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("verified: {}", report.verified);
+    println!("hashed for the first time: {}", report.hashed);
+    println!("corrupt: {}", report.corrupt);
+    println!("missing: {}", report.missing);
+    println!("unreadable: {}", report.failed);
+
+    if report.corrupt > 0 || report.missing > 0 || report.failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Compares disk and database; fails if they disagree, even if it repaired that.
+async fn fsck(state: &AppState, repair: bool) -> ExitCode {
+    let report = match fsck::check(state, repair).await {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("fsck failed: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+    println!(
+        "{} files checked, {} problems",
+        report.files,
+        report.problems.len()
+    );
+    if repair {
+        println!("quarantined: {}", report.quarantined);
+        println!("marked as missing or corrupt: {}", report.marked);
+    }
+
+    if report.problems.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
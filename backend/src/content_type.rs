@@ -0,0 +1,110 @@
+use crate::{config::UploadConfig, routes::Error};
+
+/// How much of the start of a file [`check`] has to see, enough for every format
+/// `infer` knows.
+pub const SNIFF_LEN: usize = 64 * 1024;
+/// How much of a file is checked when deciding whether it is text.
+const TEXT_SNIFF_LEN: usize = 8 * 1024;
+
+/// Determines the content type of an upload from its magic bytes, falling back to
+/// its extension, and checks it against the configured allow/deny lists.
+///
+/// Uploads whose contents contradict their extension (e.g. an executable named
+/// `holiday.jpg`) are rejected unless `CLOUD_UPLOAD_ALLOW_MISMATCH` is set.
+pub fn check(config: &UploadConfig, filename: &str, data: &[u8]) -> Result<String, Error> {
+    let guessed: Vec<String> = mime_guess::from_path(filename)
+        .iter()
+        .map(|mime| mime.essence_str().to_string())
+        .collect();
+    let sniffed = infer::get(data).map(|kind| kind.mime_type());
+
+    let (mime_type, mismatch) = match sniffed {
+        // the text matchers of `infer` are vague, the extension says more
+        Some(sniffed) if sniffed.starts_with("text/") => (
+            guessed.first().cloned().unwrap_or(sniffed.to_string()),
+            false,
+        ),
+        Some(sniffed) => {
+            let compatible = guessed.iter().find(|guess| compatible(sniffed, guess));
+            match compatible {
+                // keep the more specific type of container formats, e.g. `application/epub+zip`
+                Some(guess)
+                    if matches!(sniffed, "application/zip" | "application/x-ole-storage") =>
+                {
+                    (guess.clone(), false)
+                }
+                Some(_) => (sniffed.to_string(), false),
+                None => (sniffed.to_string(), !guessed.is_empty()),
+            }
+        }
+        None if is_text(data) => {
+            let mismatch = guessed.first().is_some_and(|guess| {
+                ["image/", "audio/", "video/"]
+                    .iter()
+                    .any(|family| guess.starts_with(family))
+                    && !guess.ends_with("+xml")
+            });
+            let mime_type = guessed.first().cloned().unwrap_or("text/plain".to_string());
+            (mime_type, mismatch)
+        }
+        None => (
+            guessed
+                .first()
+                .cloned()
+                .unwrap_or("application/octet-stream".to_string()),
+            false,
+        ),
+    };
+
+    if mismatch && config.reject_mismatch {
+        return Err(Error::UnsupportedMediaType(format!(
+            "The contents of {filename} ({mime_type}) do not match its extension"
+        )));
+    }
+    if !config.allows(&mime_type) {
+        return Err(Error::UnsupportedMediaType(format!(
+            "Files of type {mime_type} are not allowed"
+        )));
+    }
+
+    Ok(mime_type)
+}
+
+/// Whether content detected as `sniffed` may carry an extension that suggests `guess`.
+///
+/// For media only the top-level type has to agree (a PNG named `.jpg` is still an
+/// image), and zip or OLE containers may be any format built on them (`.jar`,
+/// `.epub`, `.msg`, ...). Anything else has to match exactly.
+fn compatible(sniffed: &str, guess: &str) -> bool {
+    let family = |mime: &str| mime.split('/').next().unwrap_or_default().to_string();
+    let container = matches!(sniffed, "application/zip" | "application/x-ole-storage");
+    sniffed == guess
+        || (family(sniffed) == family(guess)
+            && ["image", "audio", "video", "font"].contains(&family(guess).as_str()))
+        || (container && guess.starts_with("application/"))
+}
+
+/// Valid UTF-8 without NUL bytes, ignoring a character cut off at the end of the sample.
+fn is_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(TEXT_SNIFF_LEN)];
+    if sample.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && sample.len() == TEXT_SNIFF_LEN,
+    }
+}
+
+impl UploadConfig {
+    /// Checks a content type against the deny list (which wins) and the allow list
+    /// (where empty means everything). Entries are types like `application/pdf` or
+    /// whole families like `image/*`.
+    pub fn allows(&self, mime_type: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(family) => mime_type.split('/').next() == Some(family),
+            None => pattern == "*" || pattern == mime_type,
+        };
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
@@ -0,0 +1,535 @@
+use std::{
+    io::{self, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{Aead, Payload},
+};
+use axum::body::Bytes;
+use futures_util::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+
+use crate::{
+    config::MasterKey,
+    state::{App, AppState},
+};
+
+/// Encrypted blobs are split into chunks of this much plain text, each sealed on
+/// its own, so a range can be decrypted without reading the whole file.
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+/// Size of the authentication tag following every chunk.
+const TAG_SIZE: u64 = 16;
+const NONCE_SIZE: usize = 12;
+/// How many data keys are re-wrapped per query after a key rotation.
+const BATCH_SIZE: i64 = 500;
+
+/// The master keys data keys are wrapped with. The first one wraps new keys, the
+/// others are only kept to unwrap keys from before a rotation.
+pub struct MasterKeys(Vec<(String, Aes256Gcm)>);
+
+/// A data key for a new file, along with how it is stored in `files`.
+pub struct NewKey {
+    pub key_id: String,
+    pub wrapped: Vec<u8>,
+    pub key: DataKey,
+}
+
+impl MasterKeys {
+    pub fn new(keys: &[MasterKey]) -> Self {
+        Self(
+            keys.iter()
+                .map(|key| (key.id.clone(), Aes256Gcm::new(&key.key.into())))
+                .collect(),
+        )
+    }
+
+    /// The id of the key new data keys are wrapped with; `None` if encryption is disabled.
+    pub fn current_id(&self) -> Option<&str> {
+        self.0.first().map(|(id, _)| id.as_str())
+    }
+
+    /// A random data key for a new file, or `None` if encryption is disabled.
+    pub fn generate(&self, file_id: Uuid) -> Option<NewKey> {
+        let key_id = self.current_id()?.to_string();
+        let key: [u8; 32] = rand::random();
+        Some(NewKey {
+            key_id,
+            wrapped: self.wrap(&key, file_id),
+            key: DataKey::new(&key),
+        })
+    }
+
+    /// Unwraps the data key of a file.
+    pub fn unwrap(&self, key_id: &str, wrapped: &[u8], file_id: Uuid) -> io::Result<DataKey> {
+        Ok(DataKey::new(&self.unwrap_key(key_id, wrapped, file_id)?))
+    }
+
+    /// Wraps a file's data key with the current master key instead of `key_id`.
+    fn rewrap(&self, key_id: &str, wrapped: &[u8], file_id: Uuid) -> io::Result<Vec<u8>> {
+        let key = self.unwrap_key(key_id, wrapped, file_id)?;
+        Ok(self.wrap(&key, file_id))
+    }
+
+    /// Encrypts a data key with the current master key; the file id is authenticated
+    /// along with it, so wrapped keys can't be swapped between files.
+    fn wrap(&self, key: &[u8; 32], file_id: Uuid) -> Vec<u8> {
+        let (_, master) = &self.0[0];
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let payload = Payload {
+            msg: key,
+            aad: file_id.as_bytes(),
+        };
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend(
+            master
+                .encrypt(&nonce.into(), payload)
+                .expect("a key is far below the AES-GCM limit"),
+        );
+        wrapped
+    }
+
+    fn unwrap_key(&self, key_id: &str, wrapped: &[u8], file_id: Uuid) -> io::Result<[u8; 32]> {
+        let Some((_, master)) = self.0.iter().find(|(id, _)| id == key_id) else {
+            return Err(io::Error::other(format!(
+                "master key {key_id} is not configured"
+            )));
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid wrapped data key");
+        let (nonce, ciphertext) = wrapped.split_at_checked(NONCE_SIZE).ok_or_else(invalid)?;
+        let nonce: [u8; NONCE_SIZE] = nonce.try_into().unwrap();
+        let payload = Payload {
+            msg: ciphertext,
+            aad: file_id.as_bytes(),
+        };
+        master
+            .decrypt(&nonce.into(), payload)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(invalid)
+    }
+}
+
+/// A file's own key. Replaced contents get a new key, so every key encrypts exactly
+/// one plain text and the chunk index can serve as the nonce.
+pub struct DataKey(Aes256Gcm);
+
+impl DataKey {
+    fn new(key: &[u8; 32]) -> Self {
+        Self(Aes256Gcm::new(&(*key).into()))
+    }
+
+    /// The chunk index, and whether it is the last chunk so a truncated blob fails
+    /// to decrypt instead of silently ending early.
+    fn nonce(index: u64, last: bool) -> [u8; NONCE_SIZE] {
+        let mut nonce = [0; NONCE_SIZE];
+        nonce[..8].copy_from_slice(&index.to_be_bytes());
+        nonce[NONCE_SIZE - 1] = last as u8;
+        nonce
+    }
+
+    fn encrypt_chunk(&self, index: u64, last: bool, chunk: &[u8]) -> Vec<u8> {
+        self.0
+            .encrypt(&Self::nonce(index, last).into(), chunk)
+            .expect("a chunk is far below the AES-GCM limit")
+    }
+
+    fn decrypt_chunk(&self, index: u64, last: bool, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.0
+            .decrypt(&Self::nonce(index, last).into(), chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "blob failed authentication"))
+    }
+}
+
+/// An empty file still has one (empty) chunk, so its tag proves it is empty.
+fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(CHUNK_SIZE).max(1)
+}
+
+/// Size on disk of an encrypted file of `size` bytes.
+pub fn encrypted_size(size: u64) -> u64 {
+    size + chunk_count(size) * TAG_SIZE
+}
+
+/// Writes a new file's contents to `path`, encrypted if it has a data key. They are
+/// read a chunk at a time and have to be exactly `size` bytes long.
+pub async fn write(
+    path: &Path,
+    mut contents: impl AsyncRead + Unpin,
+    size: u64,
+    key: Option<&DataKey>,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(tokio::fs::File::create(path).await?);
+    let chunks = chunk_count(size);
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE as usize);
+    for index in 0..chunks {
+        chunk.resize(CHUNK_SIZE.min(size - index * CHUNK_SIZE) as usize, 0);
+        contents.read_exact(&mut chunk).await?;
+        match key {
+            Some(key) => {
+                let encrypted = key.encrypt_chunk(index, index == chunks - 1, &chunk);
+                file.write_all(&encrypted).await?;
+            }
+            None => file.write_all(&chunk).await?,
+        }
+    }
+    if contents.read(&mut [0]).await? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "contents are longer than their size",
+        ));
+    }
+    file.flush().await
+}
+
+/// A file's contents on disk, decrypted on the fly if they are encrypted.
+pub struct Blob {
+    file: tokio::fs::File,
+    size: u64,
+    key: Option<DataKey>,
+}
+
+impl Blob {
+    /// Opens the contents of one of the files in a drive.
+    pub async fn open(state: &AppState, drive_id: Uuid, file_id: Uuid) -> io::Result<Self> {
+        let row = sqlx::query!(
+            r#"
+            SELECT size, key_id, data_key FROM files WHERE id = $1 AND user_id = $2
+            "#,
+            file_id,
+            drive_id
+        )
+        .fetch_optional(&state.db)
+        .await
+        .map_err(io::Error::other)?
+        .ok_or(io::ErrorKind::NotFound)?;
+
+        let key = match (row.key_id, row.data_key) {
+            (Some(key_id), Some(wrapped)) => Some(state.keys.unwrap(&key_id, &wrapped, file_id)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            file: tokio::fs::File::open(state.blob_path(drive_id, file_id)).await?,
+            size: row.size as u64,
+            key,
+        })
+    }
+
+    /// Size of the plain text.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Reads `len` bytes of plain text at `offset`, fewer only at the end of the file.
+    pub async fn read_at(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let end = self.size.min(offset.saturating_add(len));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+
+        let Some(key) = &self.key else {
+            self.file.seek(SeekFrom::Start(offset)).await?;
+            let mut data = vec![0; (end - offset) as usize];
+            self.file.read_exact(&mut data).await?;
+            return Ok(data);
+        };
+
+        let chunks = chunk_count(self.size);
+        let first = offset / CHUNK_SIZE;
+        let last = (end - 1) / CHUNK_SIZE;
+        self.file
+            .seek(SeekFrom::Start(first * (CHUNK_SIZE + TAG_SIZE)))
+            .await?;
+        let mut data = Vec::with_capacity(((last - first + 1) * CHUNK_SIZE) as usize);
+        for index in first..=last {
+            let len = CHUNK_SIZE.min(self.size - index * CHUNK_SIZE) + TAG_SIZE;
+            let mut chunk = vec![0; len as usize];
+            self.file.read_exact(&mut chunk).await?;
+            data.extend(key.decrypt_chunk(index, index == chunks - 1, &chunk)?);
+        }
+
+        let start = (offset - first * CHUNK_SIZE) as usize;
+        data.truncate(start + (end - offset) as usize);
+        data.drain(..start);
+        Ok(data)
+    }
+
+    /// Reads the whole plain text.
+    pub async fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        self.read_at(0, self.size).await
+    }
+
+    /// The plain text in `range`, read a chunk at a time.
+    pub fn into_stream(self, range: Range<u64>) -> impl Stream<Item = io::Result<Bytes>> {
+        let end = range.end.min(self.size);
+        futures_util::stream::try_unfold(
+            (self, range.start),
+            move |(mut blob, position)| async move {
+                if position >= end {
+                    return Ok(None);
+                }
+                let next = end.min((position / CHUNK_SIZE + 1) * CHUNK_SIZE);
+                let data = blob.read_at(position, next - position).await?;
+                Ok(Some((Bytes::from(data), (blob, next))))
+            },
+        )
+    }
+}
+
+/// A path external programs can read a file's plain text from: the blob itself,
+/// or a decrypted copy next to it that is removed again when this is dropped.
+pub struct PlainFile {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl PlainFile {
+    pub async fn new(state: &AppState, drive_id: Uuid, file_id: Uuid) -> io::Result<Self> {
+        let path = state.blob_path(drive_id, file_id);
+        let mut blob = Blob::open(state, drive_id, file_id).await?;
+        if !blob.is_encrypted() {
+            return Ok(Self {
+                path,
+                temporary: false,
+            });
+        }
+
+        let plain = Self {
+            path: path.with_extension("plain"),
+            temporary: true,
+        };
+        let mut out = tokio::fs::File::create(&plain.path).await?;
+        let mut offset = 0;
+        while offset < blob.size() {
+            let data = blob.read_at(offset, CHUNK_SIZE).await?;
+            out.write_all(&data).await?;
+            offset += data.len() as u64;
+        }
+        out.flush().await?;
+        Ok(plain)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PlainFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Runs once at startup. After a key rotation (a new master key configured in
+/// front of the old ones), re-wraps the data keys still wrapped with an old key;
+/// the blobs themselves are left untouched. Once this is done, the old key can be
+/// removed from the configuration.
+pub async fn rewrap(state: App) {
+    if let Err(err) = check_keys(&state).await {
+        tracing::error!("Could not check the master keys: {err}");
+    }
+
+    let Some(current) = state.keys.current_id() else {
+        return;
+    };
+    let old: Vec<String> = state.keys.0[1..].iter().map(|(id, _)| id.clone()).collect();
+    if old.is_empty() {
+        return;
+    }
+
+    let mut total = 0;
+    loop {
+        match rewrap_batch(&state, current, &old).await {
+            Ok(0) => break,
+            Ok(count) => total += count,
+            Err(err) => {
+                tracing::error!("Could not re-wrap data keys: {err}");
+                return;
+            }
+        }
+    }
+    tracing::info!("Re-wrapped {total} data keys with master key {current}");
+}
+
+/// Warns about files whose master key is missing from the configuration.
+async fn check_keys(state: &App) -> Result<(), sqlx::Error> {
+    let configured: Vec<String> = state.keys.0.iter().map(|(id, _)| id.clone()).collect();
+    let missing = sqlx::query!(
+        r#"
+        SELECT key_id AS "key_id!", count(*) AS "count!" FROM files
+        WHERE key_id IS NOT NULL AND key_id <> ALL($1)
+        GROUP BY key_id
+        "#,
+        &configured
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    for key in missing {
+        tracing::error!(
+            "{} files are encrypted with master key {}, which is not configured",
+            key.count,
+            key.key_id
+        );
+    }
+    Ok(())
+}
+
+async fn rewrap_batch(
+    state: &App,
+    current: &str,
+    old: &[String],
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let files = sqlx::query!(
+        r#"
+        SELECT id, key_id AS "key_id!", data_key AS "data_key!" FROM files
+        WHERE key_id = ANY($1)
+        LIMIT $2
+        "#,
+        old,
+        BATCH_SIZE
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    for file in &files {
+        let wrapped = state.keys.rewrap(&file.key_id, &file.data_key, file.id)?;
+        sqlx::query!(
+            r#"
+            UPDATE files SET key_id = $1, data_key = $2 WHERE id = $3 AND key_id = $4
+            "#,
+            current,
+            wrapped,
+            file.id,
+            file.key_id
+        )
+        .execute(&state.db)
+        .await?;
+    }
+
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_key(id: &str) -> MasterKey {
+        MasterKey {
+            id: id.to_string(),
+            key: rand::random(),
+        }
+    }
+
+    /// Writes `data` encrypted to a temporary file and opens it as a blob.
+    async fn blob(data: &[u8]) -> (Blob, PathBuf) {
+        let key: [u8; 32] = rand::random();
+        let path = std::env::temp_dir().join(format!("blob-{}", Uuid::new_v4()));
+        write(&path, data, data.len() as u64, Some(&DataKey::new(&key)))
+            .await
+            .unwrap();
+        let blob = Blob {
+            file: tokio::fs::File::open(&path).await.unwrap(),
+            size: data.len() as u64,
+            key: Some(DataKey::new(&key)),
+        };
+        (blob, path)
+    }
+
+    #[tokio::test]
+    async fn round_trips_at_chunk_boundaries() {
+        for size in [0, CHUNK_SIZE, CHUNK_SIZE + 1] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let (mut blob, path) = blob(&data).await;
+            assert_eq!(
+                tokio::fs::metadata(&path).await.unwrap().len(),
+                encrypted_size(size)
+            );
+
+            assert_eq!(blob.read_all().await.unwrap(), data, "size {size}");
+            // a range across the chunk boundary, and one past the end
+            let start = CHUNK_SIZE.saturating_sub(10).min(size);
+            assert_eq!(
+                blob.read_at(start, 20).await.unwrap(),
+                data[start as usize..size.min(start + 20) as usize]
+            );
+            assert!(blob.read_at(size, 10).await.unwrap().is_empty());
+            tokio::fs::remove_file(&path).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_exactly_the_given_size() {
+        let path = std::env::temp_dir().join(format!("blob-{}", Uuid::new_v4()));
+        let data = vec![7; CHUNK_SIZE as usize + 1];
+        for size in [CHUNK_SIZE, CHUNK_SIZE + 2] {
+            let err = write(&path, data.as_slice(), size, None).await.unwrap_err();
+            assert!(
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ),
+                "size {size}"
+            );
+        }
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_blob_fails_authentication() {
+        let data = vec![7; (CHUNK_SIZE * 2) as usize];
+        let (mut blob, path) = blob(&data).await;
+
+        // cut off after the first chunk, which then looks like the last one
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(CHUNK_SIZE + TAG_SIZE).unwrap();
+        blob.size = CHUNK_SIZE;
+        let err = blob.read_all().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[test]
+    fn wrapped_keys_are_bound_to_their_file() {
+        let keys = MasterKeys::new(&[master_key("k1")]);
+        let (file_id, other_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let new = keys.generate(file_id).unwrap();
+
+        assert!(keys.unwrap(&new.key_id, &new.wrapped, file_id).is_ok());
+        let err = keys
+            .unwrap(&new.key_id, &new.wrapped, other_id)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rewrapped_keys_decrypt_after_a_rotation() {
+        let (old, new) = (master_key("old"), master_key("new"));
+        let file_id = Uuid::new_v4();
+        let created = MasterKeys::new(std::slice::from_ref(&old))
+            .generate(file_id)
+            .unwrap();
+        let data = b"from before the rotation";
+        let encrypted = created.key.encrypt_chunk(0, true, data);
+
+        let rotated = MasterKeys::new(&[new.clone(), old]);
+        let wrapped = rotated.rewrap("old", &created.wrapped, file_id).unwrap();
+
+        // the old key can go once everything is re-wrapped
+        let current = MasterKeys::new(&[new]);
+        let key = current.unwrap("new", &wrapped, file_id).unwrap();
+        assert_eq!(key.decrypt_chunk(0, true, &encrypted).unwrap(), data);
+        assert!(current.unwrap("old", &created.wrapped, file_id).is_err());
+    }
+}
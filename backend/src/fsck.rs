@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+use crate::{encryption, state::AppState};
+
+/// Where `--repair` moves orphaned blobs, inside the upload directory. Its name is
+/// no user id, so it is never scanned itself.
+const QUARANTINE_DIR: &str = ".quarantine";
+/// Uploads write the blob before their row is committed; younger blobs without a
+/// row are left alone.
+const GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub enum Problem {
+    /// A blob (or thumbnail, HLS directory, ...) on disk no file belongs to.
+    Orphaned { path: PathBuf },
+    /// A file whose blob is gone from disk.
+    Missing { file_id: Uuid, filename: String },
+    /// A blob of another size than its file says, i.e. truncated or overwritten.
+    SizeMismatch {
+        file_id: Uuid,
+        filename: String,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Orphaned { path } => write!(f, "orphaned: {}", path.display()),
+            Problem::Missing { file_id, filename } => {
+                write!(f, "missing blob: {file_id} ({filename})")
+            }
+            Problem::SizeMismatch {
+                file_id,
+                filename,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "size mismatch: {file_id} ({filename}), expected {expected} bytes, found {actual}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub files: u64,
+    pub problems: Vec<Problem>,
+    /// Orphans moved to the quarantine directory.
+    pub quarantined: u64,
+    /// Files marked as `missing` or `corrupt`.
+    pub marked: u64,
+}
+
+struct FileRow {
+    filename: String,
+    size: i64,
+    encrypted: bool,
+    missing: bool,
+}
+
+/// `backend fsck`: compares the upload directory with the `files` table.
+///
+/// With `repair`, orphans are moved to [`QUARANTINE_DIR`], files without a blob are
+/// marked `missing` (and unmarked again once their blob is back) and blobs of the
+/// wrong size mark their file `corrupt`.
+pub async fn check(
+    state: &AppState,
+    repair: bool,
+) -> Result<Report, Box<dyn std::error::Error + Send + Sync>> {
+    let mut files: HashMap<(Uuid, Uuid), FileRow> = sqlx::query!(
+        r#"
+        SELECT id, user_id, filename, size, key_id IS NOT NULL AS "encrypted!", missing FROM files
+        "#
+    )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let file = FileRow {
+            filename: row.filename,
+            size: row.size,
+            encrypted: row.encrypted,
+            missing: row.missing,
+        };
+        ((row.user_id, row.id), file)
+    })
+    .collect();
+
+    let mut report = Report {
+        files: files.len() as u64,
+        ..Report::default()
+    };
+    let upload_dir = Path::new(&state.upload_dir);
+    let (found, orphans) = scan(upload_dir, &files).await?;
+
+    for path in orphans {
+        if repair {
+            quarantine(upload_dir, &path).await?;
+            report.quarantined += 1;
+        }
+        report.problems.push(Problem::Orphaned { path });
+    }
+
+    let mut missing = Vec::new();
+    let mut present = Vec::new();
+    let mut corrupt = Vec::new();
+    for ((user_id, file_id), file) in files.drain() {
+        let Some(&actual) = found.get(&(user_id, file_id)) else {
+            missing.push(file_id);
+            report.problems.push(Problem::Missing {
+                file_id,
+                filename: file.filename,
+            });
+            continue;
+        };
+        if file.missing {
+            present.push(file_id);
+        }
+
+        let expected = if file.encrypted {
+            encryption::encrypted_size(file.size as u64)
+        } else {
+            file.size as u64
+        };
+        if actual != expected {
+            corrupt.push(file_id);
+            report.problems.push(Problem::SizeMismatch {
+                file_id,
+                filename: file.filename,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    if repair {
+        report.marked += sqlx::query!(
+            r#"
+            UPDATE files SET missing = true WHERE id = ANY($1) AND NOT missing
+            "#,
+            &missing
+        )
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+        report.marked += sqlx::query!(
+            r#"
+            UPDATE files SET corrupt = true WHERE id = ANY($1) AND NOT corrupt
+            "#,
+            &corrupt
+        )
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+        sqlx::query!(
+            r#"
+            UPDATE files SET missing = false WHERE id = ANY($1)
+            "#,
+            &present
+        )
+        .execute(&state.db)
+        .await?;
+    }
+
+    Ok(report)
+}
+
+/// Walks `<upload_dir>/<drive_id>/`, returning the sizes of the blobs that belong to
+/// a file and the paths of those that don't.
+async fn scan(
+    upload_dir: &Path,
+    files: &HashMap<(Uuid, Uuid), FileRow>,
+) -> io::Result<(HashMap<(Uuid, Uuid), u64>, Vec<PathBuf>)> {
+    let mut found = HashMap::new();
+    let mut orphans = Vec::new();
+
+    let mut users = match tokio::fs::read_dir(upload_dir).await {
+        Ok(users) => users,
+        // nothing was uploaded yet
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((found, orphans)),
+        Err(err) => return Err(err),
+    };
+    while let Some(user_dir) = users.next_entry().await? {
+        let Some(user_id) = parse_id(&user_dir.file_name().to_string_lossy()) else {
+            continue;
+        };
+        let mut entries = tokio::fs::read_dir(user_dir.path()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // `<file_id>` is the blob, `<file_id>.<anything>` is derived from it
+            let (stem, derived) = match name.split_once('.') {
+                Some((stem, _)) => (stem, true),
+                None => (name.as_str(), false),
+            };
+            let metadata = entry.metadata().await?;
+            let known = parse_id(stem).filter(|id| files.contains_key(&(user_id, *id)));
+
+            match known {
+                Some(file_id) if !derived => {
+                    found.insert((user_id, file_id), metadata.len());
+                }
+                Some(_) => {}
+                None => {
+                    let age = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+                    if age.is_some_and(|age| age >= GRACE_PERIOD) {
+                        orphans.push(entry.path());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((found, orphans))
+}
+
+fn parse_id(name: &str) -> Option<Uuid> {
+    Uuid::parse_str(name).ok()
+}
+
+/// Moves `path` to the same place below the quarantine directory.
+async fn quarantine(upload_dir: &Path, path: &Path) -> io::Result<()> {
+    let relative = path.strip_prefix(upload_dir).unwrap_or(path);
+    let target = upload_dir.join(QUARANTINE_DIR).join(relative);
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::rename(path, target).await
+}
@@ -0,0 +1,246 @@
+use std::{
+    io::{Cursor, Read},
+    time::Duration,
+};
+
+use uuid::Uuid;
+
+use crate::{encryption::Blob, state::App};
+
+/// Files picked up per round.
+const BATCH_SIZE: i64 = 20;
+/// Larger files are not read at all.
+const MAX_FILE_SIZE: i64 = 50 * 1024 * 1024;
+/// Extracted text is cut off here; Postgres refuses tsvectors above 1 MB.
+const MAX_TEXT_LEN: usize = 512 * 1024;
+/// How often to look for work when no upload wakes the indexer up.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Extracts the text of new files into `file_contents` for full-text search.
+///
+/// Files are pending while `indexed_at` is NULL, so nothing is lost across restarts.
+/// Uploads wake the indexer up through `AppState::indexer`.
+pub async fn run(state: App) {
+    loop {
+        match index_pending(&state).await {
+            // there may be more waiting
+            Ok(n) if n as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(err) => tracing::error!("Indexer failed: {err}"),
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, state.indexer.notified()).await;
+    }
+}
+
+async fn index_pending(state: &App) -> Result<usize, sqlx::Error> {
+    let pending = sqlx::query!(
+        r#"
+        SELECT id, user_id, filename, size FROM files
+        WHERE indexed_at IS NULL
+        ORDER BY last_modified
+        LIMIT $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    for file in &pending {
+        let format = Format::of(&file.filename);
+        let text = if let Some(format) = format.filter(|_| file.size <= MAX_FILE_SIZE) {
+            let data = async {
+                Blob::open(state, file.user_id, file.id)
+                    .await?
+                    .read_all()
+                    .await
+            };
+            let extracted = match data.await {
+                Ok(data) => tokio::task::spawn_blocking(move || extract_text(&data, format)).await,
+                Err(err) => Ok(Err(err.into())),
+            };
+            match extracted {
+                Ok(Ok(text)) => Some(text),
+                Ok(Err(err)) => {
+                    tracing::warn!("Could not extract text from {}: {err}", file.id);
+                    None
+                }
+                // the PDF parser panics on some malformed documents
+                Err(_) => {
+                    tracing::warn!("Text extraction panicked for {}", file.id);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        store(state, file.id, text).await?;
+    }
+
+    Ok(pending.len())
+}
+
+async fn store(state: &App, file_id: Uuid, text: Option<String>) -> Result<(), sqlx::Error> {
+    let mut transaction = state.db.begin().await?;
+
+    match text {
+        Some(text) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO file_contents (file_id, content) VALUES ($1, $2)
+                ON CONFLICT (file_id) DO UPDATE SET content = EXCLUDED.content
+                "#,
+                file_id,
+                text
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        None => {
+            sqlx::query!(
+                r#"
+                DELETE FROM file_contents WHERE file_id = $1
+                "#,
+                file_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE files SET indexed_at = now() WHERE id = $1
+        "#,
+        file_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await
+}
+
+/// Document formats text can be extracted from.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Plain,
+    Pdf,
+    Docx,
+    Pptx,
+    Xlsx,
+    OpenDocument,
+}
+
+impl Format {
+    /// The format of a file by its extension, or `None` for formats without text.
+    fn of(filename: &str) -> Option<Self> {
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "txt" | "md" | "markdown" | "csv" | "tsv" | "log" | "json" | "xml" | "html" | "htm"
+            | "yaml" | "yml" | "toml" | "ini" | "rst" | "tex" => Some(Format::Plain),
+            "pdf" => Some(Format::Pdf),
+            "docx" => Some(Format::Docx),
+            "pptx" => Some(Format::Pptx),
+            "xlsx" => Some(Format::Xlsx),
+            "odt" | "ods" | "odp" => Some(Format::OpenDocument),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the searchable text of a document.
+fn extract_text(
+    data: &[u8],
+    format: Format,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let text = match format {
+        Format::Plain => String::from_utf8_lossy(data).into_owned(),
+        Format::Pdf => pdf_extract::extract_text_from_mem(data)?,
+        Format::Docx => office_text(data, |name| name == "word/document.xml")?,
+        Format::Pptx => office_text(data, |name| {
+            name.starts_with("ppt/slides/slide") && name.ends_with(".xml")
+        })?,
+        Format::Xlsx => office_text(data, |name| name == "xl/sharedStrings.xml")?,
+        Format::OpenDocument => office_text(data, |name| name == "content.xml")?,
+    };
+
+    Ok(clean(text))
+}
+
+/// Concatenates the text of the XML parts of an Office (OOXML or OpenDocument) zip archive.
+fn office_text(
+    data: &[u8],
+    is_text_part: impl Fn(&str) -> bool,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let mut text = String::new();
+
+    for i in 0..archive.len() {
+        let mut part = archive.by_index(i)?;
+        if !part.name().is_ok_and(|name| is_text_part(&name)) {
+            continue;
+        }
+        let mut xml = String::new();
+        part.read_to_string(&mut xml)?;
+        xml_text(&xml, &mut text);
+        if text.len() > MAX_TEXT_LEN {
+            break;
+        }
+    }
+
+    Ok(text)
+}
+
+/// Appends the character data of `xml` to `out`.
+///
+/// Words can be split across several runs (`<w:t>Hel</w:t><w:t>lo</w:t>`), so
+/// only the end of a paragraph, cell or line break separates text.
+fn xml_text(xml: &str, out: &mut String) {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&unescape(&rest[..start]));
+        let Some(end) = rest[start..].find('>') else {
+            return;
+        };
+        let tag = &rest[start + 1..start + end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if matches!(
+            local,
+            "p" | "br" | "tab" | "si" | "c" | "h" | "s" | "line-break"
+        ) {
+            out.push(' ');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(&unescape(rest));
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Removes NUL bytes (not allowed in Postgres text) and truncates to [`MAX_TEXT_LEN`].
+fn clean(mut text: String) -> String {
+    text.retain(|c| c != '\0');
+    if text.len() > MAX_TEXT_LEN {
+        let mut end = MAX_TEXT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
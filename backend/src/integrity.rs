@@ -0,0 +1,161 @@
+use std::io;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures_util::TryStreamExt;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{encryption::Blob, state::AppState};
+
+/// Files hashed per query while verifying.
+const BATCH_SIZE: i64 = 100;
+
+pub type Checksum = [u8; 32];
+
+/// SHA-256 of a file's plain text, fed as the upload comes in.
+#[derive(Default)]
+pub struct Hasher(Sha256);
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> Checksum {
+        self.0.finalize().into()
+    }
+}
+
+pub fn sha256(data: &[u8]) -> Checksum {
+    Sha256::digest(data).into()
+}
+
+pub fn to_hex(checksum: &[u8]) -> String {
+    checksum.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parses a client-supplied checksum, hex or (standard) base64 encoded.
+pub fn parse(value: &str) -> Option<Checksum> {
+    let value = value.trim();
+    let bytes = if value.len() == 64 {
+        (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?
+    } else {
+        STANDARD.decode(value).ok()?
+    };
+    bytes.try_into().ok()
+}
+
+/// The `Digest` header value (RFC 3230) for a file.
+pub fn digest_header(checksum: &[u8]) -> String {
+    format!("sha-256={}", STANDARD.encode(checksum))
+}
+
+/// A strong `ETag`; files are never rewritten, so the checksum identifies the contents.
+pub fn etag(checksum: &[u8]) -> String {
+    format!("\"{}\"", to_hex(checksum))
+}
+
+/// Hashes the plain text of a stored file.
+async fn hash_blob(state: &AppState, drive_id: Uuid, file_id: Uuid) -> io::Result<Checksum> {
+    let blob = Blob::open(state, drive_id, file_id).await?;
+    let size = blob.size();
+    let mut hasher = Hasher::default();
+    let mut stream = std::pin::pin!(blob.into_stream(0..size));
+    while let Some(data) = stream.try_next().await? {
+        hasher.update(&data);
+    }
+    Ok(hasher.finish())
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub verified: u64,
+    /// Files uploaded before checksums existed, hashed for the first time.
+    pub hashed: u64,
+    pub corrupt: u64,
+    /// Blobs missing from disk; `backend fsck` has more to say about these.
+    pub missing: u64,
+    /// Files that could not be read for other reasons, e.g. a missing master key.
+    pub failed: u64,
+}
+
+/// `backend verify`: re-hashes every blob to detect bit rot. Files whose contents
+/// no longer match their checksum (or fail to decrypt) are marked `corrupt`.
+pub async fn verify(state: &AppState) -> Result<Report, sqlx::Error> {
+    let mut report = Report::default();
+    let mut after = Uuid::nil();
+    loop {
+        let files = sqlx::query!(
+            r#"
+            SELECT id, user_id, filename, sha256 FROM files
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+            after,
+            BATCH_SIZE
+        )
+        .fetch_all(&state.db)
+        .await?;
+        let Some(last) = files.last() else {
+            return Ok(report);
+        };
+        after = last.id;
+
+        for file in files {
+            let actual = match hash_blob(state, file.user_id, file.id).await {
+                Ok(actual) => Some(actual),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    tracing::warn!("Blob of file {} ({}) is missing", file.id, file.filename);
+                    report.missing += 1;
+                    continue;
+                }
+                // a tampered or truncated encrypted blob fails to decrypt
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    None
+                }
+                Err(err) => {
+                    tracing::error!("Could not read file {} ({}): {err}", file.id, file.filename);
+                    report.failed += 1;
+                    continue;
+                }
+            };
+
+            let corrupt = match (&file.sha256, actual) {
+                (Some(expected), Some(actual)) => expected.as_slice() != actual,
+                (None, Some(_)) => {
+                    report.hashed += 1;
+                    false
+                }
+                (_, None) => true,
+            };
+            if corrupt {
+                tracing::error!("File {} ({}) is corrupt", file.id, file.filename);
+                report.corrupt += 1;
+            } else if file.sha256.is_some() {
+                report.verified += 1;
+            }
+
+            sqlx::query!(
+                r#"
+                UPDATE files
+                SET sha256 = COALESCE(sha256, $2), verified_at = now(), corrupt = $3
+                WHERE id = $1
+                "#,
+                file.id,
+                actual.as_ref().map(|actual| actual.as_slice()),
+                corrupt
+            )
+            .execute(&state.db)
+            .await?;
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{fsck, integrity, routes::files, state::App};
+
+/// How often to look for due jobs when nothing woke the runner up.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a job may run before another runner considers it abandoned.
+const LEASE: Duration = Duration::from_secs(10 * 60);
+/// Delay before the first retry, doubled for every further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Work for the background runner. Jobs are stored as JSON until they are done, so
+/// variants must not be renamed; new fields need `#[serde(default)]`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Removes the contents of files whose rows are already deleted.
+    RemoveBlobs { user_id: Uuid, file_ids: Vec<Uuid> },
+    /// `backend verify`, started by an admin; the report goes to the log.
+    Verify,
+    /// `backend fsck`, started by an admin; the report goes to the log.
+    Fsck { repair: bool },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::RemoveBlobs { .. } => "remove_blobs",
+            Job::Verify => "verify",
+            Job::Fsck { .. } => "fsck",
+        }
+    }
+
+    async fn perform(self, state: &App) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Job::RemoveBlobs { user_id, file_ids } => {
+                for file_id in file_ids {
+                    files::remove_contents(state, user_id, file_id).await?;
+                }
+            }
+            Job::Verify => {
+                let report = integrity::verify(state).await?;
+                tracing::info!("Verified blobs: {report:?}");
+            }
+            Job::Fsck { repair } => {
+                let report = fsck::check(state, repair).await?;
+                for problem in &report.problems {
+                    tracing::warn!("fsck: {problem}");
+                }
+                tracing::info!(
+                    "fsck checked {} files, {} problems, {} quarantined, {} marked",
+                    report.files,
+                    report.problems.len(),
+                    report.quarantined,
+                    report.marked
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds a job to the queue. Pass the transaction that makes the job necessary, so
+/// the job only exists if that commits.
+pub async fn enqueue(db: impl PgExecutor<'_>, job: &Job) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO jobs (kind, payload) VALUES ($1, $2)
+        "#,
+        job.kind(),
+        serde_json::to_value(job).expect("jobs serialize to JSON")
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Runs due jobs one at a time until `shutdown` is cancelled; a job that is running
+/// by then is finished first.
+///
+/// Failed jobs are retried with exponential backoff until they run out of attempts.
+/// `AppState::jobs` wakes the runner up early.
+pub async fn run(state: App, shutdown: CancellationToken) {
+    while !shutdown.is_cancelled() {
+        match run_next(&state).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => tracing::error!("Job runner failed: {err}"),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = state.jobs.notified() => {}
+        }
+    }
+}
+
+/// Claims and runs the next due job; `false` if there was none.
+async fn run_next(state: &App) -> Result<bool, sqlx::Error> {
+    let Some(job) = sqlx::query!(
+        r#"
+        UPDATE jobs SET attempts = attempts + 1, locked_until = now() + make_interval(secs => $1)
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE failed_at IS NULL AND run_at <= now()
+              AND (locked_until IS NULL OR locked_until < now())
+            ORDER BY run_at, id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload, attempts, max_attempts
+        "#,
+        LEASE.as_secs_f64()
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(false);
+    };
+
+    let result = match serde_json::from_value::<Job>(job.payload) {
+        Ok(payload) => payload.perform(state).await,
+        Err(err) => {
+            // written by a newer version, or garbage; retrying won't help
+            tracing::error!("Job {} ({}) is invalid: {err}", job.id, job.kind);
+            sqlx::query!(
+                r#"
+                UPDATE jobs SET failed_at = now(), locked_until = NULL, last_error = $2 WHERE id = $1
+                "#,
+                job.id,
+                err.to_string()
+            )
+            .execute(&state.db)
+            .await?;
+            return Ok(true);
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            sqlx::query!("DELETE FROM jobs WHERE id = $1", job.id)
+                .execute(&state.db)
+                .await?;
+        }
+        Err(err) if job.attempts >= job.max_attempts => {
+            tracing::error!(
+                "Job {} ({}) failed for good after {} attempts: {err}",
+                job.id,
+                job.kind,
+                job.attempts
+            );
+            sqlx::query!(
+                r#"
+                UPDATE jobs SET failed_at = now(), locked_until = NULL, last_error = $2 WHERE id = $1
+                "#,
+                job.id,
+                err.to_string()
+            )
+            .execute(&state.db)
+            .await?;
+        }
+        Err(err) => {
+            let delay = RETRY_DELAY
+                .saturating_mul(1 << (job.attempts - 1).min(16))
+                .min(MAX_RETRY_DELAY);
+            tracing::warn!(
+                "Job {} ({}) failed, retrying in {}s: {err}",
+                job.id,
+                job.kind,
+                delay.as_secs()
+            );
+            sqlx::query!(
+                r#"
+                UPDATE jobs
+                SET run_at = now() + make_interval(secs => $2), locked_until = NULL, last_error = $3
+                WHERE id = $1
+                "#,
+                job.id,
+                delay.as_secs_f64(),
+                err.to_string()
+            )
+            .execute(&state.db)
+            .await?;
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,168 @@
+//! Checking passwords against an LDAP directory: look the user up, with the
+//! service account if there is one, then bind as them with their password.
+//!
+//! The directory only decides whether a password is right; users, sessions and
+//! second factors live here as for everyone else. Users the directory doesn't
+//! know fall back to local accounts, see [`routes::login`](crate::routes::login).
+
+use std::{collections::HashMap, time::Duration};
+
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry, ldap_escape};
+
+use crate::{
+    auth::{Identity, Subject},
+    config::LdapConfig,
+};
+
+/// How long connecting and each operation may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Result code of a bind with the wrong password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+pub struct Directory {
+    config: LdapConfig,
+}
+
+/// What the directory says about a login.
+#[derive(Debug)]
+pub enum Login {
+    Success(Identity),
+    WrongPassword,
+    /// Not in the directory, or without an email address.
+    UnknownUser,
+}
+
+impl Directory {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &LdapConfig {
+        &self.config
+    }
+
+    /// Checks `password` for the user with `login`, their uid or email address by
+    /// default.
+    pub async fn authenticate(&self, login: &str, password: &str) -> Result<Login, LdapError> {
+        // a bind without a password is anonymous and succeeds
+        if password.is_empty() {
+            return Ok(Login::WrongPassword);
+        }
+
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(TIMEOUT)
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+        ldap.with_timeout(TIMEOUT);
+
+        let login = self.find_and_bind(&mut ldap, login, password).await;
+        // the connection is of no use after this either way
+        let _ = ldap.unbind().await;
+        login
+    }
+
+    async fn find_and_bind(
+        &self,
+        ldap: &mut Ldap,
+        login: &str,
+        password: &str,
+    ) -> Result<Login, LdapError> {
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(bind_dn, &self.config.bind_password)
+                .await?
+                .success()?;
+        }
+
+        let filter = self
+            .config
+            .user_filter
+            .replace("{login}", &ldap_escape(login));
+        let (entries, _) = ldap
+            .search(
+                &self.config.base_dn,
+                Scope::Subtree,
+                &filter,
+                vec![
+                    self.config.email_attribute.as_str(),
+                    self.config.name_attribute.as_str(),
+                    "memberOf",
+                ],
+            )
+            .await?
+            .success()?;
+        let mut entries = entries.into_iter().map(SearchEntry::construct);
+        let (Some(entry), None) = (entries.next(), entries.next()) else {
+            // more than one match means the filter is too loose, trust neither
+            return Ok(Login::UnknownUser);
+        };
+        let Some(email) = attribute(&entry.attrs, &self.config.email_attribute) else {
+            tracing::warn!(
+                "{} has no {} attribute",
+                entry.dn,
+                self.config.email_attribute
+            );
+            return Ok(Login::UnknownUser);
+        };
+
+        let bind = ldap.simple_bind(&entry.dn, password).await?;
+        if bind.rc == INVALID_CREDENTIALS {
+            return Ok(Login::WrongPassword);
+        }
+        bind.success()?;
+
+        let groups = match &self.config.group_base_dn {
+            Some(group_base_dn) => {
+                let filter = self
+                    .config
+                    .group_filter
+                    .replace("{dn}", &ldap_escape(&entry.dn))
+                    .replace("{login}", &ldap_escape(login));
+                let (groups, _) = ldap
+                    .search(group_base_dn, Scope::Subtree, &filter, vec!["cn"])
+                    .await?
+                    .success()?;
+                groups
+                    .into_iter()
+                    .map(SearchEntry::construct)
+                    .filter_map(|group| attribute(&group.attrs, "cn"))
+                    .map(|name| name.to_lowercase())
+                    .collect()
+            }
+            None => values(&entry.attrs, "memberOf")
+                .iter()
+                .map(|dn| common_name(dn).to_lowercase())
+                .collect(),
+        };
+
+        Ok(Login::Success(Identity {
+            email: email.to_lowercase(),
+            name: attribute(&entry.attrs, &self.config.name_attribute),
+            groups,
+            subject: Subject::Ldap { dn: entry.dn },
+        }))
+    }
+}
+
+/// Values of an attribute; servers don't always keep the case it was asked for in.
+fn values<'a>(attrs: &'a HashMap<String, Vec<String>>, name: &str) -> &'a [String] {
+    attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.as_slice())
+        .unwrap_or_default()
+}
+
+fn attribute(attrs: &HashMap<String, Vec<String>>, name: &str) -> Option<String> {
+    values(attrs, name).first().cloned()
+}
+
+/// `admins` for `cn=admins,ou=groups,dc=example,dc=com`, or the whole DN if it
+/// doesn't start with a CN.
+fn common_name(dn: &str) -> &str {
+    dn.split(',')
+        .next()
+        .and_then(|rdn| rdn.split_once('='))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case("cn"))
+        .map_or(dn, |(_, value)| value.trim())
+}
@@ -0,0 +1,230 @@
+//! Counters for `GET /metrics`, in the Prometheus text format.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+
+use crate::state::App;
+
+/// Upper bounds of the request duration histogram, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+/// Routes whose request bodies are uploads.
+pub const UPLOAD_ROUTES: [&str; 1] = ["/api/v1/upload"];
+/// Routes whose response bodies are downloads.
+pub const DOWNLOAD_ROUTES: [&str; 3] = [
+    "/api/v1/download/{id}",
+    "/api/v1/files/{id}/hls/{*path}",
+    "/api/v1/vaults/{id}/files/{file_id}",
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Per method and route (the pattern, e.g. `/api/v1/files/{id}`).
+    requests: Mutex<HashMap<(String, String), RouteStats>>,
+    upload_bytes: AtomicU64,
+    download_bytes: AtomicU64,
+    active_uploads: AtomicI64,
+    active_downloads: AtomicI64,
+}
+
+#[derive(Default)]
+struct RouteStats {
+    statuses: HashMap<u16, u64>,
+    /// Non-cumulative counts per bucket; the last one is `+Inf`.
+    buckets: [u64; BUCKETS.len() + 1],
+    seconds: f64,
+}
+
+/// Counts a transfer as active until it is dropped.
+struct Active {
+    state: App,
+    gauge: fn(&Metrics) -> &AtomicI64,
+}
+
+impl Active {
+    fn new(state: &App, gauge: fn(&Metrics) -> &AtomicI64) -> Self {
+        gauge(&state.metrics).fetch_add(1, Ordering::Relaxed);
+        Self {
+            state: state.clone(),
+            gauge,
+        }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        (self.gauge)(&self.state.metrics).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Counts the contents of a stored file, however it was uploaded.
+    pub fn uploaded(&self, bytes: usize) {
+        self.upload_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn record(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(BUCKETS.len());
+        stats.buckets[bucket] += 1;
+        stats.seconds += seconds;
+    }
+
+    /// Everything counted so far; `extra` are gauges read at scrape time.
+    pub fn render(&self, extra: &[(&str, &str, f64)]) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total HTTP requests by route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        let requests = self.requests.lock().unwrap();
+        let mut routes: Vec<_> = requests.iter().collect();
+        routes.sort_by(|a, b| a.0.cmp(b.0));
+        for ((method, route), stats) in &routes {
+            let mut statuses: Vec<_> = stats.statuses.iter().collect();
+            statuses.sort();
+            for (status, count) in statuses {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{method}\",route=\"{}\",status=\"{status}\"}} {count}",
+                    escape(route)
+                );
+            }
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds Time until the response headers were sent.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), stats) in &routes {
+            let labels = format!("method=\"{method}\",route=\"{}\"", escape(route));
+            let mut cumulative = 0;
+            for (i, count) in stats.buckets.iter().enumerate() {
+                cumulative += count;
+                let bound = BUCKETS.get(i).map_or("+Inf".to_string(), |b| b.to_string());
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                stats.seconds
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {cumulative}"
+            );
+        }
+        drop(requests);
+
+        let counters = [
+            (
+                "upload_bytes_total",
+                "Bytes of file contents stored.",
+                self.upload_bytes.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "download_bytes_total",
+                "Bytes of file contents sent.",
+                self.download_bytes.load(Ordering::Relaxed) as f64,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            );
+        }
+
+        let gauges = [
+            (
+                "active_uploads",
+                "Uploads being received.",
+                self.active_uploads.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "active_downloads",
+                "Downloads being sent.",
+                self.active_downloads.load(Ordering::Relaxed) as f64,
+            ),
+        ];
+        for (name, help, value) in gauges.iter().chain(extra) {
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+            );
+        }
+
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Middleware counting requests per route, and the bytes and number of active
+/// uploads and downloads. Downloads count until their body is sent (or dropped).
+pub async fn track(State(state): State<App>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let uploading = UPLOAD_ROUTES
+        .contains(&route.as_str())
+        .then(|| Active::new(&state, |metrics| &metrics.active_uploads));
+    let response = next.run(request).await;
+    drop(uploading);
+    state.metrics.record(
+        &method,
+        &route,
+        response.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+
+    if !DOWNLOAD_ROUTES.contains(&route.as_str()) || !response.status().is_success() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let active = Active::new(&state, |metrics| &metrics.active_downloads);
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            active
+                .state
+                .metrics
+                .download_bytes
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
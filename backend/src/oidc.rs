@@ -0,0 +1,213 @@
+//! Single sign-on with an OpenID Connect provider: the authorization code flow
+//! with PKCE, verifying the ID token the provider hands back.
+//!
+//! The provider's metadata and signing keys are discovered on first use and
+//! fetched again every hour, so key rotations are picked up without a restart.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndSessionUrl, EndpointMaybeSet,
+    EndpointNotSet, EndpointSet, IssuerUrl, LogoutRequest, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, PostLogoutRedirectUrl, RedirectUrl, Scope, TokenResponse,
+    core::{CoreAuthenticationFlow, CoreClient},
+    reqwest,
+};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::{
+    auth::{Identity, Subject},
+    config::OidcConfig,
+};
+
+/// How long discovered metadata and keys are used before fetching them again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+type Client = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+/// What the provider knows about its endpoints and keys.
+struct Provider {
+    client: Client,
+    end_session: Option<EndSessionUrl>,
+    fetched: Instant,
+}
+
+pub struct Oidc {
+    config: OidcConfig,
+    http: reqwest::Client,
+    provider: RwLock<Option<Arc<Provider>>>,
+}
+
+/// A login that was sent to the provider, to be completed in the callback.
+pub struct Pending {
+    /// Where to send the browser.
+    pub url: String,
+    /// The OAuth `state`, identifying the login in the callback.
+    pub state: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        let http = reqwest::Client::builder()
+            // following redirects opens up server-side request forgery
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("HTTP client builds");
+        Self {
+            config,
+            http,
+            provider: RwLock::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    async fn provider(&self) -> Result<Arc<Provider>, Error> {
+        if let Some(provider) = &*self.provider.read().await
+            && provider.fetched.elapsed() < REFRESH_INTERVAL
+        {
+            return Ok(provider.clone());
+        }
+
+        let metadata = openidconnect::ProviderMetadataWithLogout::discover_async(
+            IssuerUrl::new(self.config.issuer.clone())?,
+            &self.http,
+        )
+        .await?;
+        let end_session = metadata.additional_metadata().end_session_endpoint.clone();
+        let client = CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(self.config.client_id.clone()),
+            self.config.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(RedirectUrl::new(self.config.redirect_url.clone())?);
+
+        let provider = Arc::new(Provider {
+            client,
+            end_session,
+            fetched: Instant::now(),
+        });
+        *self.provider.write().await = Some(provider.clone());
+        Ok(provider)
+    }
+
+    /// Starts a login at the provider.
+    pub async fn authorize(&self) -> Result<Pending, Error> {
+        let provider = self.provider().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let mut request = provider
+            .client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .set_pkce_challenge(pkce_challenge);
+        for scope in &self.config.scopes {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, state, nonce) = request.url();
+
+        Ok(Pending {
+            url: url.to_string(),
+            state: state.into_secret(),
+            pkce_verifier: pkce_verifier.into_secret(),
+            nonce: nonce.secret().clone(),
+        })
+    }
+
+    /// Completes a login: exchanges the code the provider sent back for an ID
+    /// token and checks it.
+    pub async fn exchange(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<Identity, Error> {
+        let provider = self.provider().await?;
+        let response = provider
+            .client
+            .exchange_code(AuthorizationCode::new(code))?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(&self.http)
+            .await?;
+        let id_token = response.id_token().ok_or("the provider sent no ID token")?;
+        let claims = id_token.claims(&provider.client.id_token_verifier(), &Nonce::new(nonce))?;
+
+        // providers that don't say may let anyone claim any address
+        if claims.email_verified() != Some(true) {
+            return Err("the email address is not verified".into());
+        }
+        let email = claims
+            .email()
+            .ok_or("the ID token has no email, is the `email` scope requested?")?
+            .to_string()
+            .to_lowercase();
+        let name = claims
+            .name()
+            .and_then(|name| name.get(None))
+            .map(|name| name.to_string());
+
+        // the signature was checked above, the claims can be read as they are
+        let payload = id_token
+            .to_string()
+            .split('.')
+            .nth(1)
+            .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+            .and_then(|payload| serde_json::from_slice::<Value>(&payload).ok())
+            .unwrap_or_default();
+        let groups = match payload.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_lowercase)
+                .collect(),
+            Some(Value::String(group)) => vec![group.to_lowercase()],
+            _ => Vec::new(),
+        };
+
+        Ok(Identity {
+            email,
+            name,
+            groups,
+            subject: Subject::Oidc {
+                issuer: claims.issuer().to_string(),
+                subject: claims.subject().to_string(),
+            },
+        })
+    }
+
+    /// Where to log out at the provider too, if it supports that.
+    pub async fn logout_url(&self) -> Result<Option<String>, Error> {
+        let provider = self.provider().await?;
+        let Some(end_session) = provider.end_session.clone() else {
+            return Ok(None);
+        };
+        let mut request = LogoutRequest::from(end_session)
+            .set_client_id(ClientId::new(self.config.client_id.clone()));
+        if let Some(url) = &self.config.post_logout_redirect_url {
+            request =
+                request.set_post_logout_redirect_uri(PostLogoutRedirectUrl::new(url.clone())?);
+        }
+        Ok(Some(request.http_get_url().to_string()))
+    }
+}
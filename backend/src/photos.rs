@@ -0,0 +1,110 @@
+use std::io::Cursor;
+
+use chrono::NaiveDateTime;
+use exif::{In, Tag, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What the EXIF data of a photo says; every field is optional.
+#[derive(Debug, Default)]
+pub struct Exif {
+    pub taken_at: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub orientation: Option<i16>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Reads the EXIF data of a JPEG, TIFF, PNG, WebP or HEIF image.
+pub fn read_exif(data: &[u8]) -> Exif {
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) else {
+        return Exif::default();
+    };
+
+    let ascii = |tag| match exif.get_field(tag, In::PRIMARY).map(|field| &field.value) {
+        Some(Value::Ascii(values)) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    };
+    let coordinate = |tag, ref_tag, negative: &str| {
+        let Some(Value::Rational(dms)) = exif.get_field(tag, In::PRIMARY).map(|f| &f.value) else {
+            return None;
+        };
+        let [degrees, minutes, seconds] = dms.get(..3)? else {
+            return None;
+        };
+        let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+        Some(if ascii(ref_tag).as_deref() == Some(negative) {
+            -value
+        } else {
+            value
+        })
+    };
+
+    let taken_at = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => {
+                let dt = exif::DateTime::from_ascii(values.first()?).ok()?;
+                chrono::NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?
+                    .and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into())
+            }
+            _ => None,
+        });
+
+    Exif {
+        taken_at,
+        camera_make: ascii(Tag::Make),
+        camera_model: ascii(Tag::Model),
+        orientation: exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .map(|orientation| orientation as i16),
+        latitude: coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+        longitude: coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+    }
+}
+
+/// Records the metadata of a photo; `width` and `height` are the upright dimensions.
+pub async fn store(
+    db: &PgPool,
+    drive_id: Uuid,
+    file_id: Uuid,
+    exif: Exif,
+    width: u32,
+    height: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO photo_metadata
+            (file_id, user_id, taken_at, camera_make, camera_model, width, height, orientation, latitude, longitude)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        ON CONFLICT (file_id) DO UPDATE SET
+            taken_at = EXCLUDED.taken_at,
+            camera_make = EXCLUDED.camera_make,
+            camera_model = EXCLUDED.camera_model,
+            width = EXCLUDED.width,
+            height = EXCLUDED.height,
+            orientation = EXCLUDED.orientation,
+            latitude = EXCLUDED.latitude,
+            longitude = EXCLUDED.longitude
+        "#,
+        file_id,
+        drive_id,
+        exif.taken_at,
+        exif.camera_make,
+        exif.camera_model,
+        width as i32,
+        height as i32,
+        exif.orientation,
+        exif.latitude,
+        exif.longitude
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
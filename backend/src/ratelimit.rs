@@ -0,0 +1,327 @@
+//! Token bucket rate limits per user and per client IP, and bandwidth throttling
+//! of uploads and downloads.
+//!
+//! Every class of routes has its own buckets, so e.g. a burst of thumbnail
+//! requests doesn't keep anyone from uploading. Buckets live in memory: limits
+//! apply per server process and start over on restart.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use uuid::Uuid;
+
+use crate::{auth::User, client, config::RateLimitConfig, metrics, routes::Error, state::App};
+
+/// How often buckets that filled up again are forgotten.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Routes that check passwords or other credentials.
+const AUTH_ROUTES: [&str; 8] = [
+    "/api/v1/account/password",
+    "/api/v1/account/totp",
+    "/api/v1/account/totp/confirm",
+    "/api/v1/account/totp/recovery-codes",
+    "/api/v1/auth/login",
+    "/api/v1/auth/login/totp",
+    "/api/v1/auth/oidc/login",
+    "/api/v1/auth/oidc/callback",
+];
+
+/// A group of routes sharing a request budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Auth,
+    Upload,
+    Download,
+    Api,
+}
+
+impl Class {
+    /// The class of a route pattern (see [`MatchedPath`]); routes outside of the
+    /// API, like `/healthz`, are not limited.
+    fn of(route: &str) -> Option<Self> {
+        if AUTH_ROUTES.contains(&route) {
+            Some(Class::Auth)
+        } else if metrics::UPLOAD_ROUTES.contains(&route) {
+            Some(Class::Upload)
+        } else if metrics::DOWNLOAD_ROUTES.contains(&route) {
+            Some(Class::Download)
+        } else if route.starts_with("/api/") {
+            Some(Class::Api)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whose bucket it is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    User(Uuid),
+    Ip(IpAddr),
+    /// A name someone tries to log in with, see [`Key::login`].
+    Login(String),
+}
+
+impl Key {
+    /// The bucket of a login name, whether or not an account has it, shared by
+    /// every way of logging in with a password so guesses can't be spread over
+    /// many IPs or protocols.
+    pub fn login(name: &str) -> Self {
+        Key::Login(name.trim().to_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    /// Tokens are requests.
+    Requests(Class),
+    /// Tokens are bytes.
+    UploadBytes,
+    DownloadBytes,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Refill {
+    capacity: f64,
+    per_second: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while paying off bytes sent ahead of time.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, refill: Refill, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill.per_second).min(refill.capacity);
+        self.updated = now;
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<(Kind, Key), Bucket>,
+    swept: Instant,
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, kind: Kind) -> Option<Refill> {
+        let per_second = |bytes: u64| Refill {
+            // a second's worth may be sent at once
+            capacity: bytes as f64,
+            per_second: bytes as f64,
+        };
+        match kind {
+            Kind::Requests(class) => {
+                let rate = match class {
+                    Class::Auth => self.config.auth,
+                    Class::Upload => self.config.upload,
+                    Class::Download => self.config.download,
+                    Class::Api => self.config.api,
+                }?;
+                Some(Refill {
+                    capacity: rate.requests as f64,
+                    per_second: rate.requests as f64 / rate.period.as_secs_f64(),
+                })
+            }
+            Kind::UploadBytes => self.config.upload_bandwidth.map(per_second),
+            Kind::DownloadBytes => self.config.download_bandwidth.map(per_second),
+        }
+    }
+
+    /// The buckets, after forgetting those that are full anyway once in a while.
+    fn lock(&self, now: Instant) -> MutexGuard<'_, Buckets> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            buckets.swept = now;
+            buckets.buckets.retain(|(kind, _), bucket| {
+                let Some(refill) = self.refill(*kind) else {
+                    return false;
+                };
+                bucket.refill(refill, now);
+                bucket.tokens < refill.capacity
+            });
+        }
+        buckets
+    }
+
+    /// Takes a request of `class` out of the buckets of all `keys`. If one of them is
+    /// empty, nothing is taken and the time until it has a token again is returned.
+    pub fn check(&self, class: Class, keys: &[Key]) -> Result<(), Duration> {
+        let kind = Kind::Requests(class);
+        let Some(refill) = self.refill(kind) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.lock(now);
+
+        let mut wait = 0.0f64;
+        for key in keys {
+            let bucket = buckets
+                .buckets
+                .entry((kind, key.clone()))
+                .or_insert_with(|| Bucket {
+                    tokens: refill.capacity,
+                    updated: now,
+                });
+            bucket.refill(refill, now);
+            if bucket.tokens < 1.0 {
+                wait = wait.max((1.0 - bucket.tokens) / refill.per_second);
+            }
+        }
+        if wait > 0.0 {
+            return Err(Duration::from_secs_f64(wait));
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.buckets.get_mut(&(kind, key.clone())) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes `bytes` out of a bandwidth bucket, going into debt if there aren't
+    /// enough; returns how long to wait before sending them.
+    fn consume(&self, kind: Kind, key: Key, bytes: usize) -> Duration {
+        let Some(refill) = self.refill(kind) else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let mut buckets = self.lock(now);
+        let bucket = buckets
+            .buckets
+            .entry((kind, key))
+            .or_insert_with(|| Bucket {
+                tokens: refill.capacity,
+                updated: now,
+            });
+        bucket.refill(refill, now);
+        bucket.tokens -= bytes as f64;
+        Duration::from_secs_f64((-bucket.tokens).max(0.0) / refill.per_second)
+    }
+}
+
+/// Middleware enforcing the request limits of the route's class for the user and
+/// the client IP, answering `429 Too Many Requests` with `Retry-After` otherwise.
+/// Upload and download bodies are throttled to the configured bandwidth.
+pub async fn limit(State(state): State<App>, request: Request, next: Next) -> Response {
+    let Some(class) = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| Class::of(route.as_str()))
+    else {
+        return next.run(request).await;
+    };
+
+    // the handler reuses the user, so tokens are only looked up once
+    let (mut parts, body) = request.into_parts();
+    let user = User::from_request_parts(&mut parts, &state).await;
+    let request = Request::from_parts(parts, body);
+
+    // requests with a bad token are only limited per IP, the handler rejects them
+    let user_id = user.ok().map(|user| user.id);
+    let mut keys: Vec<Key> = user_id.into_iter().map(Key::User).collect();
+    if let Some(ip) = client::ip(request.headers(), request.extensions(), state.trust_proxy) {
+        keys.push(Key::Ip(ip));
+    }
+    if let Err(wait) = state.limits.check(class, &keys) {
+        tracing::debug!("Rate limited {class:?} request from {keys:?}");
+        return Error::TooManyRequests(wait).into_response();
+    }
+
+    let Some(user_id) = user_id else {
+        return next.run(request).await;
+    };
+    let request = match class {
+        Class::Upload => request.map(|body| throttle(&state, Kind::UploadBytes, user_id, body)),
+        _ => request,
+    };
+    let response = next.run(request).await;
+    match class {
+        Class::Download if response.status().is_success() => {
+            response.map(|body| throttle(&state, Kind::DownloadBytes, user_id, body))
+        }
+        _ => response,
+    }
+}
+
+/// Holds back the chunks of `body` as long as the user's bandwidth bucket is in debt.
+fn throttle(state: &App, kind: Kind, user_id: Uuid, body: Body) -> Body {
+    if state.limits.refill(kind).is_none() {
+        return body;
+    }
+    let state = state.clone();
+    let body = body.into_data_stream().then(move |chunk| {
+        let state = state.clone();
+        async move {
+            if let Ok(chunk) = &chunk {
+                let wait = state.limits.consume(kind, Key::User(user_id), chunk.len());
+                if !wait.is_zero() {
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            chunk
+        }
+    });
+    Body::from_stream(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Rate;
+
+    #[test]
+    fn login_names_are_limited_across_ips() {
+        let limits = RateLimiter::new(RateLimitConfig {
+            auth: Some(Rate {
+                requests: 2,
+                period: Duration::from_secs(60),
+            }),
+            upload: None,
+            download: None,
+            api: None,
+            upload_bandwidth: None,
+            download_bandwidth: None,
+        });
+        let attempt = |ip: [u8; 4], login: &str| {
+            let keys = [Key::Ip(IpAddr::from(ip)), Key::login(login)];
+            limits.check(Class::Auth, &keys)
+        };
+
+        assert!(attempt([10, 0, 0, 1], "alice@example.com").is_ok());
+        assert!(attempt([10, 0, 0, 2], " Alice@Example.com").is_ok());
+        // a fresh IP doesn't help, the name is out of guesses
+        assert!(attempt([10, 0, 0, 3], "ALICE@example.com").is_err());
+        assert!(attempt([10, 0, 0, 3], "bob@example.com").is_ok());
+    }
+}
@@ -0,0 +1,479 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::NaiveDateTime;
+use russh::keys::{HashAlg, PublicKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{self, Scope, User},
+    client::Client,
+    state::App,
+    totp::{self, Enrollment},
+};
+
+use super::{Error, folder};
+
+#[derive(Debug, Deserialize)]
+pub struct SetPasswordRequest {
+    pub password: String,
+    /// Required to change a password that is set already.
+    pub current_password: Option<String>,
+    /// From the authenticator app or a recovery code, once TOTP is enabled.
+    pub code: Option<String>,
+}
+
+/// `PUT /account/password`: sets or changes the password. A session alone isn't
+/// enough to take over the account: the current password and, with TOTP, a code
+/// are needed too.
+pub async fn set_password(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<SetPasswordRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    let user_id = user.id;
+
+    if input.password.len() < 8 {
+        return Err(Error::BadRequest(
+            "Password must be at least 8 characters long".to_string(),
+        ));
+    }
+
+    let current_hash = sqlx::query_scalar!(
+        r#"
+        SELECT password_hash FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if let Some(current_hash) = &current_hash {
+        let current = input.current_password.as_deref().unwrap_or_default();
+        if !auth::verify_password(current, current_hash) {
+            return Err(Error::Unauthorized);
+        }
+    }
+    if totp::enabled(&state.db, user_id).await? {
+        let code = input.code.as_deref().unwrap_or_default();
+        totp::verify(&state.db, user_id, code)
+            .await?
+            .ok_or(Error::Unauthorized)?;
+    }
+
+    let hash =
+        auth::hash_password(&input.password).map_err(|err| Error::BadRequest(err.to_string()))?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET password_hash = $1 WHERE id = $2
+        "#,
+        hash,
+        user_id
+    )
+    .execute(&state.db)
+    .await?;
+    audit::record(
+        &state.db,
+        user_id,
+        &client,
+        Event::user("account.password", user_id)
+            .after(serde_json::json!({ "changed": current_hash.is_some() })),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SshKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+pub async fn list_ssh_keys(
+    State(state): State<App>,
+    user: User,
+) -> Result<Json<Vec<SshKeyResponse>>, Error> {
+    user.require_session()?;
+    let user_id = user.id;
+
+    let keys = sqlx::query_as!(
+        SshKeyResponse,
+        r#"
+        SELECT id, name, public_key, fingerprint, created_at FROM ssh_keys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(keys))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddSshKeyRequest {
+    pub name: String,
+    /// A key in OpenSSH format, e.g. the contents of `~/.ssh/id_ed25519.pub`.
+    pub public_key: String,
+}
+
+pub async fn add_ssh_key(
+    State(state): State<App>,
+    user: User,
+    Json(input): Json<AddSshKeyRequest>,
+) -> Result<(StatusCode, Json<SshKeyResponse>), Error> {
+    user.require_session()?;
+    let user_id = user.id;
+
+    let key = PublicKey::from_openssh(input.public_key.trim())
+        .map_err(|err| Error::BadRequest(format!("Invalid public key: {err}")))?;
+    let public_key = key
+        .to_openssh()
+        .map_err(|err| Error::BadRequest(format!("Invalid public key: {err}")))?;
+    let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM ssh_keys WHERE fingerprint = $1
+        "#,
+        fingerprint
+    )
+    .fetch_optional(&state.db)
+    .await?;
+
+    if existing.is_some() {
+        return Err(Error::Conflict("This key is already registered"));
+    }
+
+    let key = sqlx::query_as!(
+        SshKeyResponse,
+        r#"
+        INSERT INTO ssh_keys (user_id, name, public_key, fingerprint)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, name, public_key, fingerprint, created_at
+        "#,
+        user_id,
+        input.name,
+        public_key,
+        fingerprint
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+pub async fn delete_ssh_key(
+    State(state): State<App>,
+    user: User,
+    Path(key_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    let user_id = user.id;
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM ssh_keys WHERE id = $1 AND user_id = $2
+        "#,
+        key_id,
+        user_id
+    )
+    .execute(&state.db)
+    .await?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// The start of the token, e.g. `cloud_Ab3dE9`.
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub folder_id: Option<Uuid>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub last_used_ip: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// `GET /account/tokens`
+pub async fn list_tokens(
+    State(state): State<App>,
+    user: User,
+) -> Result<Json<Vec<TokenResponse>>, Error> {
+    user.require_session()?;
+
+    let tokens = sqlx::query!(
+        r#"
+        SELECT id, name, prefix, scopes, folder_id, expires_at, last_used_at,
+               host(last_used_ip) AS last_used_ip, created_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user.id
+    )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|token| TokenResponse {
+        id: token.id,
+        name: token.name,
+        prefix: token.prefix,
+        scopes: token
+            .scopes
+            .iter()
+            .filter_map(|scope| Scope::parse(scope))
+            .collect(),
+        folder_id: token.folder_id,
+        expires_at: token.expires_at,
+        last_used_at: token.last_used_at,
+        last_used_ip: token.last_used_ip,
+        created_at: token.created_at,
+    })
+    .collect();
+
+    Ok(Json(tokens))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    /// E.g. `["read"]` for a read-only token, `["upload"]` for one that can only add files.
+    pub scopes: Vec<Scope>,
+    /// Restricts the token to this folder and what is below it.
+    pub folder_id: Option<Uuid>,
+    /// Never expires if not set.
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTokenResponse {
+    #[serde(flatten)]
+    pub info: TokenResponse,
+    /// The token itself, for `Authorization: Bearer`. It is not shown again.
+    pub token: String,
+}
+
+/// `POST /account/tokens`
+pub async fn create_token(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<CreateTokenResponse>), Error> {
+    user.require_session()?;
+
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("Token name is required".to_string()));
+    }
+    if input.scopes.is_empty() {
+        return Err(Error::BadRequest(
+            "A token needs at least one scope".to_string(),
+        ));
+    }
+    if input
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= chrono::Utc::now().naive_utc())
+    {
+        return Err(Error::BadRequest(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+    if let Some(folder_id) = input.folder_id {
+        folder::or_root(&state.db, user.id, Some(folder_id)).await?;
+    }
+
+    let mut scopes = input.scopes;
+    scopes.sort_by_key(|scope| scope.as_str());
+    scopes.dedup();
+    let token = auth::generate_token();
+    let prefix = &token[..auth::TOKEN_DISPLAY_LEN];
+    let token_hash = auth::hash_token(&token);
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (user_id, name, token_hash, prefix, scopes, folder_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, created_at
+        "#,
+        user.id,
+        name,
+        token_hash.as_slice(),
+        prefix,
+        &scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect::<Vec<_>>(),
+        input.folder_id,
+        input.expires_at
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    let info = TokenResponse {
+        id: created.id,
+        name: name.to_string(),
+        prefix: prefix.to_string(),
+        scopes,
+        folder_id: input.folder_id,
+        expires_at: input.expires_at,
+        last_used_at: None,
+        last_used_ip: None,
+        created_at: created.created_at,
+    };
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::token("token.create", info.id).after(&info),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateTokenResponse { info, token }),
+    ))
+}
+
+/// `DELETE /account/tokens/{id}`: the token stops working right away.
+pub async fn revoke_token(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(token_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+
+    let revoked = sqlx::query!(
+        r#"
+        DELETE FROM api_tokens WHERE id = $1 AND user_id = $2 RETURNING name, prefix
+        "#,
+        token_id,
+        user.id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let event = Event::token("token.revoke", token_id)
+        .before(serde_json::json!({ "name": revoked.name, "prefix": revoked.prefix }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpStatus {
+    pub enabled: bool,
+    /// The user may not turn it off.
+    pub required: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// `GET /account/totp`
+pub async fn totp_status(State(state): State<App>, user: User) -> Result<Json<TotpStatus>, Error> {
+    user.require_session()?;
+    Ok(Json(TotpStatus {
+        enabled: totp::enabled(&state.db, user.id).await?,
+        required: totp::required(&state.db, &state.totp, user.id).await?,
+        recovery_codes_left: totp::recovery_codes_left(&state.db, user.id).await?,
+    }))
+}
+
+/// `POST /account/totp`: starts setting up an authenticator app; confirm with
+/// a code from it.
+pub async fn enroll_totp(State(state): State<App>, user: User) -> Result<Json<Enrollment>, Error> {
+    user.require_session()?;
+    Ok(Json(totp::enroll(&state.db, &state.totp, user.id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Only shown this once.
+    pub recovery_codes: Vec<String>,
+}
+
+/// `POST /account/totp/confirm`: enables TOTP with a first code from the app.
+pub async fn confirm_totp(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Error> {
+    user.require_session()?;
+    let recovery_codes = totp::confirm(&state.db, user.id, &input.code).await?;
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.totp_enable", user.id),
+    )
+    .await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// `DELETE /account/totp`: turns TOTP off, unless it is required.
+pub async fn disable_totp(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<TotpCodeRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    if totp::required(&state.db, &state.totp, user.id).await? {
+        return Err(Error::Conflict(
+            "Two-factor authentication is required for this account",
+        ));
+    }
+    totp::verify(&state.db, user.id, &input.code)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+
+    totp::disable(&state.db, user.id).await?;
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.totp_disable", user.id),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /account/totp/recovery-codes`: replaces all recovery codes.
+pub async fn regenerate_recovery_codes(
+    State(state): State<App>,
+    user: User,
+    Json(input): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Error> {
+    user.require_session()?;
+    totp::verify(&state.db, user.id, &input.code)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: totp::regenerate_recovery_codes(&state.db, user.id).await?,
+    }))
+}
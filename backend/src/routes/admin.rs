@@ -0,0 +1,828 @@
+//! Endpoints for administrators, i.e. users with `is_admin` set.
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{self, User},
+    client::Client,
+    jobs::{self, Job},
+    state::App,
+    totp,
+};
+
+use super::Error;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+/// Rows fetched at a time while exporting.
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
+pub async fn ensure_admin(db: &PgPool, user_id: Uuid) -> Result<(), Error> {
+    let is_admin = sqlx::query_scalar!(
+        r#"
+        SELECT is_admin FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+    match is_admin {
+        Some(true) => Ok(()),
+        _ => Err(Error::Forbidden),
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditQuery {
+    /// Only what this user did.
+    pub user_id: Option<Uuid>,
+    /// Comma-separated actions, e.g. `file.delete,folder.delete`.
+    pub action: Option<String>,
+    pub target_id: Option<Uuid>,
+    /// Inclusive.
+    pub from: Option<NaiveDateTime>,
+    /// Exclusive.
+    pub to: Option<NaiveDateTime>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+    /// `csv` exports all matching entries at once, ignoring `cursor` and `limit`.
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: NaiveDateTime,
+    pub actor_id: Uuid,
+    /// `None` if the user was deleted since.
+    pub actor_email: Option<String>,
+    pub action: String,
+    /// `file`, `folder`, `vault`, `team`, `token` or `user`.
+    pub target_type: String,
+    pub target_id: Uuid,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `cursor` to fetch the next (older) page; `None` on the last page.
+    pub next_cursor: Option<i64>,
+}
+
+/// `GET /admin/audit`: audit log entries, newest first, filtered by user, action,
+/// target and time range.
+pub async fn audit(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    if let Format::Csv = query.format {
+        return Ok(export(state, query));
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let mut entries = page(&state.db, &query, query.cursor, limit + 1).await?;
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|last| last.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AuditResponse {
+        entries,
+        next_cursor,
+    })
+    .into_response())
+}
+
+/// Streams all matching entries as CSV, in batches so the whole log never has to
+/// be in memory.
+fn export(state: App, query: AuditQuery) -> Response {
+    let header_row = Bytes::from_static(
+        b"id,at,actor_id,actor_email,action,target_type,target_id,old_value,new_value,ip,user_agent\n",
+    );
+    let rows = stream::try_unfold(Some(None), move |cursor| {
+        let state = state.clone();
+        let query = query.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+            let entries = page(&state.db, &query, cursor, EXPORT_BATCH_SIZE)
+                .await
+                .map_err(|err| {
+                    tracing::error!("Audit log export failed: {err}");
+                    std::io::Error::other(err)
+                })?;
+            let next = (entries.len() as i64 == EXPORT_BATCH_SIZE)
+                .then(|| entries.last().map(|last| last.id));
+            let chunk: String = entries.iter().map(csv_row).collect();
+            Ok::<_, std::io::Error>(Some((Bytes::from(chunk), next)))
+        }
+    });
+    let body = stream::once(async { Ok(header_row) }).chain(rows);
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"audit.csv\"",
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Up to `limit` matching entries older than `cursor`, newest first.
+async fn page(
+    db: &PgPool,
+    query: &AuditQuery,
+    cursor: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    let mut builder = QueryBuilder::<Postgres>::new(
+        "SELECT a.id, a.at, a.actor_id, u.email AS actor_email, a.action, a.target_type, a.target_id, \
+         a.old_value, a.new_value, host(a.ip) AS ip, a.user_agent \
+         FROM audit_log a LEFT JOIN users u ON u.id = a.actor_id WHERE TRUE",
+    );
+    if let Some(user_id) = query.user_id {
+        builder.push(" AND a.actor_id = ").push_bind(user_id);
+    }
+    if let Some(actions) = &query.action {
+        let actions: Vec<String> = actions
+            .split(',')
+            .map(|action| action.trim().to_string())
+            .filter(|action| !action.is_empty())
+            .collect();
+        builder
+            .push(" AND a.action = ANY(")
+            .push_bind(actions)
+            .push(")");
+    }
+    if let Some(target_id) = query.target_id {
+        builder.push(" AND a.target_id = ").push_bind(target_id);
+    }
+    if let Some(from) = query.from {
+        builder.push(" AND a.at >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder.push(" AND a.at < ").push_bind(to);
+    }
+    if let Some(cursor) = cursor {
+        builder.push(" AND a.id < ").push_bind(cursor);
+    }
+    builder.push(" ORDER BY a.id DESC LIMIT ").push_bind(limit);
+
+    builder.build_query_as().fetch_all(db).await
+}
+
+fn csv_row(entry: &AuditEntry) -> String {
+    let json = |value: &Option<serde_json::Value>| {
+        value
+            .as_ref()
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    };
+    let fields = [
+        entry.id.to_string(),
+        entry.at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string(),
+        entry.actor_id.to_string(),
+        entry.actor_email.clone().unwrap_or_default(),
+        entry.action.clone(),
+        entry.target_type.clone(),
+        entry.target_id.to_string(),
+        json(&entry.old_value),
+        json(&entry.new_value),
+        entry.ip.clone().unwrap_or_default(),
+        entry.user_agent.clone().unwrap_or_default(),
+    ];
+    let mut row = fields.map(|field| csv_field(&field)).join(",");
+    row.push('\n');
+    row
+}
+
+/// Quotes a field if needed, as RFC 4180 asks. Fields starting with a formula
+/// character are prefixed with `'`, so spreadsheets don't evaluate them.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpPolicyRequest {
+    /// `None` follows the server's `CLOUD_TOTP_REQUIRED`.
+    pub required: Option<bool>,
+}
+
+/// `PUT /admin/users/{id}/totp-policy`: whether the user must use two-factor
+/// authentication.
+pub async fn set_totp_policy(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(user_id): Path<Uuid>,
+    Json(input): Json<TotpPolicyRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let old = sqlx::query_scalar!(
+        r#"
+        UPDATE users u SET totp_required = $2 FROM users old
+        WHERE u.id = $1 AND old.id = u.id
+        RETURNING old.totp_required
+        "#,
+        user_id,
+        input.required
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.update", user_id)
+            .before(json!({ "totp_required": old }))
+            .after(json!({ "totp_required": input.required })),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `DELETE /admin/users/{id}/totp`: turns off two-factor authentication for a
+/// user who lost their app and recovery codes. If it is required, they set it up
+/// again on their next login.
+pub async fn reset_totp(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(user_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    if !totp::disable(&state.db, user_id).await? {
+        return Err(Error::NotFound);
+    }
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.totp_reset", user_id),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub email: String,
+    pub name: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub is_admin: bool,
+    /// Set while the user can't log in.
+    pub disabled_at: Option<NaiveDateTime>,
+    /// From the identity provider or LDAP directory, as of their last login.
+    pub groups: Vec<String>,
+    /// Bytes taken up by the user's files.
+    pub used_bytes: i64,
+    /// The user's own quota; `None` uses the server default.
+    pub quota_bytes: Option<i64>,
+    /// The quota that applies, `None` if the user has no limit.
+    pub quota: Option<i64>,
+    pub files: i64,
+    /// Unexpired browser sessions.
+    pub sessions: i64,
+}
+
+/// `GET /admin/users`: all users with their storage usage, by email.
+pub async fn list_users(
+    State(state): State<App>,
+    user: User,
+) -> Result<Json<Vec<UserSummary>>, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    Ok(Json(users(&state, None).await?))
+}
+
+/// Users with their usage, or only the one with `user_id`.
+async fn users(state: &App, user_id: Option<Uuid>) -> Result<Vec<UserSummary>, sqlx::Error> {
+    let users = sqlx::query!(
+        r#"
+        SELECT u.id, u.email, u.name, u.created_at, u.is_admin, u.disabled_at, u.groups,
+               u.used_bytes, u.quota_bytes,
+               (SELECT COUNT(*) FROM files f WHERE f.user_id = u.id) AS "files!",
+               (SELECT COUNT(*) FROM sessions s
+                WHERE s.user_id = u.id AND s.expires_at > now()) AS "sessions!"
+        FROM users u
+        WHERE $1::UUID IS NULL OR u.id = $1
+        ORDER BY lower(u.email)
+        "#,
+        user_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(users
+        .into_iter()
+        .map(|user| UserSummary {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            created_at: user.created_at,
+            is_admin: user.is_admin,
+            disabled_at: user.disabled_at,
+            groups: user.groups,
+            used_bytes: user.used_bytes,
+            quota_bytes: user.quota_bytes,
+            quota: user.quota_bytes.or(state.uploads.default_quota),
+            files: user.files,
+            sessions: user.sessions,
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub email: String,
+    pub name: Option<String>,
+    /// Without one, the user logs in through the identity provider or LDAP.
+    pub password: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
+    /// `None` uses the server default.
+    pub quota_bytes: Option<i64>,
+}
+
+/// `POST /admin/users`: creates a user.
+pub async fn create_user(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<CreateUserRequest>,
+) -> Result<(StatusCode, Json<UserSummary>), Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let email = input.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(Error::BadRequest("Invalid email address".to_string()));
+    }
+    let name = input
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    check_quota(input.quota_bytes)?;
+    let password_hash = match &input.password {
+        Some(password) if password.len() < 8 => {
+            return Err(Error::BadRequest(
+                "Password must be at least 8 characters long".to_string(),
+            ));
+        }
+        Some(password) => {
+            Some(auth::hash_password(password).map_err(|err| Error::BadRequest(err.to_string()))?)
+        }
+        None => None,
+    };
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (email, name, password_hash, is_admin, quota_bytes)
+        SELECT $1, $2, $3, $4, $5
+        WHERE NOT EXISTS (SELECT 1 FROM users WHERE lower(email) = $1)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        email,
+        name,
+        password_hash,
+        input.is_admin,
+        input.quota_bytes
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::Conflict("A user with this email already exists"))?;
+
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.create", user_id).after(json!({
+            "email": email,
+            "name": name,
+            "is_admin": input.is_admin,
+            "quota_bytes": input.quota_bytes,
+        })),
+    )
+    .await?;
+
+    let created = users(&state, Some(user_id))
+        .await?
+        .pop()
+        .ok_or(Error::NotFound)?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisabledRequest {
+    pub disabled: bool,
+}
+
+/// `PUT /admin/users/{id}/disabled`: disables or enables a user. Disabling ends
+/// their sessions; their API tokens stop working until they are enabled again.
+pub async fn set_disabled(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(user_id): Path<Uuid>,
+    Json(input): Json<DisabledRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+    if user_id == user.id {
+        return Err(Error::Conflict("Admins can't disable themselves"));
+    }
+
+    let mut tx = state.db.begin().await?;
+    let was_disabled = sqlx::query_scalar!(
+        r#"
+        UPDATE users u
+        SET disabled_at = CASE WHEN $2 THEN COALESCE(old.disabled_at, now()) END
+        FROM users old
+        WHERE u.id = $1 AND old.id = u.id
+        RETURNING old.disabled_at IS NOT NULL AS "disabled!"
+        "#,
+        user_id,
+        input.disabled
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+    if input.disabled {
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    audit::record(
+        &mut *tx,
+        user.id,
+        &client,
+        Event::user("user.update", user_id)
+            .before(json!({ "disabled": was_disabled }))
+            .after(json!({ "disabled": input.disabled })),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuotaRequest {
+    /// `None` uses the server default.
+    pub quota_bytes: Option<i64>,
+}
+
+/// `PUT /admin/users/{id}/quota`: how much the user may store.
+pub async fn set_quota(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(user_id): Path<Uuid>,
+    Json(input): Json<QuotaRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+    check_quota(input.quota_bytes)?;
+
+    let old = sqlx::query_scalar!(
+        r#"
+        UPDATE users u SET quota_bytes = $2 FROM users old
+        WHERE u.id = $1 AND old.id = u.id
+        RETURNING old.quota_bytes
+        "#,
+        user_id,
+        input.quota_bytes
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.update", user_id)
+            .before(json!({ "quota_bytes": old }))
+            .after(json!({ "quota_bytes": input.quota_bytes })),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn check_quota(quota_bytes: Option<i64>) -> Result<(), Error> {
+    match quota_bytes {
+        Some(bytes) if bytes < 0 => {
+            Err(Error::BadRequest("Quota must not be negative".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `DELETE /admin/users/{id}`: deletes a user with all their folders and files.
+/// The contents are removed from disk in the background.
+pub async fn delete_user(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(user_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+    if user_id == user.id {
+        return Err(Error::Conflict("Admins can't delete themselves"));
+    }
+
+    let mut tx = state.db.begin().await?;
+    let deleted = sqlx::query!(
+        r#"
+        SELECT email, name FROM users WHERE id = $1 FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    // files don't go along with their user by themselves
+    let file_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM files WHERE user_id = $1 RETURNING id
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if !file_ids.is_empty() {
+        let job = Job::RemoveBlobs {
+            user_id,
+            file_ids: file_ids.clone(),
+        };
+        jobs::enqueue(&mut *tx, &job).await?;
+    }
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    // teams they owned alone pass to their longest-standing member
+    sqlx::query!(
+        r#"
+        UPDATE team_members m SET role = 'owner'
+        FROM (
+            SELECT DISTINCT ON (team_id) team_id, user_id FROM team_members t
+            WHERE NOT EXISTS (
+                SELECT 1 FROM team_members o WHERE o.team_id = t.team_id AND o.role = 'owner'
+            )
+            ORDER BY team_id, added_at, user_id
+        ) heir
+        WHERE m.team_id = heir.team_id AND m.user_id = heir.user_id
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    audit::record(
+        &mut *tx,
+        user.id,
+        &client,
+        Event::user("user.delete", user_id).before(json!({
+            "email": deleted.email,
+            "name": deleted.name,
+            "files": file_ids.len(),
+        })),
+    )
+    .await?;
+    tx.commit().await?;
+    state.jobs.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionsQuery {
+    /// Only this user's sessions.
+    pub user_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// `GET /admin/sessions`: unexpired browser sessions, newest first.
+pub async fn list_sessions(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<SessionsQuery>,
+) -> Result<Json<Vec<SessionSummary>>, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let sessions = sqlx::query_as!(
+        SessionSummary,
+        r#"
+        SELECT s.id, s.user_id, u.email, s.created_at, s.expires_at, host(s.ip) AS ip,
+               s.user_agent
+        FROM sessions s JOIN users u ON u.id = s.user_id
+        WHERE s.expires_at > now() AND ($1::UUID IS NULL OR s.user_id = $1)
+        ORDER BY s.created_at DESC
+        "#,
+        query.user_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+    Ok(Json(sessions))
+}
+
+/// `DELETE /admin/sessions/{id}`: logs a browser out.
+pub async fn delete_session(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(session_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let user_id = sqlx::query_scalar!(
+        "DELETE FROM sessions WHERE id = $1 RETURNING user_id",
+        session_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::user("user.logout", user_id).before(json!({ "session_id": session_id })),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub vault_id: Uuid,
+    pub vault_name: String,
+    pub owner_id: Uuid,
+    pub owner_email: String,
+    pub member_id: Uuid,
+    pub member_email: String,
+    pub added_at: NaiveDateTime,
+}
+
+/// `GET /admin/shares`: vaults shared with users other than their owner, newest
+/// first. Vaults are the only thing users share.
+pub async fn list_shares(State(state): State<App>, user: User) -> Result<Json<Vec<Share>>, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let shares = sqlx::query_as!(
+        Share,
+        r#"
+        SELECT f.id AS vault_id, f.name AS vault_name, o.id AS owner_id, o.email AS owner_email,
+               m.id AS member_id, m.email AS member_email, vm.added_at
+        FROM vault_members vm
+        JOIN folders f ON f.id = vm.folder_id
+        JOIN users o ON o.id = f.user_id
+        JOIN users m ON m.id = vm.user_id
+        WHERE vm.user_id <> f.user_id
+        ORDER BY vm.added_at DESC
+        "#
+    )
+    .fetch_all(&state.db)
+    .await?;
+    Ok(Json(shares))
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobSummary {
+    pub id: i64,
+    pub kind: String,
+    pub run_at: NaiveDateTime,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// Set while a runner works on the job.
+    pub locked_until: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    /// Set once the job ran out of attempts.
+    pub failed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// `GET /admin/jobs`: queued, running and failed background jobs, oldest first.
+pub async fn list_jobs(
+    State(state): State<App>,
+    user: User,
+) -> Result<Json<Vec<JobSummary>>, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let jobs = sqlx::query_as!(
+        JobSummary,
+        r#"
+        SELECT id, kind, run_at, attempts, max_attempts, locked_until, last_error, failed_at,
+               created_at
+        FROM jobs
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&state.db)
+    .await?;
+    Ok(Json(jobs))
+}
+
+/// Jobs an admin may start.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MaintenanceRequest {
+    /// Re-hash every blob, see `backend verify`.
+    Verify,
+    /// Compare disk and database, see `backend fsck`.
+    Fsck {
+        #[serde(default)]
+        repair: bool,
+    },
+}
+
+/// `POST /admin/jobs`: starts a maintenance job in the background; its report
+/// goes to the server log.
+pub async fn start_job(
+    State(state): State<App>,
+    user: User,
+    Json(input): Json<MaintenanceRequest>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    ensure_admin(&state.db, user.id).await?;
+
+    let job = match input {
+        MaintenanceRequest::Verify => Job::Verify,
+        MaintenanceRequest::Fsck { repair } => Job::Fsck { repair },
+    };
+    tracing::info!("Admin {} started {job:?}", user.id);
+    jobs::enqueue(&state.db, &job).await?;
+    state.jobs.notify_one();
+    Ok(StatusCode::ACCEPTED)
+}
@@ -1 +1,211 @@
-pub async fn handler() {}
+use std::{io, ops::Range};
+
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use tower_http::services::ServeFile;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    encryption::Blob,
+    integrity,
+    state::App,
+};
+
+use super::{Error, team::Role};
+
+/// `GET /download/{id}`: the file's contents with its stored content type.
+///
+/// Range requests are supported, so videos can be seeked and large downloads resumed.
+/// Of encrypted files only the chunks in the requested range are decrypted.
+/// Files with a checksum get it as `ETag` and `Digest`, and `If-None-Match` is honoured.
+pub async fn handler(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(file_id): Path<Uuid>,
+    request: Request,
+) -> Result<Response, Error> {
+    user.require(Scope::Read)?;
+    user.ensure_file(&state.db, file_id).await?;
+    let drive = user.file_drive(&state.db, file_id, Role::Viewer).await?;
+
+    let file = sqlx::query!(
+        r#"
+        SELECT filename, mime_type, sha256 FROM files WHERE id = $1 AND user_id = $2
+        "#,
+        file_id,
+        drive
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let etag = file.sha256.as_deref().map(integrity::etag);
+    if let Some(etag) = &etag {
+        let cached = request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                // weak comparison, as RFC 9110 asks for `If-None-Match`
+                value.split(',').any(|tag| {
+                    let tag = tag.trim();
+                    tag == "*" || tag.trim_start_matches("W/") == etag
+                })
+            });
+        if cached {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response());
+        }
+    }
+
+    let blob = match Blob::open(&state, drive, file_id).await {
+        Ok(blob) => blob,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(err) => return Err(err.into()),
+    };
+
+    // every request counts, resumed downloads and seeking in videos included
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let event = Event::file("file.download", file_id)
+        .after(serde_json::json!({ "filename": file.filename, "range": range }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    let mut response = if blob.is_encrypted() {
+        let range = request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| byte_range(value, blob.size()));
+        serve_encrypted(blob, range)
+    } else {
+        ServeFile::new(state.blob_path(drive, file_id))
+            .try_call(request)
+            .await?
+            .map(Body::new)
+    };
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(Error::NotFound);
+    }
+
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&file.mime_type)
+            .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&content_disposition(&file.filename)).unwrap(),
+    );
+    if let (Some(etag), Some(checksum)) = (etag, &file.sha256) {
+        headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+        // the digest of the whole file, also on partial responses
+        headers.insert(
+            HeaderName::from_static("digest"),
+            HeaderValue::from_str(&integrity::digest_header(checksum)).unwrap(),
+        );
+    }
+    // never let browsers second-guess the sniffed type
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    Ok(response.into_response())
+}
+
+/// Decrypts the requested part of an encrypted blob while it is sent.
+fn serve_encrypted(blob: Blob, range: Option<Range<u64>>) -> Response {
+    let size = blob.size();
+    let Some(range) = range else {
+        return (
+            [
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+                (header::CONTENT_LENGTH, size.to_string()),
+            ],
+            Body::from_stream(blob.into_stream(0..size)),
+        )
+            .into_response();
+    };
+
+    if range.start >= range.end {
+        return (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+        )
+            .into_response();
+    }
+
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (
+                header::CONTENT_LENGTH,
+                (range.end - range.start).to_string(),
+            ),
+            (
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{size}", range.start, range.end - 1),
+            ),
+        ],
+        Body::from_stream(blob.into_stream(range)),
+    )
+        .into_response()
+}
+
+/// Parses a single `bytes=<first>-<last>` range, `bytes=<first>-` or the last bytes
+/// with `bytes=-<count>`, clamped to the file. Anything else, like several ranges,
+/// is ignored and gets the whole file, as RFC 9110 allows. An empty range means
+/// the request is not satisfiable.
+fn byte_range(value: &str, size: u64) -> Option<Range<u64>> {
+    let (first, last) = value.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let range = match (first, last) {
+        ("", count) => size.saturating_sub(count.parse().ok()?)..size,
+        (first, "") => first.parse().ok()?..size,
+        (first, last) => {
+            let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+            if last < first {
+                return None;
+            }
+            first..size.min(last.saturating_add(1))
+        }
+    };
+    Some(range.start.min(size)..range.end)
+}
+
+/// `attachment` with an ASCII fallback name and the exact name in RFC 5987 encoding.
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect();
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
@@ -1,73 +1,584 @@
-use axum::{extract::{Path, State}, response::IntoResponse, Json};
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 
-use crate::state::App;
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    state::{App, AppState},
+    thumbnails,
+};
 
-use super::folder;
+use super::{
+    Error, folder,
+    path::{self, Node},
+    team::Role,
+};
 
-#[derive(Debug, serde::Deserialize)]
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
 pub struct FileRequest {
     pub folder_id: Option<Uuid>,
+    /// Alternative to `folder_id`, e.g. `/Projects/2025`.
+    pub path: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// List all folders before the files (default), regardless of the sort order.
+    #[serde(default = "default_true")]
+    pub folders_first: bool,
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Only list files or only list folders.
+    pub kind: Option<EntryKind>,
+    /// Only list files with one of these extensions (e.g. `["pdf", "md"]`).
+    pub types: Option<Vec<String>>,
+    pub modified_after: Option<NaiveDateTime>,
+    pub modified_before: Option<NaiveDateTime>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortField {
+    /// The SQL expression entries are ordered by, and the type it has.
+    fn key(self) -> (&'static str, &'static str) {
+        match self {
+            SortField::Name => ("lower(name)", "TEXT"),
+            SortField::Size => ("size", "BIGINT"),
+            SortField::Modified => ("COALESCE(last_modified, 'epoch')", "TIMESTAMP"),
+            SortField::Type => ("COALESCE(type, '')", "TEXT"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
 }
 
-pub async fn get_handler(State(state): State<App>, Json(payload): Json<FileRequest>) -> impl IntoResponse {
-    // let folder_id = if folder_id.is_empty() {
-    //     Uuid::nil()
-    // } else {
-    //     Uuid::parse_str(&folder_id).unwrap()
-    // };
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Folder,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Entry {
+    /// `file` or `folder`.
+    pub kind: String,
+    pub id: Uuid,
+    pub name: String,
+    /// For folders the total size of everything below them.
+    pub size: i64,
+    pub last_modified: Option<NaiveDateTime>,
+    /// Lowercase file extension, `None` for folders.
+    #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
+    pub file_type: Option<String>,
+    /// Whether `GET /files/{id}/thumbnail` has an image for this file.
+    pub thumbnail: bool,
+    /// Sniffed content type, `None` for folders.
+    pub mime_type: Option<String>,
+    /// `pending`, `processing`, `ready` or `failed` for videos, see `GET /files/{id}/hls/master.m3u8`.
+    pub video_status: Option<String>,
+    /// Hex encoded SHA-256 of the contents; `None` for folders and files not hashed yet.
+    pub sha256: Option<String>,
+    /// Whether the file's blob is missing or damaged, as found by `backend verify` or `backend fsck`.
+    pub broken: bool,
+    /// Number of files below a folder, at any depth; `None` for files.
+    pub file_count: Option<i64>,
+    /// Number of folders below a folder, at any depth; `None` for files.
+    pub folder_count: Option<i64>,
+    #[serde(skip)]
+    sort_group: i32,
+    #[serde(skip)]
+    sort_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListingResponse {
+    pub entries: Vec<Entry>,
+    /// Pass as `cursor` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Number of matching files and folders across all pages.
+    pub total_files: i64,
+    pub total_folders: i64,
+}
 
-    // if payload.folder_id.is_some_and(|id| id.is_nil()) {
-    //     payload.folder_id = None;
-    // }
+/// Position after the last entry of a page.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    group: i32,
+    key: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    fn decode(cursor: &str) -> Result<Self, Error> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| Error::BadRequest("Invalid cursor".to_string()))
+    }
+}
 
-    dbg!(&payload);
+pub async fn get_handler(
+    State(state): State<App>,
+    user: User,
+    Json(payload): Json<FileRequest>,
+) -> Result<Json<ListingResponse>, Error> {
+    user.require(Scope::Read)?;
+
+    // paths are relative to the root of the user's own drive
+    let (user_id, folder_id) = match &payload.path {
+        Some(folder_path) => {
+            match path::resolve(&state.db, user.id, &path::split(folder_path)).await? {
+                Node::Folder(id) => (user.id, id),
+                Node::File(_) => return Err(Error::BadRequest("Path is not a folder".to_string())),
+            }
+        }
+        None => {
+            let folder_id = user.folder_or_default(&state.db, payload.folder_id).await?;
+            let drive = user.drive(&state.db, folder_id, Role::Viewer).await?;
+            (drive, folder::or_root(&state.db, drive, folder_id).await?)
+        }
+    };
+    user.ensure_folder(&state.db, folder_id).await?;
+
+    tracing::info!("Fetching files in folder: {}", folder_id);
+
+    let (entries, next_cursor) = page(&state.db, user_id, folder_id, &payload).await?;
+
+    let mut count = QueryBuilder::new(
+        "SELECT count(*) FILTER (WHERE kind = 'file'), count(*) FILTER (WHERE kind = 'folder') FROM ",
+    );
+    push_entries(&mut count, user_id, folder_id, &payload);
+    let (total_files, total_folders): (i64, i64) =
+        count.build_query_as().fetch_one(&state.db).await?;
+
+    Ok(Json(ListingResponse {
+        entries,
+        next_cursor,
+        total_files,
+        total_folders,
+    }))
+}
+
+/// Fetches one page of the listing of `folder_id` and the cursor of the page after it.
+async fn page(
+    db: &PgPool,
+    drive_id: Uuid,
+    folder_id: Uuid,
+    payload: &FileRequest,
+) -> Result<(Vec<Entry>, Option<String>), Error> {
+    let limit = payload
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = payload.cursor.as_deref().map(Cursor::decode).transpose()?;
+    let (key, key_type) = payload.sort.key();
+    let (cmp, dir) = match payload.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    let sort_group = if payload.folders_first {
+        "CASE WHEN kind = 'folder' THEN 0 ELSE 1 END"
+    } else {
+        "0"
+    };
+
+    let mut query = QueryBuilder::new(format!(
+        "SELECT * FROM (SELECT e.*, {sort_group} AS sort_group, ({key})::TEXT AS sort_key FROM "
+    ));
+    push_entries(&mut query, drive_id, folder_id, payload);
+    query.push(") page");
+
+    // keyset pagination: continue strictly after the last entry of the previous page
+    if let Some(cursor) = cursor {
+        query
+            .push(" WHERE sort_group > ")
+            .push_bind(cursor.group)
+            .push(" OR (sort_group = ")
+            .push_bind(cursor.group)
+            .push(format!(" AND ({key}) {cmp} CAST("))
+            .push_bind(cursor.key.clone())
+            .push(format!(" AS {key_type})) OR (sort_group = "))
+            .push_bind(cursor.group)
+            .push(format!(" AND ({key}) = CAST("))
+            .push_bind(cursor.key)
+            .push(format!(" AS {key_type}) AND id {cmp} "))
+            .push_bind(cursor.id)
+            .push(")");
+    }
+
+    query
+        .push(format!(
+            " ORDER BY sort_group, ({key}) {dir}, id {dir} LIMIT "
+        ))
+        .push_bind(limit + 1);
+
+    let mut entries: Vec<Entry> = query.build_query_as().fetch_all(db).await?;
+
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|last| {
+            Cursor {
+                group: last.sort_group,
+                key: last.sort_key.clone(),
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok((entries, next_cursor))
+}
+
+/// Pushes the (filtered) files and folders of `folder_id` as a subquery named `e`.
+fn push_entries(
+    query: &mut QueryBuilder<'_, Postgres>,
+    drive_id: Uuid,
+    folder_id: Uuid,
+    payload: &FileRequest,
+) {
+    query
+        .push(
+            "(SELECT 'folder' AS kind, id, name, total_size AS size, created_at AS last_modified, NULL::TEXT AS type, false AS thumbnail, \
+             NULL::TEXT AS mime_type, NULL::TEXT AS video_status, NULL::TEXT AS sha256, false AS broken, file_count, folder_count \
+             FROM folders WHERE user_id = ",
+        )
+        .push_bind(drive_id)
+        .push(" AND parent_id = ")
+        .push_bind(folder_id)
+        .push(
+            " UNION ALL \
+             SELECT 'file', id, filename, size, last_modified, lower(substring(filename FROM '\\.([^.]+)$')), has_thumbnail, \
+             mime_type, video_status, encode(sha256, 'hex'), corrupt OR missing, NULL, NULL \
+             FROM files WHERE user_id = ",
+        )
+        .push_bind(drive_id)
+        .push(" AND folder_id = ")
+        .push_bind(folder_id)
+        .push(") e WHERE TRUE");
+
+    match payload.kind {
+        Some(EntryKind::File) => query.push(" AND kind = 'file'"),
+        Some(EntryKind::Folder) => query.push(" AND kind = 'folder'"),
+        None => query,
+    };
+    if let Some(types) = &payload.types {
+        let types: Vec<_> = types
+            .iter()
+            .map(|t| t.trim_start_matches('.').to_lowercase())
+            .collect();
+        query.push(" AND type = ANY(").push_bind(types).push(")");
+    }
+    if let Some(after) = payload.modified_after {
+        query.push(" AND last_modified >= ").push_bind(after);
+    }
+    if let Some(before) = payload.modified_before {
+        query.push(" AND last_modified < ").push_bind(before);
+    }
+}
 
-    tracing::info!("Fetching files in folder: {}", payload.folder_id.unwrap_or_default().to_string());
-    tracing::info!("Fetching files in folder: {:?}", payload.folder_id);
-    
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(); // placeholder
-    // get all files in the folder
-    let files = sqlx::query!(
+pub async fn delete_handler(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(file_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    user.require(Scope::Write)?;
+    user.ensure_file(&state.db, file_id).await?;
+    let drive = user.file_drive(&state.db, file_id, Role::Editor).await?;
+
+    let deleted = delete(&state, drive, file_id).await?;
+    audit::record(
+        &state.db,
+        user.id,
+        &client,
+        Event::file("file.delete", file_id).before(deleted),
+    )
+    .await?;
+
+    Ok(axum::http::StatusCode::NO_CONTENT)
+}
+
+/// Where a file is, as recorded in the [audit log](crate::audit).
+#[derive(Debug, Serialize)]
+pub struct Location {
+    pub filename: String,
+    pub folder_id: Uuid,
+}
+
+/// Deletes a file's database row and its contents on disk, returning where it was.
+pub async fn delete(state: &AppState, drive_id: Uuid, file_id: Uuid) -> Result<Location, Error> {
+    let deleted = sqlx::query_as!(
+        Location,
+        r#"
+        DELETE FROM files WHERE id = $1 AND user_id = $2 RETURNING filename, folder_id
+        "#,
+        file_id,
+        drive_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    remove_contents(state, drive_id, file_id).await?;
+    Ok(deleted)
+}
+
+/// Removes a file's blob and everything derived from it; what is gone already is fine.
+pub async fn remove_contents(
+    state: &AppState,
+    drive_id: Uuid,
+    file_id: Uuid,
+) -> std::io::Result<()> {
+    remove_derived(state, drive_id, file_id).await;
+    match tokio::fs::remove_file(state.blob_path(drive_id, file_id)).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the thumbnails and video streams made from a file's contents.
+pub async fn remove_derived(state: &AppState, drive_id: Uuid, file_id: Uuid) {
+    let _ = tokio::fs::remove_dir_all(state.hls_dir(drive_id, file_id)).await;
+    for size in thumbnails::SIZES {
+        let _ = tokio::fs::remove_file(state.thumbnail_path(drive_id, file_id, size)).await;
+    }
+}
+
+/// Renames a file and/or moves it into `folder_id`, returning where it was before.
+pub async fn rename(
+    db: &PgPool,
+    drive_id: Uuid,
+    file_id: Uuid,
+    folder_id: Uuid,
+    new_name: &str,
+) -> Result<Location, Error> {
+    let new_name = sanitize_filename::sanitize(new_name);
+    folder::or_root(db, drive_id, Some(folder_id)).await?;
+
+    // names in vaults are encrypted, names elsewhere are not
+    let file = sqlx::query!(
         r#"
-        SELECT id, filename, size, last_modified FROM files WHERE folder_id = $1 AND user_id = $2
+        SELECT f.filename, f.folder_id, d.vault FROM files f JOIN folders d ON d.id = f.folder_id
+        WHERE f.id = $1 AND f.user_id = $2
         "#,
-        payload.folder_id.unwrap_or_default(),
-        user_id
+        file_id,
+        drive_id
     )
-    .fetch_all(&state.db)
-    .await
-    .unwrap();
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+    if file.vault != folder::is_vault(db, folder_id).await? {
+        return Err(Error::BadRequest(
+            "Files cannot be moved into or out of a vault".to_string(),
+        ));
+    }
 
-    // get all folders in the folder
-    let folders = sqlx::query!(
+    // Check for duplicate file name in the target folder
+    let existing = sqlx::query_scalar!(
         r#"
-        SELECT id, name, parent_id FROM folders WHERE parent_id = $1 AND user_id = $2
+        SELECT id FROM files
+        WHERE user_id = $1 AND folder_id = $2 AND filename = $3 AND id <> $4
         "#,
-        payload.folder_id,
-        user_id
+        drive_id,
+        folder_id,
+        new_name,
+        file_id
     )
-    .fetch_all(&state.db)
-    .await
-    .unwrap();
-
-    // return files and folders as JSON
-    let files: Vec<_> = files
-        .into_iter()
-        .map(|f| (f.id, f.filename, f.size, f.last_modified))
-        .collect();
-    dbg!(&files);
-    let folders: Vec<_> = folders
-        .into_iter()
-        .map(|f| (f.id, f.name, f.parent_id))
-        .collect();
-    dbg!(&folders);
-
-    let response = serde_json::json!({
-        "files": files,
-        "folders": folders,
-    });
-    (axum::http::StatusCode::OK, axum::Json(response))
-}
-
-pub async fn delete_handler() {}
+    .fetch_optional(db)
+    .await?;
+
+    if existing.is_some() {
+        return Err(Error::Conflict("File already exists with that name"));
+    }
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE files
+        SET filename = $1, folder_id = $2, last_modified = now()
+        WHERE id = $3 AND user_id = $4
+        "#,
+        new_name,
+        folder_id,
+        file_id,
+        drive_id
+    )
+    .execute(db)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(Location {
+        filename: file.filename,
+        folder_id: file.folder_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            group: 1,
+            key: "report.pdf".to_string(),
+            id: Uuid::new_v4(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.group, cursor.group);
+        assert_eq!(decoded.key, cursor.key);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        let invalid = [
+            "".to_string(),
+            "not a cursor!".to_string(),
+            URL_SAFE_NO_PAD.encode("not json"),
+            URL_SAFE_NO_PAD.encode(r#"{"group":0,"key":"a"}"#),
+            URL_SAFE_NO_PAD.encode(r#"{"group":0,"key":"a","id":"nope"}"#),
+        ];
+        for cursor in invalid {
+            assert!(
+                matches!(Cursor::decode(&cursor), Err(Error::BadRequest(_))),
+                "{cursor:?}"
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn pages_are_stable_and_complete(db: PgPool) {
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('pages@example.com') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let root_id = sqlx::query_scalar!(
+            "SELECT id FROM folders WHERE user_id = $1 AND parent_id IS NULL",
+            user_id
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        for name in ["b", "A", "c"] {
+            sqlx::query!(
+                "INSERT INTO folders (user_id, parent_id, name) VALUES ($1, $2, $3)",
+                user_id,
+                root_id,
+                name
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        // names differing only in case and equal sizes leave the id to break ties
+        for (name, size) in [
+            ("a.txt", 10),
+            ("A.TXT", 10),
+            ("b.pdf", 10),
+            ("B.md", 20),
+            ("c", 0),
+        ] {
+            sqlx::query!(
+                "INSERT INTO files (user_id, folder_id, filename, size) VALUES ($1, $2, $3, $4)",
+                user_id,
+                root_id,
+                name,
+                size
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        for sort in ["name", "size", "modified", "type"] {
+            for order in ["asc", "desc"] {
+                for folders_first in [true, false] {
+                    let request = |cursor: Option<String>, limit: i64| {
+                        serde_json::from_value::<FileRequest>(serde_json::json!({
+                            "sort": sort,
+                            "order": order,
+                            "folders_first": folders_first,
+                            "limit": limit,
+                            "cursor": cursor,
+                        }))
+                        .unwrap()
+                    };
+                    let (all, next) = page(&db, user_id, root_id, &request(None, 100))
+                        .await
+                        .unwrap();
+                    assert_eq!(all.len(), 8);
+                    assert!(next.is_none());
+
+                    let mut paged = Vec::new();
+                    let mut cursor = None;
+                    loop {
+                        let (entries, next) = page(&db, user_id, root_id, &request(cursor, 3))
+                            .await
+                            .unwrap();
+                        assert!(entries.len() <= 3);
+                        paged.extend(entries.into_iter().map(|entry| entry.id));
+                        match next {
+                            Some(next) => cursor = Some(next),
+                            None => break,
+                        }
+                    }
+                    let all: Vec<_> = all.iter().map(|entry| entry.id).collect();
+                    assert_eq!(paged, all, "{sort} {order} folders_first={folders_first}");
+                }
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn invalid_cursor_is_a_bad_request(db: PgPool) {
+        let request: FileRequest =
+            serde_json::from_value(serde_json::json!({ "cursor": "garbage" })).unwrap();
+        let result = page(&db, Uuid::new_v4(), Uuid::new_v4(), &request).await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+}
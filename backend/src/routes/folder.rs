@@ -1,8 +1,17 @@
-use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use axum::{Json, extract::State, http::StatusCode};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::state::App;
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    jobs::{self, Job},
+    state::App,
+};
+
+use super::{Error, team::Role};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateFolderRequest {
@@ -14,60 +23,159 @@ pub struct CreateFolderRequest {
 pub struct FolderResponse {
     pub id: Uuid,
     pub name: String,
-    pub parent_id: Option<Uuid>,
+    pub parent_id: Uuid,
+    /// Total size of everything below the folder.
+    pub size: i64,
+    /// Number of files below the folder, at any depth.
+    pub file_count: i64,
+    /// Number of folders below the folder, at any depth.
+    pub folder_count: i64,
 }
 
 pub async fn create_folder(
     State(state): State<App>,
+    user: User,
+    client: Client,
     Json(input): Json<CreateFolderRequest>,
-) -> impl IntoResponse {
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(); // placeholder
+) -> Result<(StatusCode, Json<FolderResponse>), Error> {
+    user.require(Scope::Write)?;
+
+    let parent_id = user.folder_or_default(&state.db, input.parent_id).await?;
+    let drive = user.drive(&state.db, parent_id, Role::Editor).await?;
+    let folder = create(&state.db, drive, input.name, parent_id, false).await?;
+    let event = Event::folder("folder.create", folder.id)
+        .after(serde_json::json!({ "name": folder.name, "parent_id": folder.parent_id }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok((StatusCode::CREATED, Json(folder)))
+}
+
+/// Returns the id of the user's (or team's) root folder, the only folder without a parent.
+pub async fn root(db: &PgPool, drive_id: Uuid) -> Result<Uuid, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders WHERE user_id = $1 AND parent_id IS NULL
+        "#,
+        drive_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Checks that `folder_id` is one of the user's folders, defaulting to their root folder.
+pub async fn or_root(db: &PgPool, drive_id: Uuid, folder_id: Option<Uuid>) -> Result<Uuid, Error> {
+    let Some(folder_id) = folder_id else {
+        return root(db, drive_id).await;
+    };
+
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders WHERE id = $1 AND user_id = $2
+        "#,
+        folder_id,
+        drive_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Whether files in the folder are [end-to-end encrypted](super::vault).
+pub async fn is_vault(db: &PgPool, folder_id: Uuid) -> Result<bool, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT vault FROM folders WHERE id = $1
+        "#,
+        folder_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Vaults only hold files: names of subfolders would not be encrypted.
+pub async fn ensure_not_vault(db: &PgPool, folder_id: Uuid) -> Result<(), Error> {
+    if is_vault(db, folder_id).await? {
+        return Err(Error::BadRequest(
+            "Vaults cannot contain folders".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The root folder cannot be renamed, moved or deleted.
+pub async fn ensure_not_root(db: &PgPool, drive_id: Uuid, folder_id: Uuid) -> Result<(), Error> {
+    if root(db, drive_id).await? == folder_id {
+        return Err(Error::BadRequest(
+            "The root folder cannot be changed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Folder names are path components, so they can't hold a `/` or be `.` or `..`;
+/// see [`path::split`](super::path::split).
+pub fn ensure_valid_name(name: &str) -> Result<(), Error> {
+    if matches!(name, "" | "." | "..") || name.contains('/') {
+        return Err(Error::BadRequest(format!("Invalid folder name: {name:?}")));
+    }
+    Ok(())
+}
+
+/// Creates a folder named `name` in `parent_id` (`None` is the root folder), or
+/// a [vault](super::vault) for encrypted files.
+pub async fn create(
+    db: &PgPool,
+    drive_id: Uuid,
+    name: String,
+    parent_id: Option<Uuid>,
+    vault: bool,
+) -> Result<FolderResponse, Error> {
+    ensure_valid_name(&name)?;
+    let parent_id = or_root(db, drive_id, parent_id).await?;
+    ensure_not_vault(db, parent_id).await?;
 
     // Check for duplicate folder name in same parent
     let existing = sqlx::query_scalar!(
         r#"
         SELECT id FROM folders
-        WHERE user_id = $1 AND parent_id IS NOT DISTINCT FROM $2 AND name = $3
+        WHERE user_id = $1 AND parent_id = $2 AND name = $3
         "#,
-        user_id,
-        input.parent_id,
-        input.name
+        drive_id,
+        parent_id,
+        name
     )
-    .fetch_optional(&state.db)
-    .await
-    .unwrap();
+    .fetch_optional(db)
+    .await?;
 
     if existing.is_some() {
-        return (
-            StatusCode::CONFLICT,
-            "Folder already exists with that name".into_response(),
-        );
+        return Err(Error::Conflict("Folder already exists with that name"));
     }
 
     let id = Uuid::new_v4();
     sqlx::query!(
         r#"
-        INSERT INTO folders (id, user_id, name, parent_id)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO folders (id, user_id, name, parent_id, vault)
+        VALUES ($1, $2, $3, $4, $5)
         "#,
         id,
-        user_id,
-        input.name,
-        input.parent_id
-    )
-    .execute(&state.db)
-    .await
-    .unwrap();
-
-    (
-        StatusCode::CREATED,
-        Json(FolderResponse {
-            id,
-            name: input.name,
-            parent_id: input.parent_id,
-        })
-        .into_response(),
+        drive_id,
+        name,
+        parent_id,
+        vault
     )
+    .execute(db)
+    .await?;
+
+    Ok(FolderResponse {
+        id,
+        name,
+        parent_id,
+        size: 0,
+        file_count: 0,
+        folder_count: 0,
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -84,53 +192,49 @@ pub struct RenameFolderResponse {
 
 pub async fn rename_folder(
     State(state): State<App>,
+    user: User,
+    client: Client,
     Json(input): Json<RenameFolderRequest>,
-) -> impl IntoResponse {
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(); // placeholder
+) -> Result<Json<RenameFolderResponse>, Error> {
+    user.require(Scope::Write)?;
+    user.ensure_folder(&state.db, input.folder_id).await?;
+    let drive = user
+        .drive(&state.db, Some(input.folder_id), Role::Editor)
+        .await?;
+
+    let old_name = rename(&state.db, drive, input.folder_id, &input.new_name).await?;
+    let event = Event::folder("folder.rename", input.folder_id)
+        .before(serde_json::json!({ "name": old_name }))
+        .after(serde_json::json!({ "name": input.new_name }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok(Json(RenameFolderResponse {
+        id: input.folder_id,
+        name: input.new_name,
+    }))
+}
 
-    // Check for duplicate folder name in same parent
-    let existing = sqlx::query_scalar!(
+/// Renames a folder, returning its previous name.
+pub async fn rename(
+    db: &PgPool,
+    drive_id: Uuid,
+    folder_id: Uuid,
+    new_name: &str,
+) -> Result<String, Error> {
+    ensure_not_root(db, drive_id, folder_id).await?;
+    let parent_id = sqlx::query_scalar!(
         r#"
-        SELECT id FROM folders
-        WHERE user_id = $1 AND parent_id IS NOT DISTINCT FROM (SELECT parent_id FROM folders WHERE id = $2) AND name = $3
+        SELECT parent_id AS "parent_id!" FROM folders WHERE id = $1 AND user_id = $2
         "#,
-        user_id,
-        input.folder_id,
-        input.new_name
+        folder_id,
+        drive_id
     )
-    .fetch_optional(&state.db)
-    .await
-    .unwrap();
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
 
-    if existing.is_some() {
-        return (
-            StatusCode::CONFLICT,
-            "Folder already exists with that name".into_response(),
-        );
-    }
-
-    sqlx::query!(
-        r#"
-        UPDATE folders
-        SET name = $1
-        WHERE id = $2 AND user_id = $3
-        "#,
-        input.new_name,
-        input.folder_id,
-        user_id
-    )
-    .execute(&state.db)
-    .await
-    .unwrap();
-
-    (
-        StatusCode::OK,
-        Json(RenameFolderResponse {
-            id: input.folder_id,
-            name: input.new_name,
-        })
-        .into_response(),
-    )
+    let (_, old_name) = relocate(db, drive_id, folder_id, parent_id, new_name).await?;
+    Ok(old_name)
 }
 
 #[derive(Debug, Deserialize)]
@@ -142,70 +246,131 @@ pub struct MoveFolderRequest {
 #[derive(Debug, Serialize)]
 pub struct MoveFolderResponse {
     pub id: Uuid,
-    pub new_parent_id: Option<Uuid>,
+    pub new_parent_id: Uuid,
 }
 
 pub async fn move_folder(
     State(state): State<App>,
+    user: User,
+    client: Client,
     Json(input): Json<MoveFolderRequest>,
-) -> impl IntoResponse {
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(); // placeholder
+) -> Result<Json<MoveFolderResponse>, Error> {
+    user.require(Scope::Write)?;
+    user.ensure_folder(&state.db, input.folder_id).await?;
+    user.ensure_folder(&state.db, input.new_parent_id).await?;
+    // only within a drive; moving folders into a team drive is a transfer
+    let drive = user
+        .drive(&state.db, Some(input.folder_id), Role::Editor)
+        .await?;
+
+    let old_parent_id = move_to(&state.db, drive, input.folder_id, input.new_parent_id).await?;
+    let event = Event::folder("folder.move", input.folder_id)
+        .before(serde_json::json!({ "parent_id": old_parent_id }))
+        .after(serde_json::json!({ "parent_id": input.new_parent_id }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok(Json(MoveFolderResponse {
+        id: input.folder_id,
+        new_parent_id: input.new_parent_id,
+    }))
+}
 
-    // Check for duplicate folder name in new parent
-    let folder_name = sqlx::query_scalar!(
+/// Moves a folder into `new_parent_id`, returning its previous parent.
+pub async fn move_to(
+    db: &PgPool,
+    drive_id: Uuid,
+    folder_id: Uuid,
+    new_parent_id: Uuid,
+) -> Result<Uuid, Error> {
+    let name = sqlx::query_scalar!(
         r#"
         SELECT name FROM folders
         WHERE id = $1 AND user_id = $2
         "#,
-        input.folder_id,
-        user_id
+        folder_id,
+        drive_id
     )
-    .fetch_one(&state.db)
-    .await
-    .unwrap();
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
 
+    let (old_parent_id, _) = relocate(db, drive_id, folder_id, new_parent_id, &name).await?;
+    Ok(old_parent_id)
+}
+
+/// Moves a folder into `new_parent_id` under `new_name` at once, returning its
+/// previous parent and name.
+pub async fn relocate(
+    db: &PgPool,
+    drive_id: Uuid,
+    folder_id: Uuid,
+    new_parent_id: Uuid,
+    new_name: &str,
+) -> Result<(Uuid, String), Error> {
+    ensure_valid_name(new_name)?;
+    ensure_not_root(db, drive_id, folder_id).await?;
+    or_root(db, drive_id, Some(new_parent_id)).await?;
+    ensure_not_vault(db, new_parent_id).await?;
+
+    // a folder can't go into itself or anything below it, that would make a cycle
+    let inside = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM folders WHERE id = $1
+            UNION
+            SELECT f.id, f.parent_id FROM folders f
+            JOIN ancestors a ON f.id = a.parent_id
+        )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $2) AS "inside!"
+        "#,
+        new_parent_id,
+        folder_id
+    )
+    .fetch_one(db)
+    .await?;
+    if inside {
+        return Err(Error::BadRequest(
+            "A folder cannot be moved into itself".to_string(),
+        ));
+    }
+
+    // Check for duplicate folder name in new parent
     let existing = sqlx::query_scalar!(
         r#"
         SELECT id FROM folders
-        WHERE user_id = $1 AND parent_id IS NOT DISTINCT FROM $2 AND name = $3
+        WHERE user_id = $1 AND parent_id = $2 AND name = $3
         "#,
-        user_id,
-        input.new_parent_id,
-        folder_name
+        drive_id,
+        new_parent_id,
+        new_name
     )
-    .fetch_optional(&state.db)
-    .await
-    .unwrap();
+    .fetch_optional(db)
+    .await?;
 
     if existing.is_some() {
-        return (
-            StatusCode::CONFLICT,
-            "Folder already exists with that name".into_response(),
-        );
+        return Err(Error::Conflict("Folder already exists with that name"));
     }
 
-    sqlx::query!(
+    // one statement, so the folder is never left moved under its old name; joining
+    // the row to itself gives access to its values before the update
+    let old = sqlx::query!(
         r#"
-        UPDATE folders
-        SET parent_id = $1
-        WHERE id = $2 AND user_id = $3
+        UPDATE folders f
+        SET parent_id = $1, name = $2
+        FROM folders old
+        WHERE f.id = $3 AND f.user_id = $4 AND old.id = f.id
+        RETURNING old.parent_id AS "parent_id!", old.name
         "#,
-        input.new_parent_id,
-        input.folder_id,
-        user_id
-    )
-    .execute(&state.db)
-    .await
-    .unwrap();
-
-    (
-        StatusCode::OK,
-        Json(MoveFolderResponse {
-            id: input.folder_id,
-            new_parent_id: Some(input.new_parent_id),
-        })
-        .into_response(),
+        new_parent_id,
+        new_name,
+        folder_id,
+        drive_id
     )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok((old.parent_id, old.name))
 }
 
 #[derive(Debug, Deserialize)]
@@ -220,12 +385,54 @@ pub struct DeleteFolderResponse {
 
 pub async fn delete_folder(
     State(state): State<App>,
+    user: User,
+    client: Client,
     Json(input): Json<DeleteFolderRequest>,
-) -> impl IntoResponse {
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap(); // placeholder
+) -> Result<Json<DeleteFolderResponse>, Error> {
+    user.require(Scope::Write)?;
+    user.ensure_folder(&state.db, input.folder_id).await?;
+    let drive = user
+        .drive(&state.db, Some(input.folder_id), Role::Editor)
+        .await?;
+
+    let deleted = delete(&state.db, drive, input.folder_id).await?;
+    state.jobs.notify_one();
+    let event = Event::folder("folder.delete", input.folder_id).before(deleted);
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok(Json(DeleteFolderResponse {
+        id: input.folder_id,
+    }))
+}
+
+/// What [`delete`] removed.
+#[derive(Debug, Serialize)]
+pub struct DeletedFolder {
+    pub name: String,
+    pub parent_id: Option<Uuid>,
+    /// Subfolders, at any depth.
+    pub folder_ids: Vec<Uuid>,
+    /// Files in the folder and its subfolders.
+    pub file_ids: Vec<Uuid>,
+}
+
+/// Deletes a folder together with all of its subfolders and files.
+pub async fn delete(db: &PgPool, drive_id: Uuid, folder_id: Uuid) -> Result<DeletedFolder, Error> {
+    ensure_not_root(db, drive_id, folder_id).await?;
 
     // start transaction
-    let mut transaction = state.db.begin().await.unwrap();
+    let mut transaction = db.begin().await?;
+
+    let folder = sqlx::query!(
+        r#"
+        SELECT name, parent_id FROM folders WHERE id = $1 AND user_id = $2
+        "#,
+        folder_id,
+        drive_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(Error::NotFound)?;
 
     // get all subfolders
     let subfolders = sqlx::query_scalar!(
@@ -236,49 +443,214 @@ pub async fn delete_folder(
             SELECT f.id FROM folders f
             JOIN subfolders sf ON f.parent_id = sf.id
         )
-        SELECT id FROM subfolders
+        SELECT id AS "id!" FROM subfolders
         "#,
-        input.folder_id,
-        user_id
+        folder_id,
+        drive_id
     )
     .fetch_all(&mut *transaction)
-    .await
-    .unwrap();
+    .await?;
+
+    // delete all files in them first, deleting a folder would take its files along
+    let file_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM files WHERE folder_id = ANY($1) AND user_id = $2 RETURNING id
+        "#,
+        &subfolders,
+        drive_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
 
     // delete all subfolders (and the folder itself)
-    for folder_id in subfolders {
+    for &folder_id in &subfolders {
         sqlx::query!(
             r#"
             DELETE FROM folders WHERE id = $1 AND user_id = $2
             "#,
             folder_id,
-            user_id
+            drive_id
         )
         .execute(&mut *transaction)
+        .await?;
+    }
+
+    // their contents are removed in the background, once the rows are surely gone
+    if !file_ids.is_empty() {
+        let job = Job::RemoveBlobs {
+            user_id: drive_id,
+            file_ids: file_ids.clone(),
+        };
+        jobs::enqueue(&mut *transaction, &job).await?;
+    }
+
+    // commit transaction
+    transaction.commit().await?;
+
+    Ok(DeletedFolder {
+        name: folder.name,
+        parent_id: folder.parent_id,
+        folder_ids: subfolders
+            .into_iter()
+            .filter(|&id| id != folder_id)
+            .collect(),
+        file_ids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Version of the migration that gave every user a root folder.
+    const ROOT_FOLDERS: i64 = 20250520090000;
+
+    #[test]
+    fn rejects_names_that_are_not_path_components() {
+        for name in ["", ".", "..", "a/b", "/"] {
+            assert!(matches!(ensure_valid_name(name), Err(Error::BadRequest(_))));
+        }
+        for name in ["Projects", "...", ".config", "a b", "a\\b"] {
+            assert!(ensure_valid_name(name).is_ok());
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn root_folder_migration_renames_clashing_orphans(db: PgPool) {
+        // the schema as it was before root folders existed
+        let mut migrator = sqlx::migrate!("../migrations");
+        let before: Vec<_> = migrator
+            .migrations
+            .iter()
+            .filter(|migration| migration.version < ROOT_FOLDERS)
+            .cloned()
+            .collect();
+        migrator.migrations = before.into();
+        migrator.run(&db).await.unwrap();
+
+        let user_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (email) VALUES ('orphans@example.com') RETURNING id",
+        )
+        .fetch_one(&db)
         .await
         .unwrap();
+        sqlx::query("INSERT INTO folders (id, user_id, name) VALUES ($1, $2, 'root')")
+            .bind(Uuid::nil())
+            .bind(user_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        // two orphans left behind by deleting their folders, and one clashing with a
+        // file in the nil UUID folder
+        for (folder_id, filename) in [
+            (None, "notes.txt"),
+            (None, "notes.txt"),
+            (None, "report.pdf"),
+            (Some(Uuid::nil()), "report.pdf"),
+        ] {
+            sqlx::query(
+                "INSERT INTO files (user_id, folder_id, filename, size) VALUES ($1, $2, $3, 0)",
+            )
+            .bind(user_id)
+            .bind(folder_id)
+            .bind(filename)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        sqlx::migrate!("../migrations").run(&db).await.unwrap();
+
+        let root_id = root(&db, user_id).await.unwrap();
+        let mut filenames: Vec<String> =
+            sqlx::query_scalar("SELECT filename FROM files WHERE user_id = $1 AND folder_id = $2")
+                .bind(user_id)
+                .bind(root_id)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        filenames.sort();
+        assert_eq!(filenames.len(), 4);
+        assert_eq!(filenames[0], "notes.txt");
+        assert!(filenames[1].starts_with("notes.txt ("));
+        assert_eq!(filenames[2], "report.pdf");
+        assert!(filenames[3].starts_with("report.pdf ("));
+    }
 
-        // delete all files in the folder
+    #[sqlx::test(migrations = "../migrations")]
+    async fn deleting_users_and_teams_deletes_their_drives(db: PgPool) {
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('drive@example.com') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let team_id = sqlx::query_scalar!("INSERT INTO teams (name) VALUES ('Drive') RETURNING id")
+            .fetch_one(&db)
+            .await
+            .unwrap();
         sqlx::query!(
-            r#"
-            DELETE FROM files WHERE folder_id = $1 AND user_id = $2
-            "#,
-            folder_id,
+            "INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, 'owner')",
+            team_id,
             user_id
         )
-        .execute(&mut *transaction)
+        .execute(&db)
         .await
         .unwrap();
+        for drive_id in [user_id, team_id] {
+            let folder_id = create(&db, drive_id, "Photos".into(), None, false)
+                .await
+                .unwrap()
+                .id;
+            let file_id = sqlx::query_scalar!(
+                r#"
+                INSERT INTO files (user_id, folder_id, filename, size)
+                VALUES ($1, $2, 'beach.jpg', 0) RETURNING id
+                "#,
+                drive_id,
+                folder_id
+            )
+            .fetch_one(&db)
+            .await
+            .unwrap();
+            sqlx::query!(
+                "INSERT INTO photo_metadata (file_id, user_id, width, height) VALUES ($1, $2, 1, 1)",
+                file_id,
+                drive_id
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let rows = async |drive_id: Uuid| {
+            sqlx::query!(
+                r#"
+                SELECT (SELECT COUNT(*) FROM folders WHERE user_id = $1) AS "folders!",
+                       (SELECT COUNT(*) FROM files WHERE user_id = $1) AS "files!",
+                       (SELECT COUNT(*) FROM photo_metadata WHERE user_id = $1) AS "photos!"
+                "#,
+                drive_id
+            )
+            .fetch_one(&db)
+            .await
+            .map(|row| (row.folders, row.files, row.photos))
+            .unwrap()
+        };
+        assert_eq!(rows(user_id).await, (2, 1, 1));
+        assert_eq!(rows(team_id).await, (2, 1, 1));
+
+        // the team's drive outlives its members
+        sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(rows(user_id).await, (0, 0, 0));
+        assert_eq!(rows(team_id).await, (2, 1, 1));
+
+        sqlx::query!("DELETE FROM teams WHERE id = $1", team_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(rows(team_id).await, (0, 0, 0));
     }
-
-    // commit transaction
-    transaction.commit().await.unwrap();
-
-    (
-        StatusCode::OK,
-        Json(DeleteFolderResponse {
-            id: input.folder_id,
-        })
-        .into_response(),
-    )
 }
@@ -0,0 +1,115 @@
+//! Operational endpoints, served outside of `/api/v1`.
+
+use std::{path::Path, time::Duration};
+
+use axum::{
+    Json,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+
+use crate::state::App;
+
+use super::Error;
+
+/// How long a readiness check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `GET /healthz`: the process is alive and serving requests.
+pub async fn healthz() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// `GET /readyz`: the database and the upload directory can be used; 503 if not.
+pub async fn readyz(State(state): State<App>) -> impl IntoResponse {
+    let database = tokio::time::timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(&state.db))
+        .await
+        .map_err(|_| "timed out".to_string())
+        .and_then(|result| result.map(|_| ()).map_err(|err| err.to_string()));
+    let storage = tokio::time::timeout(CHECK_TIMEOUT, check_storage(Path::new(&state.upload_dir)))
+        .await
+        .map_err(|_| "timed out".to_string())
+        .and_then(|result| result.map_err(|err| err.to_string()));
+
+    let status = if database.is_ok() && storage.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let describe = |result: Result<(), String>| result.err().unwrap_or_else(|| "ok".to_string());
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if status == StatusCode::OK { "ok" } else { "failed" },
+            "database": describe(database),
+            "storage": describe(storage),
+        })),
+    )
+}
+
+/// Writes and removes a file, so a read-only or full disk is noticed too.
+async fn check_storage(upload_dir: &Path) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(upload_dir).await?;
+    let probe = upload_dir.join(".readyz");
+    tokio::fs::write(&probe, b"ok").await?;
+    tokio::fs::remove_file(&probe).await
+}
+
+/// `GET /metrics`: request, transfer, database pool, storage and job queue metrics
+/// in the Prometheus text format.
+pub async fn metrics(State(state): State<App>) -> Result<impl IntoResponse, Error> {
+    let storage = sqlx::query!(
+        r#"
+        SELECT count(*) AS "files!", COALESCE(sum(size), 0)::BIGINT AS "bytes!" FROM files
+        "#
+    )
+    .fetch_one(&state.db)
+    .await?;
+    let jobs = sqlx::query!(
+        r#"
+        SELECT count(*) FILTER (WHERE failed_at IS NULL) AS "pending!",
+               count(*) FILTER (WHERE failed_at IS NOT NULL) AS "failed!"
+        FROM jobs
+        "#
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    let pool_size = state.db.size();
+    let pool_idle = state.db.num_idle() as u32;
+    let body = state.metrics.render(&[
+        (
+            "db_pool_connections",
+            "Open database connections.",
+            pool_size as f64,
+        ),
+        (
+            "db_pool_idle_connections",
+            "Open database connections not in use.",
+            pool_idle as f64,
+        ),
+        (
+            "storage_files",
+            "Files stored, across all users.",
+            storage.files as f64,
+        ),
+        (
+            "storage_bytes",
+            "Size of all files, across all users.",
+            storage.bytes as f64,
+        ),
+        (
+            "jobs_pending",
+            "Background jobs waiting or running.",
+            jobs.pending as f64,
+        ),
+        (
+            "jobs_failed",
+            "Background jobs that ran out of attempts.",
+            jobs.failed as f64,
+        ),
+    ]);
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, hash_map::Entry};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+};
+
+use super::{Error, files::EntryKind, folder, path, team::Role};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct LargestQuery {
+    /// Only report files or only folders.
+    pub kind: Option<EntryKind>,
+    /// Only report what is inside this folder, at any depth.
+    pub folder_id: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct LargestItem {
+    /// `file` or `folder`.
+    pub kind: String,
+    pub id: Uuid,
+    pub name: String,
+    /// Absolute path, e.g. `/Videos/2024/holiday.mp4`.
+    #[sqlx(skip)]
+    pub path: String,
+    /// For folders the total size of everything below them.
+    pub size: i64,
+    /// Number of files below a folder, at any depth; `None` for files.
+    pub file_count: Option<i64>,
+    /// Number of folders below a folder, at any depth; `None` for files.
+    pub folder_count: Option<i64>,
+    #[serde(skip)]
+    folder_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LargestResponse {
+    /// Largest first. A folder's size includes the files listed separately.
+    pub items: Vec<LargestItem>,
+}
+
+/// `GET /largest?kind=file&limit=20`: the files and folders taking up the most space.
+///
+/// Folder sizes come from the totals maintained on every change, so this never
+/// walks the whole tree unless it is limited to a folder.
+pub async fn handler(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<LargestQuery>,
+) -> Result<Json<LargestResponse>, Error> {
+    user.require(Scope::Read)?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let folder_id = user.folder_or_default(&state.db, query.folder_id).await?;
+    let user_id = user.drive(&state.db, folder_id, Role::Viewer).await?;
+    let root = folder::root(&state.db, user_id).await?;
+    let scope = folder::or_root(&state.db, user_id, folder_id).await?;
+    // everything is inside the root folder, no need to find the subfolders
+    let scope = (scope != root).then_some(scope);
+
+    let mut sql = QueryBuilder::new("");
+    if let Some(scope) = scope {
+        sql.push(
+            "WITH RECURSIVE scope AS (\
+             SELECT id FROM folders WHERE id = ",
+        )
+        .push_bind(scope)
+        .push(
+            " UNION ALL \
+             SELECT f.id FROM folders f JOIN scope s ON f.parent_id = s.id) ",
+        );
+    }
+
+    sql.push(
+        "SELECT * FROM (\
+         SELECT 'folder' AS kind, id, name, parent_id AS folder_id, total_size AS size, \
+         file_count, folder_count \
+         FROM folders WHERE user_id = ",
+    )
+    .push_bind(user_id)
+    .push(" AND parent_id IS NOT NULL");
+    if scope.is_some() {
+        push_scope(&mut sql, "parent_id");
+    }
+    sql.push(
+        " UNION ALL \
+         SELECT 'file', id, filename, folder_id, size, NULL, NULL \
+         FROM files WHERE user_id = ",
+    )
+    .push_bind(user_id);
+    if scope.is_some() {
+        push_scope(&mut sql, "folder_id");
+    }
+    sql.push(") e WHERE TRUE");
+
+    match query.kind {
+        Some(EntryKind::File) => sql.push(" AND kind = 'file'"),
+        Some(EntryKind::Folder) => sql.push(" AND kind = 'folder'"),
+        None => &mut sql,
+    };
+    sql.push(" ORDER BY size DESC, id LIMIT ").push_bind(limit);
+
+    let mut items: Vec<LargestItem> = sql.build_query_as().fetch_all(&state.db).await?;
+
+    let mut folder_paths = HashMap::new();
+    for item in &mut items {
+        let folder_path = match folder_paths.entry(item.folder_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(path::folder_path(&state.db, user_id, item.folder_id).await?)
+            }
+        };
+        item.path = format!("{}/{}", folder_path.trim_end_matches('/'), item.name);
+    }
+
+    Ok(Json(LargestResponse { items }))
+}
+
+/// Limits `column` to the folders of the `scope` CTE.
+fn push_scope(sql: &mut QueryBuilder<'_, Postgres>, column: &str) {
+    sql.push(format!(" AND {column} IN (SELECT id FROM scope)"));
+}
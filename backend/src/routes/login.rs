@@ -0,0 +1,711 @@
+//! Logging in and out of browser sessions.
+//!
+//! Password logins start at `POST /auth/login`. Users with two-factor
+//! authentication get a challenge to send along with a code to
+//! `POST /auth/login/totp`; users who must use it but haven't set it up yet
+//! first get their secret from `POST /auth/login/totp/setup`.
+//!
+//! With single sign-on, `GET /auth/oidc/login` sends the browser to the identity
+//! provider, which sends it back to `GET /auth/oidc/callback`. Users are created
+//! on their first login and matched by the provider's subject from then on; to
+//! link an existing account, log in to it and start at `GET /auth/oidc/login?link=true`.
+//! Accounts without a password, e.g. added by an admin, are linked on their first
+//! login instead.
+//!
+//! With an LDAP directory, passwords are checked there first; users it doesn't
+//! know, or all users while it can't be reached, log in with local passwords.
+//! Directory users are created on their first login as well and matched by
+//! their entry's DN; an account with the same email is only taken over if it
+//! has no password.
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{self, Identity, Subject},
+    client::Client,
+    config::GroupMapping,
+    ldap::Login,
+    ratelimit::{Class, Key},
+    session,
+    state::App,
+    totp::{self, Enrollment},
+};
+
+use super::Error;
+
+/// How long a login may take at the identity provider.
+const LOGIN_TIMEOUT_SECS: f64 = 10.0 * 60.0;
+/// How long the second step of a password login may take.
+const CHALLENGE_TIMEOUT_SECS: f64 = 5.0 * 60.0;
+/// Wrong codes after which the password has to be entered again.
+const MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordLoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NextStep {
+    /// Logged in, the session cookie is set.
+    Done,
+    /// Send a code from the authenticator app or a recovery code.
+    Totp,
+    /// Set up an authenticator app, then send a code from it.
+    TotpSetup,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub next: NextStep,
+    /// Identifies the login in the next step.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+    /// After setting up TOTP; only shown this once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// `POST /auth/login`: checks the password and either logs in or asks for a
+/// second factor.
+pub async fn password_login(
+    State(state): State<App>,
+    client: Client,
+    Json(input): Json<PasswordLoginRequest>,
+) -> Result<Response, Error> {
+    // on top of the limit per IP, so one account can't be guessed from many
+    state
+        .limits
+        .check(Class::Auth, &[Key::login(&input.email)])
+        .map_err(Error::TooManyRequests)?;
+    let user_id = check_password(&state, &input.email, &input.password, &client)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+
+    let next = if totp::enabled(&state.db, user_id).await? {
+        NextStep::Totp
+    } else if totp::required(&state.db, &state.totp, user_id).await? {
+        NextStep::TotpSetup
+    } else {
+        let cookie =
+            start_session(&state, user_id, &client, json!({ "method": "password" })).await?;
+        return Ok(logged_in(cookie, None));
+    };
+
+    let kind = match next {
+        NextStep::TotpSetup => "enroll",
+        _ => "verify",
+    };
+    let challenge = create_challenge(&state.db, user_id, kind).await?;
+    Ok(Json(LoginResponse {
+        next,
+        challenge: Some(challenge),
+        recovery_codes: None,
+    })
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChallengeRequest {
+    pub challenge: String,
+}
+
+/// `POST /auth/login/totp/setup`: a new TOTP secret for a user who must set it
+/// up before logging in.
+pub async fn totp_setup(
+    State(state): State<App>,
+    Json(input): Json<ChallengeRequest>,
+) -> Result<Json<Enrollment>, Error> {
+    let challenge = find_challenge(&state.db, &input.challenge).await?;
+    if challenge.kind != "enroll" {
+        return Err(Error::Conflict(
+            "Two-factor authentication is already set up",
+        ));
+    }
+    Ok(Json(
+        totp::enroll(&state.db, &state.totp, challenge.user_id).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpLoginRequest {
+    pub challenge: String,
+    pub code: String,
+}
+
+/// `POST /auth/login/totp`: completes a password login with a code, or with the
+/// first code from a newly set up app.
+pub async fn totp_login(
+    State(state): State<App>,
+    client: Client,
+    Json(input): Json<TotpLoginRequest>,
+) -> Result<Response, Error> {
+    let challenge = find_challenge(&state.db, &input.challenge).await?;
+    let user_id = challenge.user_id;
+    state
+        .limits
+        .check(Class::Auth, &[Key::User(user_id)])
+        .map_err(Error::TooManyRequests)?;
+
+    let (factor, recovery_codes) = if challenge.kind == "enroll" {
+        match totp::confirm(&state.db, user_id, &input.code).await {
+            Ok(codes) => {
+                audit::record(
+                    &state.db,
+                    user_id,
+                    &client,
+                    Event::user("user.totp_enable", user_id),
+                )
+                .await?;
+                (totp::Factor::Totp, Some(codes))
+            }
+            Err(Error::Unauthorized) => {
+                failed_attempt(&state.db, &input.challenge).await?;
+                return Err(Error::Unauthorized);
+            }
+            Err(err) => return Err(err),
+        }
+    } else {
+        match totp::verify(&state.db, user_id, &input.code).await? {
+            Some(factor) => (factor, None),
+            None => {
+                failed_attempt(&state.db, &input.challenge).await?;
+                return Err(Error::Unauthorized);
+            }
+        }
+    };
+
+    delete_challenge(&state.db, &input.challenge).await?;
+    let details = json!({ "method": "password", "second_factor": factor });
+    let cookie = start_session(&state, user_id, &client, details).await?;
+    Ok(logged_in(cookie, recovery_codes))
+}
+
+struct Challenge {
+    user_id: Uuid,
+    kind: String,
+}
+
+async fn create_challenge(db: &sqlx::PgPool, user_id: Uuid, kind: &str) -> Result<String, Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM login_challenges WHERE created_at < now() - make_interval(secs => $1)
+        "#,
+        CHALLENGE_TIMEOUT_SECS
+    )
+    .execute(db)
+    .await?;
+
+    let token = auth::generate_secret();
+    let token_hash = auth::hash_token(&token);
+    sqlx::query!(
+        r#"
+        INSERT INTO login_challenges (token_hash, user_id, kind) VALUES ($1, $2, $3)
+        "#,
+        token_hash.as_slice(),
+        user_id,
+        kind
+    )
+    .execute(db)
+    .await?;
+    Ok(token)
+}
+
+/// A challenge that is neither expired nor out of attempts.
+async fn find_challenge(db: &sqlx::PgPool, token: &str) -> Result<Challenge, Error> {
+    let token_hash = auth::hash_token(token);
+    sqlx::query_as!(
+        Challenge,
+        r#"
+        SELECT user_id, kind FROM login_challenges
+        WHERE token_hash = $1 AND attempts < $2
+          AND created_at >= now() - make_interval(secs => $3)
+        "#,
+        token_hash.as_slice(),
+        MAX_ATTEMPTS,
+        CHALLENGE_TIMEOUT_SECS
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::Unauthorized)
+}
+
+async fn failed_attempt(db: &sqlx::PgPool, token: &str) -> Result<(), sqlx::Error> {
+    let token_hash = auth::hash_token(token);
+    sqlx::query!(
+        r#"
+        UPDATE login_challenges SET attempts = attempts + 1 WHERE token_hash = $1
+        "#,
+        token_hash.as_slice()
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+async fn delete_challenge(db: &sqlx::PgPool, token: &str) -> Result<(), sqlx::Error> {
+    let token_hash = auth::hash_token(token);
+    sqlx::query!(
+        "DELETE FROM login_challenges WHERE token_hash = $1",
+        token_hash.as_slice()
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Starts a session and records the login; returns the `Set-Cookie` value.
+async fn start_session(
+    state: &App,
+    user_id: Uuid,
+    client: &Client,
+    details: Value,
+) -> Result<HeaderValue, Error> {
+    let token = session::create(&state.db, &state.sessions, user_id, client).await?;
+    audit::record(
+        &state.db,
+        user_id,
+        client,
+        Event::user("user.login", user_id).after(details),
+    )
+    .await?;
+    Ok(session::cookie(&state.sessions, &token))
+}
+
+fn logged_in(cookie: HeaderValue, recovery_codes: Option<Vec<String>>) -> Response {
+    (
+        [(header::SET_COOKIE, cookie)],
+        Json(LoginResponse {
+            next: NextStep::Done,
+            challenge: None,
+            recovery_codes,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    /// Path to return to once logged in, e.g. `/photos`.
+    pub return_to: Option<String>,
+    /// Link the identity to the account of the session, see [`link`].
+    #[serde(default)]
+    pub link: bool,
+}
+
+/// `GET /auth/oidc/login`: redirects to the identity provider.
+pub async fn oidc_login(
+    State(state): State<App>,
+    headers: HeaderMap,
+    Query(query): Query<LoginQuery>,
+) -> Result<Response, Error> {
+    let oidc = state.oidc.as_ref().ok_or(Error::NotFound)?;
+
+    let link_user_id = if query.link {
+        let token = session::token(&headers).ok_or(Error::Unauthorized)?;
+        let user_id = session::authenticate(&state.db, token).await?;
+        Some(user_id.ok_or(Error::Unauthorized)?)
+    } else {
+        None
+    };
+
+    // only paths on this server, `//host` would leave it
+    let return_to = query
+        .return_to
+        .filter(|path| path.starts_with('/') && !path.starts_with("//") && !path.contains('\\'))
+        .unwrap_or_else(|| "/".to_string());
+
+    let pending = oidc.authorize().await.map_err(|err| {
+        tracing::error!("Cannot reach the identity provider: {err}");
+        Error::BadGateway
+    })?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM oidc_logins WHERE created_at < now() - make_interval(secs => $1)
+        "#,
+        LOGIN_TIMEOUT_SECS
+    )
+    .execute(&state.db)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO oidc_logins (state, pkce_verifier, nonce, return_to, link_user_id)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        pending.state,
+        pending.pkce_verifier,
+        pending.nonce,
+        return_to,
+        link_user_id
+    )
+    .execute(&state.db)
+    .await?;
+
+    // only the browser that started the login may complete it, or an attacker
+    // could log a victim into the attacker's account with their own callback URL
+    let cookie = session::login_cookie(&state.sessions, &pending.state, LOGIN_TIMEOUT_SECS as u64);
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&pending.url)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub state: Option<String>,
+    pub code: Option<String>,
+    /// Set instead of `code` if the provider turned the login down.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// `GET /auth/oidc/callback`: completes the login, starts a session and returns
+/// to where the login started.
+pub async fn oidc_callback(
+    State(state): State<App>,
+    client: Client,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, Error> {
+    let oidc = state.oidc.as_ref().ok_or(Error::NotFound)?;
+
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or_default();
+        tracing::warn!("Identity provider refused login: {error} {description}");
+        return Err(Error::BadRequest(format!("Login failed: {error}")));
+    }
+    let (Some(login_state), Some(code)) = (query.state, query.code) else {
+        return Err(Error::BadRequest("Missing state or code".to_string()));
+    };
+    if session::login_state(&headers) != Some(login_state.as_str()) {
+        tracing::warn!("Single sign-on callback from a browser that didn't start the login");
+        return Err(Error::BadRequest(
+            "Login was started elsewhere, please try again".to_string(),
+        ));
+    }
+
+    // a login can only be completed once
+    let login = sqlx::query!(
+        r#"
+        DELETE FROM oidc_logins WHERE state = $1
+        RETURNING pkce_verifier, nonce, return_to, link_user_id,
+                  created_at >= now() - make_interval(secs => $2) AS "fresh!"
+        "#,
+        login_state,
+        LOGIN_TIMEOUT_SECS
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .filter(|login| login.fresh)
+    .ok_or_else(|| Error::BadRequest("Login expired, please try again".to_string()))?;
+
+    let identity = oidc
+        .exchange(code, login.pkce_verifier, login.nonce)
+        .await
+        .map_err(|err| {
+            tracing::warn!("Login with the identity provider failed: {err}");
+            Error::BadGateway
+        })?;
+
+    let groups = &oidc.config().groups;
+    if !allowed(&identity, groups) {
+        return Err(Error::Forbidden);
+    }
+
+    if let Some(user_id) = login.link_user_id {
+        link(&state, user_id, &identity, &client).await?;
+    }
+    let user_id = provision(&state, &identity, groups, &client).await?;
+    let cookie = start_session(&state, user_id, &client, json!({ "method": "oidc" })).await?;
+
+    Ok((
+        AppendHeaders([
+            (header::SET_COOKIE, cookie),
+            (
+                header::SET_COOKIE,
+                session::clear_login_cookie(&state.sessions),
+            ),
+        ]),
+        Redirect::to(&login.return_to),
+    )
+        .into_response())
+}
+
+/// The user with `login` as email, or as known to the LDAP directory, if
+/// `password` is theirs. Directory users are provisioned like with single
+/// sign-on; those not in an allowed group are turned away.
+pub async fn check_password(
+    state: &App,
+    login: &str,
+    password: &str,
+    client: &Client,
+) -> Result<Option<Uuid>, Error> {
+    let login = login.trim();
+    if let Some(ldap) = &state.ldap {
+        match ldap.authenticate(login, password).await {
+            Ok(Login::Success(identity)) => {
+                let groups = &ldap.config().groups;
+                if !allowed(&identity, groups) {
+                    return Err(Error::Forbidden);
+                }
+                return Ok(Some(provision(state, &identity, groups, client).await?));
+            }
+            Ok(Login::WrongPassword) => return Ok(None),
+            Ok(Login::UnknownUser) => {}
+            Err(err) => tracing::error!("Cannot check the password of {login} in LDAP: {err}"),
+        }
+    }
+
+    let user = sqlx::query!(
+        r#"
+        SELECT id, password_hash FROM users
+        WHERE lower(email) = lower($1) AND disabled_at IS NULL
+        "#,
+        login
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    Ok(user.and_then(|user| {
+        let hash = user.password_hash?;
+        auth::verify_password(password, &hash).then_some(user.id)
+    }))
+}
+
+/// Whether the identity is in a group allowed to log in, if that is restricted.
+fn allowed(identity: &Identity, groups: &GroupMapping) -> bool {
+    let allowed = groups.user_groups.is_empty() || identity.in_any(&groups.user_groups);
+    if !allowed {
+        tracing::info!("{} is not in a group allowed to log in", identity.email);
+    }
+    allowed
+}
+
+/// Links the identity to `user_id`, who started the login from their session.
+/// This is the only way an identity gets an account that existed before.
+async fn link(
+    state: &App,
+    user_id: Uuid,
+    identity: &Identity,
+    client: &Client,
+) -> Result<(), Error> {
+    let Subject::Oidc { issuer, subject } = &identity.subject else {
+        return Err(Error::BadRequest("Nothing to link".to_string()));
+    };
+    let mut tx = state.db.begin().await?;
+
+    let owner = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users WHERE oidc_issuer = $1 AND oidc_subject = $2
+        "#,
+        issuer,
+        subject
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if owner.is_some_and(|owner| owner != user_id) {
+        return Err(Error::Conflict("The identity is linked to another account"));
+    }
+
+    let linked = sqlx::query!(
+        r#"
+        UPDATE users SET oidc_issuer = $2, oidc_subject = $3
+        WHERE id = $1 AND (oidc_subject IS NULL OR (oidc_issuer = $2 AND oidc_subject = $3))
+        "#,
+        user_id,
+        issuer,
+        subject
+    )
+    .execute(&mut *tx)
+    .await?;
+    if linked.rows_affected() == 0 {
+        return Err(Error::Conflict("The account is linked to another identity"));
+    }
+    audit::record(
+        &mut *tx,
+        user_id,
+        client,
+        Event::user("user.link", user_id).after(json!({ "issuer": issuer, "subject": subject })),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The user with the identity's subject, created if there is none yet. Their name
+/// and groups are updated on every login and, with admin groups configured, so
+/// is `is_admin`. Disabled users are turned away.
+async fn provision(
+    state: &App,
+    identity: &Identity,
+    groups: &GroupMapping,
+    client: &Client,
+) -> Result<Uuid, Error> {
+    let mut tx = state.db.begin().await?;
+
+    let (issuer, subject, dn) = match &identity.subject {
+        Subject::Oidc { issuer, subject } => (Some(issuer), Some(subject), None),
+        Subject::Ldap { dn } => (None, None, Some(dn)),
+    };
+
+    // Accounts an admin added for single sign-on or the directory, or from before
+    // subjects were stored, have no password and are taken over by the first login
+    // with their (verified) email; accounts that do have one stay out of reach.
+    let adopted = sqlx::query!(
+        r#"
+        UPDATE users SET oidc_issuer = $2, oidc_subject = $3, ldap_dn = $4
+        WHERE lower(email) = $1 AND password_hash IS NULL
+          AND ldap_dn IS NULL AND oidc_subject IS NULL
+          AND NOT EXISTS (
+              SELECT 1 FROM users WHERE (oidc_issuer = $2 AND oidc_subject = $3) OR ldap_dn = $4
+          )
+        "#,
+        identity.email,
+        issuer,
+        subject,
+        dn
+    )
+    .execute(&mut *tx)
+    .await?;
+    if adopted.rows_affected() > 0 {
+        tracing::info!("Linked {} to {:?}", identity.email, identity.subject);
+    }
+
+    let existing = match &identity.subject {
+        Subject::Oidc { issuer, subject } => sqlx::query!(
+            r#"
+            UPDATE users SET name = COALESCE($3, name), groups = $4
+            WHERE oidc_issuer = $1 AND oidc_subject = $2
+            RETURNING id, is_admin, disabled_at IS NOT NULL AS "disabled!"
+            "#,
+            issuer,
+            subject,
+            identity.name,
+            &identity.groups
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|user| (user.id, user.is_admin, user.disabled)),
+        Subject::Ldap { dn } => sqlx::query!(
+            r#"
+            UPDATE users SET name = COALESCE($2, name), groups = $3
+            WHERE ldap_dn = $1
+            RETURNING id, is_admin, disabled_at IS NOT NULL AS "disabled!"
+            "#,
+            dn,
+            identity.name,
+            &identity.groups
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|user| (user.id, user.is_admin, user.disabled)),
+    };
+    let (user_id, is_admin) = match existing {
+        Some((_, _, true)) => {
+            tracing::info!("{} is disabled", identity.email);
+            return Err(Error::Forbidden);
+        }
+        Some((user_id, is_admin, false)) => (user_id, is_admin),
+        None => {
+            // whoever the provider lets use an address doesn't get a password account with it
+            let taken = sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = $1) AS "taken!"
+                "#,
+                identity.email
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if taken {
+                tracing::info!("{} has an account, which isn't linked", identity.email);
+                return Err(Error::Conflict(
+                    "An account with this email exists and isn't linked",
+                ));
+            }
+
+            let user = sqlx::query!(
+                r#"
+                INSERT INTO users (email, name, groups, oidc_issuer, oidc_subject, ldap_dn)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING id, is_admin
+                "#,
+                identity.email,
+                identity.name,
+                &identity.groups,
+                issuer,
+                subject,
+                dn
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            tracing::info!("Created user {} on first login", identity.email);
+            audit::record(
+                &mut *tx,
+                user.id,
+                client,
+                Event::user("user.create", user.id)
+                    .after(json!({ "email": identity.email, "name": identity.name })),
+            )
+            .await?;
+            (user.id, user.is_admin)
+        }
+    };
+
+    let admin_groups = &groups.admin_groups;
+    let should_be_admin = identity.in_any(admin_groups);
+    if !admin_groups.is_empty() && should_be_admin != is_admin {
+        sqlx::query!(
+            r#"
+            UPDATE users SET is_admin = $2 WHERE id = $1
+            "#,
+            user_id,
+            should_be_admin
+        )
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut *tx,
+            user_id,
+            client,
+            Event::user("user.update", user_id)
+                .before(json!({ "is_admin": is_admin }))
+                .after(json!({ "is_admin": should_be_admin })),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+/// `POST /auth/logout`: ends the session and, with single sign-on, redirects to
+/// log out at the identity provider as well.
+pub async fn logout(State(state): State<App>, headers: HeaderMap) -> Result<Response, Error> {
+    if let Some(token) = session::token(&headers) {
+        session::delete(&state.db, token).await?;
+    }
+
+    let provider_logout = match &state.oidc {
+        Some(oidc) => oidc.logout_url().await.unwrap_or_else(|err| {
+            // logged out here at least
+            tracing::warn!("Cannot log out at the identity provider: {err}");
+            None
+        }),
+        None => None,
+    };
+
+    Ok((
+        [(header::SET_COOKIE, session::clear_cookie(&state.sessions))],
+        Redirect::to(provider_logout.as_deref().unwrap_or("/")),
+    )
+        .into_response())
+}
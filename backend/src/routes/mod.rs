@@ -1,12 +1,217 @@
-use axum::Router;
+use axum::{
+    Json, Router,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
 
 use crate::state::App;
 
+pub mod account;
+pub mod admin;
 pub mod download;
 pub mod files;
 pub mod folder;
+pub mod health;
+pub mod largest;
+pub mod login;
+pub mod path;
+pub mod photos;
+pub mod quota;
+pub mod search;
+pub mod stream;
+pub mod team;
+pub mod thumbnail;
 pub mod upload;
+pub mod vault;
 
 pub fn router() -> Router<App> {
     Router::new()
+        .route("/upload", post(upload::handler))
+        .route("/download/{id}", get(download::handler))
+        .route("/files", post(files::get_handler))
+        .route("/files/{id}", delete(files::delete_handler))
+        .route("/files/{id}/thumbnail", get(thumbnail::handler))
+        .route("/files/{id}/hls/{*path}", get(stream::hls_handler))
+        .route("/files/{id}/poster", get(stream::poster_handler))
+        .route(
+            "/folder",
+            post(folder::create_folder)
+                .delete(folder::delete_folder)
+                .put(folder::rename_folder)
+                .patch(folder::move_folder),
+        )
+        .route("/resolve", get(path::resolve_handler))
+        .route("/path/{id}", get(path::path_handler))
+        .route("/search", get(search::handler))
+        .route("/photos", get(photos::timeline_handler))
+        .route("/largest", get(largest::handler))
+        .route("/account/password", put(account::set_password))
+        .route(
+            "/account/ssh-keys",
+            get(account::list_ssh_keys).post(account::add_ssh_key),
+        )
+        .route("/account/ssh-keys/{id}", delete(account::delete_ssh_key))
+        .route(
+            "/account/tokens",
+            get(account::list_tokens).post(account::create_token),
+        )
+        .route("/account/tokens/{id}", delete(account::revoke_token))
+        .route(
+            "/account/totp",
+            get(account::totp_status)
+                .post(account::enroll_totp)
+                .delete(account::disable_totp),
+        )
+        .route("/account/totp/confirm", post(account::confirm_totp))
+        .route(
+            "/account/totp/recovery-codes",
+            post(account::regenerate_recovery_codes),
+        )
+        .route("/account/usage", get(quota::usage_handler))
+        .route(
+            "/account/vault-key",
+            get(vault::get_key_pair).put(vault::set_key_pair),
+        )
+        .route("/users/vault-key", get(vault::get_public_key))
+        .route("/vaults", get(vault::list).post(vault::create))
+        .route("/vaults/{id}", get(vault::get))
+        .route(
+            "/vaults/{id}/members",
+            get(vault::list_members).post(vault::add_member),
+        )
+        .route(
+            "/vaults/{id}/members/{user_id}",
+            delete(vault::remove_member),
+        )
+        .route("/vaults/{id}/files", get(vault::list_files))
+        .route("/vaults/{id}/files/{file_id}", get(vault::download))
+        .route("/teams", get(team::list).post(team::create))
+        .route("/teams/{id}", delete(team::delete))
+        .route(
+            "/teams/{id}/members",
+            get(team::list_members).post(team::add_member),
+        )
+        .route(
+            "/teams/{id}/members/{user_id}",
+            put(team::set_role).delete(team::remove_member),
+        )
+        .route("/teams/{id}/transfer", post(team::transfer))
+        .route("/admin/audit", get(admin::audit))
+        .route(
+            "/admin/users",
+            get(admin::list_users).post(admin::create_user),
+        )
+        .route("/admin/users/{id}", delete(admin::delete_user))
+        .route("/admin/users/{id}/disabled", put(admin::set_disabled))
+        .route("/admin/users/{id}/quota", put(admin::set_quota))
+        .route("/admin/users/{id}/totp", delete(admin::reset_totp))
+        .route("/admin/users/{id}/totp-policy", put(admin::set_totp_policy))
+        .route("/admin/sessions", get(admin::list_sessions))
+        .route("/admin/sessions/{id}", delete(admin::delete_session))
+        .route("/admin/shares", get(admin::list_shares))
+        .route("/admin/jobs", get(admin::list_jobs).post(admin::start_job))
+        .route("/auth/login", post(login::password_login))
+        .route("/auth/login/totp", post(login::totp_login))
+        .route("/auth/login/totp/setup", post(login::totp_setup))
+        .route("/auth/oidc/login", get(login::oidc_login))
+        .route("/auth/oidc/callback", get(login::oidc_callback))
+        .route("/auth/logout", post(login::logout))
+}
+
+/// Errors shared by the HTTP handlers and the other frontends (e.g. SFTP)
+/// that reuse their logic.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    /// Missing or invalid credentials, e.g. an expired API token.
+    Unauthorized,
+    /// Not allowed for this user or API token.
+    Forbidden,
+    Conflict(&'static str),
+    BadRequest(String),
+    UnsupportedMediaType(String),
+    QuotaExceeded,
+    /// Out of requests, try again after the given time; see [`ratelimit`](crate::ratelimit).
+    TooManyRequests(std::time::Duration),
+    /// The identity provider failed or could not be reached.
+    BadGateway,
+    /// The named upload does not match the checksum the client sent along.
+    ChecksumMismatch(String),
+    Io(std::io::Error),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Error::Unauthorized => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    Json(serde_json::json!({ "status": "failed", "message": "Unauthorized" })),
+                )
+                    .into_response();
+            }
+            Error::TooManyRequests(wait) => {
+                // whole seconds, rounded up so a retry doesn't come too early
+                let seconds = (wait.as_secs_f64().ceil() as u64).max(1);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, seconds.to_string())],
+                    Json(serde_json::json!({ "status": "failed", "message": "Too many requests" })),
+                )
+                    .into_response();
+            }
+            Error::NotFound => (StatusCode::NOT_FOUND, "Not found".to_string()),
+            Error::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            Error::Conflict(message) => (StatusCode::CONFLICT, message.to_string()),
+            Error::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            Error::UnsupportedMediaType(message) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, message),
+            Error::QuotaExceeded => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Storage quota exceeded".to_string(),
+            ),
+            Error::BadGateway => (
+                StatusCode::BAD_GATEWAY,
+                "Identity provider unavailable".to_string(),
+            ),
+            Error::ChecksumMismatch(filename) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Checksum mismatch for {filename}"),
+            ),
+            Error::Io(err) => {
+                tracing::error!("I/O error: {err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+            Error::Database(err) => {
+                tracing::error!("Database error: {err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+
+        (
+            status,
+            Json(serde_json::json!({ "status": "failed", "message": message })),
+        )
+            .into_response()
+    }
 }
@@ -0,0 +1,403 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+};
+
+use super::{Error, team::Role};
+
+/// What a path points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    Folder(Uuid),
+    File(Uuid),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Folder,
+    File,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Breadcrumb {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathResponse {
+    pub kind: NodeKind,
+    pub id: Uuid,
+    pub path: String,
+    /// The folders below the root down to the item, including it if it is a folder.
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveQuery {
+    pub path: String,
+    /// Resolves the path in this team's drive instead of the user's own.
+    pub team_id: Option<Uuid>,
+}
+
+/// `GET /resolve?path=/Projects/2025/report.pdf[&team_id=...]`
+pub async fn resolve_handler(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<ResolveQuery>,
+) -> Result<Json<PathResponse>, Error> {
+    user.require(Scope::Read)?;
+    let drive = user
+        .team_drive(&state.db, query.team_id, Role::Viewer)
+        .await?;
+
+    let node = resolve(&state.db, drive, &split(&query.path)).await?;
+    ensure_node(&state.db, &user, node).await?;
+    Ok(Json(describe(&state.db, drive, node).await?))
+}
+
+/// `GET /path/{id}` for either a file or a folder id.
+pub async fn path_handler(
+    State(state): State<App>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PathResponse>, Error> {
+    user.require(Scope::Read)?;
+
+    let is_folder = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .is_some();
+
+    // paths in a team drive start at its root
+    let (node, drive) = if is_folder {
+        let drive = user.drive(&state.db, Some(id), Role::Viewer).await?;
+        (Node::Folder(id), drive)
+    } else {
+        let drive = user.file_drive(&state.db, id, Role::Viewer).await?;
+        (Node::File(id), drive)
+    };
+    ensure_node(&state.db, &user, node).await?;
+    Ok(Json(describe(&state.db, drive, node).await?))
+}
+
+async fn ensure_node(db: &PgPool, user: &User, node: Node) -> Result<(), Error> {
+    match node {
+        Node::Folder(folder_id) => user.ensure_folder(db, folder_id).await,
+        Node::File(file_id) => user.ensure_file(db, file_id).await,
+    }
+}
+
+/// Splits a path into its components, resolving `.` and `..`.
+pub fn split(path: &str) -> Vec<String> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name.to_string()),
+        }
+    }
+    components
+}
+
+/// Resolves path components (as returned by [`split`]) to a folder or file.
+///
+/// The whole folder chain is walked in a single recursive query, so the cost
+/// does not grow with the number of round trips for deep trees.
+pub async fn resolve(db: &PgPool, drive_id: Uuid, components: &[String]) -> Result<Node, Error> {
+    let deepest = sqlx::query!(
+        r#"
+        WITH RECURSIVE walk AS (
+            SELECT id, 0 AS depth FROM folders
+            WHERE user_id = $1 AND parent_id IS NULL
+            UNION ALL
+            SELECT f.id, w.depth + 1 FROM folders f
+            JOIN walk w ON f.parent_id = w.id
+            WHERE f.user_id = $1 AND f.name = ($2::text[])[w.depth + 1]
+        )
+        SELECT id AS "id!", depth AS "depth!" FROM walk ORDER BY depth DESC LIMIT 1
+        "#,
+        drive_id,
+        components
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let depth = deepest.depth as usize;
+    if depth == components.len() {
+        return Ok(Node::Folder(deepest.id));
+    }
+    if depth + 1 < components.len() {
+        return Err(Error::NotFound);
+    }
+
+    // everything but the last component is a folder, so the last one may be a file
+    let last = &components[depth];
+    let file_id = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM files
+        WHERE user_id = $1 AND folder_id = $2 AND filename = $3
+        "#,
+        drive_id,
+        deepest.id,
+        last
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Node::File(file_id))
+}
+
+/// Returns the folders below the root down to (and including) `folder_id`.
+pub async fn breadcrumbs(
+    db: &PgPool,
+    drive_id: Uuid,
+    folder_id: Uuid,
+) -> Result<Vec<Breadcrumb>, Error> {
+    let mut ancestors = sqlx::query!(
+        r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, name, parent_id, 0 AS depth FROM folders WHERE id = $1 AND user_id = $2
+            UNION ALL
+            SELECT f.id, f.name, f.parent_id, a.depth + 1 FROM folders f
+            JOIN ancestors a ON f.id = a.parent_id
+        ) CYCLE id SET is_cycle USING visited
+        SELECT id AS "id!", name AS "name!", parent_id FROM ancestors
+        WHERE NOT is_cycle
+        ORDER BY depth DESC
+        "#,
+        folder_id,
+        drive_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    if ancestors.is_empty() {
+        return Err(Error::NotFound);
+    }
+    // the root folder has no name of its own
+    ancestors.retain(|row| row.parent_id.is_some());
+
+    Ok(ancestors
+        .into_iter()
+        .map(|row| Breadcrumb {
+            id: row.id,
+            name: row.name,
+        })
+        .collect())
+}
+
+/// Returns the absolute path of a folder, e.g. `/Projects/2025` (`/` for the root folder).
+pub async fn folder_path(db: &PgPool, drive_id: Uuid, folder_id: Uuid) -> Result<String, Error> {
+    Ok(join(&breadcrumbs(db, drive_id, folder_id).await?))
+}
+
+async fn describe(db: &PgPool, drive_id: Uuid, node: Node) -> Result<PathResponse, Error> {
+    match node {
+        Node::Folder(id) => {
+            let breadcrumbs = breadcrumbs(db, drive_id, id).await?;
+            Ok(PathResponse {
+                kind: NodeKind::Folder,
+                id,
+                path: join(&breadcrumbs),
+                breadcrumbs,
+            })
+        }
+        Node::File(id) => {
+            let file = sqlx::query!(
+                r#"
+                SELECT filename, folder_id FROM files WHERE id = $1 AND user_id = $2
+                "#,
+                id,
+                drive_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+            let breadcrumbs = breadcrumbs(db, drive_id, file.folder_id).await?;
+            let folder = join(&breadcrumbs);
+            Ok(PathResponse {
+                kind: NodeKind::File,
+                id,
+                path: format!("{}/{}", folder.trim_end_matches('/'), file.filename),
+                breadcrumbs,
+            })
+        }
+    }
+}
+
+fn join(breadcrumbs: &[Breadcrumb]) -> String {
+    let names: Vec<_> = breadcrumbs.iter().map(|b| b.name.as_str()).collect();
+    format!("/{}", names.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_into_components() {
+        assert_eq!(
+            split("/Projects/2025/report.pdf"),
+            ["Projects", "2025", "report.pdf"]
+        );
+        assert_eq!(split("Projects//2025/"), ["Projects", "2025"]);
+        assert!(split("/").is_empty());
+        assert!(split("").is_empty());
+    }
+
+    #[test]
+    fn resolves_dots() {
+        assert_eq!(split("/Projects/./2025/."), ["Projects", "2025"]);
+        assert_eq!(split("/Projects/2025/../2024"), ["Projects", "2024"]);
+        assert_eq!(split("/Projects/2025/../.."), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stays_below_the_root() {
+        assert_eq!(split("/../Projects"), ["Projects"]);
+        assert_eq!(split("../../etc/passwd"), ["etc", "passwd"]);
+        assert_eq!(split("/Projects/../../.."), Vec::<String>::new());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn resolves_folders_and_a_final_file(db: PgPool) {
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('path@example.com') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let root_id = sqlx::query_scalar!(
+            "SELECT id FROM folders WHERE user_id = $1 AND parent_id IS NULL",
+            user_id
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let folder_id = sqlx::query_scalar!(
+            "INSERT INTO folders (user_id, parent_id, name) VALUES ($1, $2, 'Projects') RETURNING id",
+            user_id,
+            root_id
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let file_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO files (user_id, folder_id, filename, size)
+            VALUES ($1, $2, 'report.pdf', 0) RETURNING id
+            "#,
+            user_id,
+            folder_id
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        let resolve = async |path: &str| resolve(&db, user_id, &split(path)).await;
+        assert_eq!(resolve("/").await.unwrap(), Node::Folder(root_id));
+        assert_eq!(resolve("/Projects").await.unwrap(), Node::Folder(folder_id));
+        assert_eq!(
+            resolve("/Projects/report.pdf").await.unwrap(),
+            Node::File(file_id)
+        );
+        assert_eq!(
+            resolve("/../Projects/./report.pdf").await.unwrap(),
+            Node::File(file_id)
+        );
+        // files have nothing below them, and missing folders hide what is below
+        assert!(matches!(
+            resolve("/Projects/report.pdf/more").await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            resolve("/Missing/report.pdf").await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(resolve("/report.pdf").await, Err(Error::NotFound)));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn resolves_in_team_drives_of_members_only(db: PgPool) {
+        let member = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('member@example.com') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let outsider = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('outsider@example.com') RETURNING id"
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let team_id =
+            sqlx::query_scalar!("INSERT INTO teams (name) VALUES ('Design') RETURNING id")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        sqlx::query!(
+            "INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, 'viewer')",
+            team_id,
+            member
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let team_root = sqlx::query_scalar!(
+            "SELECT id FROM folders WHERE user_id = $1 AND parent_id IS NULL",
+            team_id
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        let member = User {
+            id: member,
+            token: None,
+        };
+        let drive = member
+            .team_drive(&db, Some(team_id), Role::Viewer)
+            .await
+            .unwrap();
+        assert_eq!(drive, team_id);
+        assert_eq!(
+            resolve(&db, drive, &split("/")).await.unwrap(),
+            Node::Folder(team_root)
+        );
+        assert!(matches!(
+            member.team_drive(&db, Some(team_id), Role::Editor).await,
+            Err(Error::Forbidden)
+        ));
+
+        let outsider = User {
+            id: outsider,
+            token: None,
+        };
+        assert!(matches!(
+            outsider.team_drive(&db, Some(team_id), Role::Viewer).await,
+            Err(Error::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+};
+
+use super::Error;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct TimelineQuery {
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Photo {
+    pub id: Uuid,
+    pub name: String,
+    pub folder_id: Uuid,
+    /// Capture time from EXIF, `None` if the photo has none.
+    pub taken_at: Option<NaiveDateTime>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub width: i32,
+    pub height: i32,
+    pub orientation: Option<i16>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub photos: Vec<Photo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineResponse {
+    /// Newest first. A day can continue on the next page.
+    pub days: Vec<Day>,
+    /// Pass as `cursor` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last photo of a page.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    at: NaiveDateTime,
+    id: Uuid,
+}
+
+/// `GET /photos`: all of the user's photos across folders, grouped by capture date.
+///
+/// Photos without a capture time are placed at their upload time.
+pub async fn timeline_handler(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<TimelineQuery>,
+) -> Result<Json<TimelineResponse>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            URL_SAFE_NO_PAD
+                .decode(cursor)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+                .ok_or_else(|| Error::BadRequest("Invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let mut rows = sqlx::query!(
+        r#"
+        SELECT f.id, f.filename, f.folder_id,
+               COALESCE(p.taken_at, f.last_modified, 'epoch') AS "at!",
+               p.taken_at, p.camera_make, p.camera_model, p.width, p.height,
+               p.orientation, p.latitude, p.longitude
+        FROM photo_metadata p
+        JOIN files f ON f.id = p.file_id
+        WHERE p.user_id = $1
+          AND ($2::TIMESTAMP IS NULL
+               OR (COALESCE(p.taken_at, f.last_modified, 'epoch'), f.id) < ($2, $3))
+        ORDER BY 4 DESC, f.id DESC
+        LIMIT $4
+        "#,
+        user_id,
+        cursor.as_ref().map(|cursor| cursor.at),
+        cursor.as_ref().map(|cursor| cursor.id),
+        limit + 1
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|last| {
+            URL_SAFE_NO_PAD.encode(
+                serde_json::to_vec(&Cursor {
+                    at: last.at,
+                    id: last.id,
+                })
+                .unwrap(),
+            )
+        })
+    } else {
+        None
+    };
+
+    let mut days: Vec<Day> = Vec::new();
+    for row in rows {
+        let photo = Photo {
+            id: row.id,
+            name: row.filename,
+            folder_id: row.folder_id,
+            taken_at: row.taken_at,
+            camera_make: row.camera_make,
+            camera_model: row.camera_model,
+            width: row.width,
+            height: row.height,
+            orientation: row.orientation,
+            latitude: row.latitude,
+            longitude: row.longitude,
+        };
+        match days.last_mut() {
+            Some(day) if day.date == row.at.date() => day.photos.push(photo),
+            _ => days.push(Day {
+                date: row.at.date(),
+                photos: vec![photo],
+            }),
+        }
+    }
+
+    Ok(Json(TimelineResponse { days, next_cursor }))
+}
@@ -0,0 +1,60 @@
+use axum::{Json, extract::State};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    config::UploadConfig,
+    state::App,
+};
+
+use super::Error;
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    /// Bytes taken up by the user's files.
+    pub used: i64,
+    /// `None` if the user has no limit.
+    pub quota: Option<i64>,
+    /// Bytes left, negative if over quota; `None` if the user has no limit.
+    pub available: Option<i64>,
+}
+
+/// The storage usage of a user or [team](super::team), with their own quota or
+/// else the server default.
+///
+/// `used_bytes` is kept up to date by a trigger on `files`, so inside a transaction
+/// that just inserted a file this already includes it.
+pub async fn usage(
+    db: impl PgExecutor<'_>,
+    config: &UploadConfig,
+    drive_id: Uuid,
+) -> Result<Usage, Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT used_bytes AS "used_bytes!", quota_bytes FROM users WHERE id = $1
+        UNION ALL
+        SELECT used_bytes, quota_bytes FROM teams WHERE id = $1
+        "#,
+        drive_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let quota = user.quota_bytes.or(config.default_quota);
+    Ok(Usage {
+        used: user.used_bytes,
+        quota,
+        available: quota.map(|quota| quota - user.used_bytes),
+    })
+}
+
+/// `GET /account/usage`
+pub async fn usage_handler(State(state): State<App>, user: User) -> Result<Json<Usage>, Error> {
+    user.require(Scope::Read)?;
+    let user_id = user.id;
+
+    Ok(Json(usage(&state.db, &state.uploads, user_id).await?))
+}
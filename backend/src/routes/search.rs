@@ -0,0 +1,210 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+};
+
+use super::{Error, files::EntryKind, folder, team::Role};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Words to search for; every word must match, the last one may be incomplete.
+    pub q: String,
+    /// Only return files or only folders.
+    pub kind: Option<EntryKind>,
+    /// Comma-separated file extensions, e.g. `pdf,md`.
+    pub types: Option<String>,
+    /// Only search this folder and its subfolders.
+    pub folder_id: Option<Uuid>,
+    pub modified_after: Option<NaiveDateTime>,
+    pub modified_before: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct SearchResult {
+    /// `file` or `folder`.
+    pub kind: String,
+    pub id: Uuid,
+    pub name: String,
+    /// The folder containing the result.
+    pub folder_id: Uuid,
+    /// `None` for folders.
+    pub size: Option<i64>,
+    pub last_modified: Option<NaiveDateTime>,
+    /// Lowercase file extension, `None` for folders.
+    #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
+    pub file_type: Option<String>,
+    pub rank: f64,
+    /// HTML-escaped name with the matching words wrapped in `<mark>`.
+    #[sqlx(skip)]
+    pub highlight: String,
+    /// HTML-escaped excerpts of the document text with the matches wrapped in
+    /// `<mark>`, if the contents matched.
+    pub snippet: Option<String>,
+    #[serde(skip)]
+    total: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+    /// Number of matches across all pages.
+    pub total: i64,
+}
+
+/// `GET /search?q=quarterly rep&types=pdf,docx`
+///
+/// Matches file and folder names and the text the [indexer](crate::indexer)
+/// extracted from documents. Names weigh more than contents in the ranking.
+pub async fn handler(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    // the user's own drive, or the team drive `folder_id` is in
+    let user_id = user.drive(&state.db, query.folder_id, Role::Viewer).await?;
+
+    let terms = terms(&query.q);
+    if terms.is_empty() {
+        return Err(Error::BadRequest("Search query is empty".to_string()));
+    }
+    let scope = match query.folder_id {
+        Some(folder_id) => Some(folder::or_root(&state.db, user_id, Some(folder_id)).await?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // every term is a prefix match, all of them have to match
+    let tsquery = terms
+        .iter()
+        .map(|term| format!("{term}:*"))
+        .collect::<Vec<_>>()
+        .join(" & ");
+
+    let mut sql = QueryBuilder::new("WITH RECURSIVE input AS (SELECT to_tsquery('simple', ");
+    sql.push_bind(tsquery).push(") AS q)");
+    if let Some(scope) = scope {
+        sql.push(", scope AS (SELECT id FROM folders WHERE id = ")
+            .push_bind(scope)
+            .push(" UNION ALL SELECT f.id FROM folders f JOIN scope s ON f.parent_id = s.id)");
+    }
+
+    sql.push(
+        " SELECT *, count(*) OVER () AS total FROM (\
+         SELECT 'folder' AS kind, f.id, f.name, f.parent_id AS folder_id, NULL::BIGINT AS size, \
+         f.created_at AS last_modified, NULL::TEXT AS type, ts_rank(f.name_tsv, q) * 2 AS rank, \
+         NULL::TEXT AS snippet \
+         FROM folders f, input WHERE f.user_id = ",
+    )
+    .push_bind(user_id)
+    // the root folder has no name, and can't be a result
+    .push(
+        " AND f.parent_id IS NOT NULL AND f.name_tsv @@ q \
+         UNION ALL \
+         SELECT 'file', f.id, f.filename, f.folder_id, f.size, f.last_modified, \
+         lower(substring(f.filename FROM '\\.([^.]+)$')), \
+         ts_rank(f.name_tsv, q) * 2 + COALESCE(ts_rank(c.content_tsv, q), 0), \
+         CASE WHEN c.content_tsv @@ q THEN ts_headline('simple', html_escape(c.content), q, \
+         'MaxFragments=2, MinWords=8, MaxWords=25, StartSel=<mark>, StopSel=</mark>, FragmentDelimiter=\" … \"') END \
+         FROM files f LEFT JOIN file_contents c ON c.file_id = f.id, input WHERE f.user_id = ",
+    )
+    .push_bind(user_id)
+    .push(" AND (f.name_tsv @@ q OR c.content_tsv @@ q)) r WHERE TRUE");
+
+    push_filters(&mut sql, &query, scope.is_some());
+
+    sql.push(" ORDER BY rank DESC, lower(name), id LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(query.offset.max(0));
+
+    let mut results: Vec<SearchResult> = sql.build_query_as().fetch_all(&state.db).await?;
+    for result in &mut results {
+        result.highlight = highlight(&result.name, &terms);
+    }
+
+    Ok(Json(SearchResponse {
+        total: results.first().map_or(0, |r| r.total),
+        results,
+    }))
+}
+
+fn push_filters(sql: &mut QueryBuilder<'_, Postgres>, query: &SearchQuery, scoped: bool) {
+    match query.kind {
+        Some(EntryKind::File) => sql.push(" AND kind = 'file'"),
+        Some(EntryKind::Folder) => sql.push(" AND kind = 'folder'"),
+        None => sql,
+    };
+    if let Some(types) = &query.types {
+        let types: Vec<_> = types
+            .split(',')
+            .map(|t| t.trim().trim_start_matches('.').to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        sql.push(" AND type = ANY(").push_bind(types).push(")");
+    }
+    if scoped {
+        sql.push(" AND folder_id IN (SELECT id FROM scope)");
+    }
+    if let Some(after) = query.modified_after {
+        sql.push(" AND last_modified >= ").push_bind(after);
+    }
+    if let Some(before) = query.modified_before {
+        sql.push(" AND last_modified < ").push_bind(before);
+    }
+}
+
+/// Splits a query into lowercase words the same way names are split for indexing.
+fn terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Escapes `name` for HTML and marks the words that start with one of `terms`.
+fn highlight(name: &str, terms: &[String]) -> String {
+    let mut out = String::new();
+    let mut rest = name;
+    while !rest.is_empty() {
+        let is_word = rest.starts_with(char::is_alphanumeric);
+        let end = rest
+            .find(|c: char| c.is_alphanumeric() != is_word)
+            .unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        let lower = part.to_lowercase();
+        if is_word && terms.iter().any(|term| lower.starts_with(term.as_str())) {
+            out.push_str("<mark>");
+            out.push_str(&escape(part));
+            out.push_str("</mark>");
+        } else {
+            out.push_str(&escape(part));
+        }
+        rest = tail;
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
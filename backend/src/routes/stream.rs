@@ -0,0 +1,99 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+    video,
+};
+
+use super::{Error, team::Role};
+
+/// `GET /files/{id}/hls/{*path}`, starting with `master.m3u8`.
+///
+/// Serves the playlists and segments the [transcoder](crate::video) produced,
+/// once the file's `video_status` is `ready`.
+pub async fn hls_handler(
+    State(state): State<App>,
+    user: User,
+    Path((file_id, path)): Path<(Uuid, String)>,
+) -> Result<Response, Error> {
+    user.require(Scope::Read)?;
+    user.ensure_file(&state.db, file_id).await?;
+    let drive = user.file_drive(&state.db, file_id, Role::Viewer).await?;
+
+    // only plain names like `v0/segment0001.ts`, nothing that could leave the directory
+    let valid = path.split('/').all(|component| {
+        !component.is_empty()
+            && !component.starts_with('.')
+            && component
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    });
+    if !valid {
+        return Err(Error::NotFound);
+    }
+
+    serve(&state, drive, file_id, &path).await
+}
+
+/// `GET /files/{id}/poster`, a still frame of a transcoded video.
+pub async fn poster_handler(
+    State(state): State<App>,
+    user: User,
+    Path(file_id): Path<Uuid>,
+) -> Result<Response, Error> {
+    user.require(Scope::Read)?;
+    user.ensure_file(&state.db, file_id).await?;
+    let drive = user.file_drive(&state.db, file_id, Role::Viewer).await?;
+
+    serve(&state, drive, file_id, video::POSTER).await
+}
+
+async fn serve(state: &App, drive_id: Uuid, file_id: Uuid, path: &str) -> Result<Response, Error> {
+    let status = sqlx::query_scalar!(
+        r#"
+        SELECT video_status FROM files WHERE id = $1 AND user_id = $2
+        "#,
+        file_id,
+        drive_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if status.as_deref() != Some("ready") {
+        return Err(Error::NotFound);
+    }
+
+    let data = match tokio::fs::read(state.hls_dir(drive_id, file_id).join(path)).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(err) => return Err(err.into()),
+    };
+
+    let extension = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    let (content_type, cache_control) = match extension {
+        "m3u8" => ("application/vnd.apple.mpegurl", "private, no-cache"),
+        // segments are never rewritten
+        "ts" => ("video/mp2t", "private, max-age=31536000, immutable"),
+        "jpg" => ("image/jpeg", "private, no-cache"),
+        _ => ("application/octet-stream", "private, no-cache"),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(cache_control),
+            ),
+        ],
+        data,
+    )
+        .into_response())
+}
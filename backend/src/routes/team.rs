@@ -0,0 +1,636 @@
+//! Teams and their shared drives.
+//!
+//! A team owns a drive of its own, so the files in it stay when members leave.
+//! Folders and files in it have the team's id as `user_id`, and the handlers
+//! elsewhere find the drive of the folder or file a request is about with
+//! [`User::drive`] and [`User::file_drive`], which check the requesting user's
+//! [`Role`] in the team.
+
+use std::io;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    jobs::{self, Job},
+    state::{App, AppState},
+    thumbnails,
+};
+
+use super::{Error, folder, quota};
+
+/// What a member may do in a team, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// List, search and download.
+    Viewer,
+    /// Also upload, rename, move and delete, and move their own folders in.
+    Editor,
+    /// Also manage the members, and delete the team.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that the user is a member of the team with at least `role`.
+async fn ensure_role(
+    db: impl PgExecutor<'_>,
+    team_id: Uuid,
+    user_id: Uuid,
+    role: Role,
+) -> Result<(), Error> {
+    let member = sqlx::query_scalar!(
+        r#"
+        SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2
+        "#,
+        team_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .as_deref()
+    .and_then(Role::parse)
+    .ok_or(Error::NotFound)?;
+    if member < role {
+        return Err(Error::Forbidden);
+    }
+    Ok(())
+}
+
+/// A team can't be left without an owner, who alone could delete it.
+async fn ensure_has_owner(db: impl PgExecutor<'_>, team_id: Uuid) -> Result<(), Error> {
+    let owners = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM team_members WHERE team_id = $1 AND role = 'owner'
+        "#,
+        team_id
+    )
+    .fetch_one(db)
+    .await?;
+    if owners == 0 {
+        return Err(Error::Conflict("A team needs at least one owner"));
+    }
+    Ok(())
+}
+
+/// Locks the team's row, so concurrent changes to its members take turns.
+async fn lock(db: impl PgExecutor<'_>, team_id: Uuid) -> Result<String, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT name FROM teams WHERE id = $1 FOR UPDATE
+        "#,
+        team_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TeamResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// The requesting user's role.
+    pub role: Role,
+    /// List it with `POST /files` to browse the team drive.
+    pub root_folder_id: Uuid,
+    /// Bytes taken up by the team's files.
+    pub used_bytes: i64,
+    /// `None` if the team has no limit.
+    pub quota: Option<i64>,
+    pub members: i64,
+}
+
+/// `GET /teams`: the teams the user is a member of, by name.
+pub async fn list(State(state): State<App>, user: User) -> Result<Json<Vec<TeamResponse>>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+
+    Ok(Json(teams(&state, user.id, None).await?))
+}
+
+/// The user's teams, or only the one with `team_id`.
+async fn teams(
+    state: &AppState,
+    user_id: Uuid,
+    team_id: Option<Uuid>,
+) -> Result<Vec<TeamResponse>, Error> {
+    let teams = sqlx::query!(
+        r#"
+        SELECT t.id, t.name, m.role, f.id AS root_folder_id, t.used_bytes, t.quota_bytes,
+               (SELECT COUNT(*) FROM team_members c WHERE c.team_id = t.id) AS "members!"
+        FROM team_members m
+        JOIN teams t ON t.id = m.team_id
+        JOIN folders f ON f.user_id = t.id AND f.parent_id IS NULL
+        WHERE m.user_id = $1 AND ($2::UUID IS NULL OR t.id = $2)
+        ORDER BY lower(t.name), t.id
+        "#,
+        user_id,
+        team_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(teams
+        .into_iter()
+        .filter_map(|team| {
+            Some(TeamResponse {
+                id: team.id,
+                name: team.name,
+                role: Role::parse(&team.role)?,
+                root_folder_id: team.root_folder_id,
+                used_bytes: team.used_bytes,
+                quota: team.quota_bytes.or(state.uploads.default_quota),
+                members: team.members,
+            })
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTeamRequest {
+    pub name: String,
+}
+
+/// `POST /teams`: creates a team with the user as its owner.
+pub async fn create(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<CreateTeamRequest>,
+) -> Result<(StatusCode, Json<TeamResponse>), Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("Team name is empty".to_string()));
+    }
+
+    let mut tx = state.db.begin().await?;
+    let team_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO teams (name) VALUES ($1) RETURNING id
+        "#,
+        name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, 'owner')
+        "#,
+        team_id,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let event = Event::team("team.create", team_id).after(json!({ "name": name }));
+    audit::record(&mut *tx, user.id, &client, event).await?;
+    tx.commit().await?;
+
+    let team = teams(&state, user.id, Some(team_id))
+        .await?
+        .pop()
+        .ok_or(Error::NotFound)?;
+    Ok((StatusCode::CREATED, Json(team)))
+}
+
+/// `DELETE /teams/{id}`: deletes a team together with everything in its drive.
+pub async fn delete(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(team_id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    let mut tx = state.db.begin().await?;
+    let name = lock(&mut *tx, team_id).await?;
+    ensure_role(&mut *tx, team_id, user.id, Role::Owner).await?;
+
+    // the folders and files go along with the team, but not their blobs on disk
+    let file_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM files WHERE user_id = $1 RETURNING id
+        "#,
+        team_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if !file_ids.is_empty() {
+        let job = Job::RemoveBlobs {
+            user_id: team_id,
+            file_ids: file_ids.clone(),
+        };
+        jobs::enqueue(&mut *tx, &job).await?;
+    }
+    sqlx::query!("DELETE FROM teams WHERE id = $1", team_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let event = Event::team("team.delete", team_id)
+        .before(json!({ "name": name, "files": file_ids.len() }));
+    audit::record(&mut *tx, user.id, &client, event).await?;
+    tx.commit().await?;
+    state.jobs.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    pub user_id: Uuid,
+    pub email: String,
+    pub name: Option<String>,
+    pub role: String,
+    pub added_at: NaiveDateTime,
+}
+
+/// `GET /teams/{id}/members`: who is in the team, owners first.
+pub async fn list_members(
+    State(state): State<App>,
+    user: User,
+    Path(team_id): Path<Uuid>,
+) -> Result<Json<Vec<MemberResponse>>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+
+    ensure_role(&state.db, team_id, user.id, Role::Viewer).await?;
+    let members = sqlx::query_as!(
+        MemberResponse,
+        r#"
+        SELECT m.user_id, u.email, u.name, m.role, m.added_at FROM team_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.team_id = $1
+        ORDER BY m.role = 'owner' DESC, lower(u.email)
+        "#,
+        team_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(members))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMemberRequest {
+    pub email: String,
+    pub role: Role,
+}
+
+/// `POST /teams/{id}/members`: adds a user to the team.
+pub async fn add_member(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(team_id): Path<Uuid>,
+    Json(input): Json<AddMemberRequest>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    ensure_role(&state.db, team_id, user.id, Role::Owner).await?;
+    let member_id = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users WHERE lower(email) = lower($1)
+        "#,
+        input.email.trim()
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let added = sqlx::query!(
+        r#"
+        INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (team_id, user_id) DO NOTHING
+        "#,
+        team_id,
+        member_id,
+        input.role.as_str()
+    )
+    .execute(&state.db)
+    .await?;
+    if added.rows_affected() == 0 {
+        return Err(Error::Conflict("The user is already a member of the team"));
+    }
+
+    let event = Event::team("team.add_member", team_id)
+        .after(json!({ "user_id": member_id, "role": input.role }));
+    audit::record(&state.db, user.id, &client, event).await?;
+
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleRequest {
+    pub role: Role,
+}
+
+/// `PUT /teams/{id}/members/{user_id}`: changes a member's role.
+pub async fn set_role(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path((team_id, member_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<SetRoleRequest>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    let mut tx = state.db.begin().await?;
+    lock(&mut *tx, team_id).await?;
+    ensure_role(&mut *tx, team_id, user.id, Role::Owner).await?;
+    let old_role = sqlx::query_scalar!(
+        r#"
+        UPDATE team_members m SET role = $3 FROM team_members old
+        WHERE m.team_id = $1 AND m.user_id = $2
+          AND old.team_id = m.team_id AND old.user_id = m.user_id
+        RETURNING old.role
+        "#,
+        team_id,
+        member_id,
+        input.role.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+    ensure_has_owner(&mut *tx, team_id).await?;
+
+    let event = Event::team("team.update_member", team_id)
+        .before(json!({ "user_id": member_id, "role": old_role }))
+        .after(json!({ "user_id": member_id, "role": input.role }));
+    audit::record(&mut *tx, user.id, &client, event).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `DELETE /teams/{id}/members/{user_id}`: removes a member; anyone may leave.
+///
+/// What they put in the team drive stays there.
+pub async fn remove_member(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path((team_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    let mut tx = state.db.begin().await?;
+    lock(&mut *tx, team_id).await?;
+    let required = if member_id == user.id {
+        Role::Viewer
+    } else {
+        Role::Owner
+    };
+    ensure_role(&mut *tx, team_id, user.id, required).await?;
+    let role = sqlx::query_scalar!(
+        r#"
+        DELETE FROM team_members WHERE team_id = $1 AND user_id = $2 RETURNING role
+        "#,
+        team_id,
+        member_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+    ensure_has_owner(&mut *tx, team_id).await?;
+
+    let event = Event::team("team.remove_member", team_id)
+        .before(json!({ "user_id": member_id, "role": role }));
+    audit::record(&mut *tx, user.id, &client, event).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    /// One of the user's own folders.
+    pub folder_id: Uuid,
+    /// Where in the team drive to put it; its root folder by default.
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferResponse {
+    pub id: Uuid,
+    pub parent_id: Uuid,
+    /// Subfolders moved along, at any depth.
+    pub folders: usize,
+    pub files: usize,
+}
+
+/// `POST /teams/{id}/transfer`: hands one of the user's folders, with everything
+/// in it, over to the team. It then counts against the team's quota, and stays
+/// when the user leaves or is deleted.
+pub async fn transfer(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(team_id): Path<Uuid>,
+    Json(input): Json<TransferRequest>,
+) -> Result<Json<TransferResponse>, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+
+    ensure_role(&state.db, team_id, user.id, Role::Editor).await?;
+    let parent_id = folder::or_root(&state.db, team_id, input.parent_id).await?;
+    folder::ensure_not_vault(&state.db, parent_id).await?;
+    folder::ensure_not_root(&state.db, user.id, input.folder_id).await?;
+
+    let mut tx = state.db.begin().await?;
+    let moved = sqlx::query!(
+        r#"
+        SELECT name, parent_id AS "parent_id!" FROM folders
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#,
+        input.folder_id,
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+    // names from before they were checked must not turn into paths in the team drive
+    folder::ensure_valid_name(&moved.name)?;
+
+    // nothing may be added to or moved out of the subtree while it changes hands
+    let subfolders = sqlx::query!(
+        r#"
+        WITH RECURSIVE subfolders AS (
+            SELECT id FROM folders WHERE id = $1
+            UNION
+            SELECT f.id FROM folders f
+            JOIN subfolders sf ON f.parent_id = sf.id
+        )
+        SELECT id, vault FROM folders
+        WHERE id IN (SELECT id FROM subfolders)
+        ORDER BY id
+        FOR UPDATE
+        "#,
+        input.folder_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    // vaults are shared by their owner, who has to stay one
+    if subfolders.iter().any(|folder| folder.vault) {
+        return Err(Error::BadRequest(
+            "Vaults cannot be moved to a team drive".to_string(),
+        ));
+    }
+    let folder_ids: Vec<Uuid> = subfolders.iter().map(|folder| folder.id).collect();
+
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders
+        WHERE user_id = $1 AND parent_id = $2 AND name = $3
+        "#,
+        team_id,
+        parent_id,
+        moved.name
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if existing.is_some() {
+        return Err(Error::Conflict("Folder already exists with that name"));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE folders SET user_id = $2 WHERE id = ANY($1)
+        "#,
+        &folder_ids,
+        team_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE folders SET parent_id = $2 WHERE id = $1
+        "#,
+        input.folder_id,
+        parent_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let file_ids = sqlx::query_scalar!(
+        r#"
+        UPDATE files SET user_id = $2 WHERE folder_id = ANY($1) RETURNING id
+        "#,
+        &folder_ids,
+        team_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE photo_metadata SET user_id = $2 WHERE file_id = ANY($1)
+        "#,
+        &file_ids,
+        team_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let usage = quota::usage(&mut *tx, &state.uploads, team_id).await?;
+    if usage.available.is_some_and(|available| available < 0) {
+        return Err(Error::QuotaExceeded);
+    }
+
+    let event = Event::folder("folder.transfer", input.folder_id)
+        .before(json!({ "user_id": user.id, "parent_id": moved.parent_id }))
+        .after(json!({ "team_id": team_id, "parent_id": parent_id, "files": file_ids.len() }));
+    audit::record(&mut *tx, user.id, &client, event).await?;
+
+    // blobs live in their owner's directory; they move before the commit and
+    // back if it fails, so the rows never point at the wrong one for long
+    move_contents(&state, user.id, team_id, &file_ids).await?;
+    if let Err(err) = tx.commit().await {
+        move_contents(&state, team_id, user.id, &file_ids).await?;
+        return Err(err.into());
+    }
+
+    Ok(Json(TransferResponse {
+        id: input.folder_id,
+        parent_id,
+        folders: folder_ids.len() - 1,
+        files: file_ids.len(),
+    }))
+}
+
+/// Moves the blobs of files, with their thumbnails and videos, to another
+/// owner's directory; all of them or, on errors, none.
+async fn move_contents(
+    state: &AppState,
+    from: Uuid,
+    to: Uuid,
+    file_ids: &[Uuid],
+) -> io::Result<()> {
+    for (moved, &file_id) in file_ids.iter().enumerate() {
+        if let Err(err) = move_file(state, from, to, file_id).await {
+            for &file_id in &file_ids[..=moved] {
+                let _ = move_file(state, to, from, file_id).await;
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// What is missing (e.g. a thumbnail not generated yet) is fine.
+async fn move_file(state: &AppState, from: Uuid, to: Uuid, file_id: Uuid) -> io::Result<()> {
+    let mut paths = vec![
+        (state.blob_path(from, file_id), state.blob_path(to, file_id)),
+        (state.hls_dir(from, file_id), state.hls_dir(to, file_id)),
+    ];
+    paths.extend(thumbnails::SIZES.map(|size| {
+        (
+            state.thumbnail_path(from, file_id, size),
+            state.thumbnail_path(to, file_id, size),
+        )
+    }));
+
+    for (old, new) in paths {
+        if let Some(dir) = new.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        match tokio::fs::rename(&old, &new).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Scope, User},
+    state::App,
+    thumbnails,
+};
+
+use super::{Error, team::Role};
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailQuery {
+    /// Requested edge length in pixels; defaults to 256.
+    pub size: Option<u32>,
+}
+
+/// `GET /files/{id}/thumbnail?size=256`
+///
+/// Returns a JPEG no larger than the next generated size, or 404 while it is being
+/// generated or if the file has no preview.
+pub async fn handler(
+    State(state): State<App>,
+    user: User,
+    Path(file_id): Path<Uuid>,
+    Query(query): Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    user.require(Scope::Read)?;
+    user.ensure_file(&state.db, file_id).await?;
+    let drive = user.file_drive(&state.db, file_id, Role::Viewer).await?;
+
+    let has_thumbnail = sqlx::query_scalar!(
+        r#"
+        SELECT has_thumbnail FROM files WHERE id = $1 AND user_id = $2
+        "#,
+        file_id,
+        drive
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if !has_thumbnail {
+        return Err(Error::NotFound);
+    }
+
+    let requested = query.size.unwrap_or(256);
+    let size = thumbnails::SIZES
+        .into_iter()
+        .find(|&size| size >= requested)
+        .unwrap_or(thumbnails::SIZES[thumbnails::SIZES.len() - 1]);
+
+    // file contents never change, so neither do their thumbnails
+    let etag = format!("\"{file_id}-{size}\"");
+    let cache_headers = [
+        (
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, max-age=31536000, immutable"),
+        ),
+        (header::ETAG, HeaderValue::from_str(&etag).unwrap()),
+    ];
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let data = match tokio::fs::read(state.thumbnail_path(drive, file_id, size)).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"))],
+        cache_headers,
+        data,
+    )
+        .into_response())
+}
@@ -1,5 +1,3 @@
-use std::{fs, io::Write, path::PathBuf};
-
 use axum::{
     Json,
     extract::{Multipart, State},
@@ -8,93 +6,381 @@ use axum::{
 };
 use chrono::Utc;
 use sanitize_filename::sanitize;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use uuid::Uuid;
 
-use crate::state::App;
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    content_type, encryption,
+    integrity::{self, Checksum, Hasher},
+    state::{App, AppState},
+    video,
+};
+
+use super::{Error, files, folder, path, quota, team::Role};
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize)]
 pub struct UploadResponse {
-    id: String,
-    original_filename: String,
-    folder_path: String,
-    size: i64,
+    pub id: Uuid,
+    pub original_filename: String,
+    pub folder_id: Uuid,
+    pub folder_path: String,
+    pub size: i64,
+    pub mime_type: String,
+    /// Hex encoded SHA-256 of the contents.
+    pub sha256: String,
 }
 
-pub async fn handler(State(state): State<App>, mut multipart: Multipart) -> impl IntoResponse {
+pub async fn handler(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
     tracing::info!("Uploading file...");
 
-    // Hardcoded user ID & folder path for now
-    let user_id = Uuid::parse_str("aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa").unwrap();
+    if let Err(err) = user.require(Scope::Upload) {
+        return err.into_response();
+    }
 
-    let mut file_count: u32 = 0;
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    let mut folder_id = None;
+    let mut expected = None;
+    let mut uploaded = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return Error::BadRequest(err.body_text()).into_response(),
+        };
         tracing::info!("Processing field: {:?}", field.name());
+        // an optional `folder_id` field selects the target folder for the files after it
+        if field.name() == Some("folder_id") {
+            let text = field.text().await.unwrap_or_default();
+            match Uuid::parse_str(text.trim()) {
+                Ok(id) => folder_id = Some(id),
+                Err(_) => {
+                    return Error::BadRequest("Invalid folder_id".to_string()).into_response();
+                }
+            }
+            continue;
+        }
+        // an optional `sha256` field (hex or base64) is checked against the next file
+        if field.name() == Some("sha256") {
+            let text = field.text().await.unwrap_or_default();
+            match integrity::parse(&text) {
+                Some(checksum) => expected = Some(checksum),
+                None => return Error::BadRequest("Invalid sha256".to_string()).into_response(),
+            }
+            continue;
+        }
         // there might be files in the multipart form
         if let Some(filename) = field.file_name().map(|s| s.to_string()) {
             tracing::info!("File name: {:?}", filename);
-            let original_filename = sanitize(&filename);
-            let file_id = Uuid::new_v4();
-
-            // Check for duplicate file name in same folder
-            let existing = sqlx::query_scalar!(
-                r#"
-                SELECT id FROM files
-                WHERE user_id = $1 AND filename = $2 AND folder_id IS NULL
-                "#,
-                user_id,
-                original_filename
-            )
-            .fetch_optional(&state.db)
-            .await
-            .unwrap();
-
-            if existing.is_some() {
-                tracing::info!("File already exists with that name");
-                return (
-                    StatusCode::CONFLICT,
-                    Json(r#"{"status":"failed","message":"File already exists with that name"}"#),
-                );
+
+            // without a folder the file ends up in the root folder
+            let folder_id = match user.folder_or_default(&state.db, folder_id).await {
+                Ok(folder_id) => folder_id,
+                Err(err) => return err.into_response(),
+            };
+            let drive = match user.drive(&state.db, folder_id, Role::Editor).await {
+                Ok(drive) => drive,
+                Err(err) => return err.into_response(),
+            };
+            // checked again when storing, this only stops receiving what won't fit
+            let available = match quota::usage(&state.db, &state.uploads, drive).await {
+                Ok(usage) => usage.available,
+                Err(err) => return err.into_response(),
+            };
+
+            let mut data = Vec::new();
+            let mut hasher = Hasher::default();
+            loop {
+                match field.chunk().await {
+                    Ok(Some(chunk)) => {
+                        if available
+                            .is_some_and(|available| (data.len() + chunk.len()) as i64 > available)
+                        {
+                            return Error::QuotaExceeded.into_response();
+                        }
+                        hasher.update(&chunk);
+                        data.extend_from_slice(&chunk);
+                    }
+                    Ok(None) => break,
+                    Err(err) => return Error::BadRequest(err.body_text()).into_response(),
+                }
+            }
+            let checksum = hasher.finish();
+            if expected.take().is_some_and(|expected| expected != checksum) {
+                return Error::ChecksumMismatch(filename).into_response();
             }
 
-            tracing::info!("File ID: {:?}", file_id);
-
-            // Prepare upload path
-            let mut upload_path = PathBuf::from(&state.upload_dir).join(user_id.to_string());
-            fs::create_dir_all(&upload_path).unwrap();
-
-            // Save file to disk
-            let data = field.bytes().await.unwrap();
-            let size = data.len() as i64;
-
-            upload_path.push(file_id.to_string());
-            let mut file = fs::File::create(&upload_path).unwrap();
-            file.write_all(&data).unwrap();
-            file.flush().unwrap();
-
-            // Save file info to database (we do not provide a folder, so it is null, ie the root folder)
-            sqlx::query!(
-                r#"
-                INSERT INTO files (id, user_id, filename, folder_id, size, last_modified)
-                VALUES ($1, $2, $3, $4, $5, $6)
-                "#,
-                file_id,
-                user_id,
-                original_filename,
-                Uuid::nil(), // root folder
+            let size = data.len() as u64;
+            let stored = store_file(
+                &state,
+                drive,
+                folder_id,
+                &filename,
+                data.as_slice(),
                 size,
-                Utc::now().naive_utc()
-            )
-            .execute(&state.db)
-            .await
-            .unwrap();
-
-            file_count += 1;
+                checksum,
+            );
+            let file = match stored.await {
+                Ok(file) => file,
+                Err(err) => return err.into_response(),
+            };
+            let event = Event::file("file.upload", file.id).after(&file);
+            if let Err(err) = audit::record(&state.db, user.id, &client, event).await {
+                return Error::from(err).into_response();
+            }
+            uploaded.push(file);
         }
     }
 
-    if file_count > 0 {
-        (StatusCode::OK, Json(r#"{"status":"success"}"#))
+    if uploaded.is_empty() {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "status": "failed" })),
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "success", "files": uploaded })),
+        )
+            .into_response()
+    }
+}
+
+/// Stores `contents` as a new file named `filename` in `folder_id` (`None` is the root
+/// folder). They are `size` bytes long and `checksum` is their SHA-256, both known to
+/// the caller from receiving them.
+///
+/// This is the single place new files enter the system, so every frontend
+/// (HTTP uploads, SFTP, ...) keeps disk and database in the same shape.
+pub async fn store_file(
+    state: &AppState,
+    drive_id: Uuid,
+    folder_id: Option<Uuid>,
+    filename: &str,
+    mut contents: impl AsyncRead + Unpin,
+    size: u64,
+    checksum: Checksum,
+) -> Result<UploadResponse, Error> {
+    let original_filename = sanitize(filename);
+    let folder_id = folder::or_root(&state.db, drive_id, folder_id).await?;
+    // files in vaults are encrypted by the client, there is nothing to look into
+    let vault = folder::is_vault(&state.db, folder_id).await?;
+    let head = read_head(&mut contents).await?;
+    let mime_type = if vault {
+        "application/octet-stream".to_string()
     } else {
-        (StatusCode::BAD_REQUEST, Json(r#"{"status":"failed"}"#))
+        content_type::check(&state.uploads, &original_filename, &head)?
+    };
+
+    // Check for duplicate file name in same folder
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM files
+        WHERE user_id = $1 AND filename = $2 AND folder_id = $3
+        "#,
+        drive_id,
+        original_filename,
+        folder_id
+    )
+    .fetch_optional(&state.db)
+    .await?;
+
+    if existing.is_some() {
+        tracing::info!("File already exists with that name");
+        return Err(Error::Conflict("File already exists with that name"));
     }
+
+    let file_id = Uuid::new_v4();
+    tracing::info!("File ID: {:?}", file_id);
+    let key = state.keys.generate(file_id);
+
+    // Save file info to database first: the row counts towards the user's usage,
+    // so the quota is checked before anything is written to disk. Files in vaults
+    // are marked as indexed and thumbnailed right away, the workers can't read them.
+    let mut transaction = state.db.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO files
+            (id, user_id, filename, folder_id, size, last_modified, video_status, mime_type, key_id, data_key,
+             indexed_at, thumbnailed_at, sha256)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                CASE WHEN $11 THEN now() END, CASE WHEN $11 THEN now() END, $12)
+        "#,
+        file_id,
+        drive_id,
+        original_filename,
+        folder_id,
+        size as i64,
+        Utc::now().naive_utc(),
+        (!vault && video::is_video(&original_filename)).then_some("pending"),
+        mime_type,
+        key.as_ref().map(|key| key.key_id.as_str()),
+        key.as_ref().map(|key| key.wrapped.as_slice()),
+        vault,
+        checksum.as_slice()
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let usage = quota::usage(&mut *transaction, &state.uploads, drive_id).await?;
+    if usage.available.is_some_and(|available| available < 0) {
+        return Err(Error::QuotaExceeded);
+    }
+
+    // Save file to disk
+    let upload_path = state.blob_path(drive_id, file_id);
+    if let Some(user_dir) = upload_path.parent() {
+        tokio::fs::create_dir_all(user_dir).await?;
+    }
+    let contents = head.as_slice().chain(contents);
+    let written = encryption::write(
+        &upload_path,
+        contents,
+        size,
+        key.as_ref().map(|key| &key.key),
+    )
+    .await;
+    if let Err(err) = written {
+        let _ = tokio::fs::remove_file(&upload_path).await;
+        return Err(err.into());
+    }
+
+    transaction.commit().await?;
+    state.metrics.uploaded(size as usize);
+    state.indexer.notify_one();
+    state.thumbnailer.notify_one();
+    state.transcoder.notify_one();
+
+    Ok(UploadResponse {
+        id: file_id,
+        original_filename,
+        folder_id,
+        folder_path: path::folder_path(&state.db, drive_id, folder_id).await?,
+        size: size as i64,
+        mime_type,
+        sha256: integrity::to_hex(&checksum),
+    })
+}
+
+/// Replaces the contents of an existing file, keeping its id, name and place, like
+/// [`store_file`] does for new ones. Everything derived from the old contents is
+/// redone, and the file gets a new data key: a key never encrypts two plain texts.
+pub async fn replace_file(
+    state: &AppState,
+    drive_id: Uuid,
+    file_id: Uuid,
+    mut contents: impl AsyncRead + Unpin,
+    size: u64,
+    checksum: Checksum,
+) -> Result<UploadResponse, Error> {
+    let file = sqlx::query!(
+        r#"
+        SELECT f.filename, f.folder_id, d.vault FROM files f JOIN folders d ON d.id = f.folder_id
+        WHERE f.id = $1 AND f.user_id = $2
+        "#,
+        file_id,
+        drive_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+    let head = read_head(&mut contents).await?;
+    let mime_type = if file.vault {
+        "application/octet-stream".to_string()
+    } else {
+        content_type::check(&state.uploads, &file.filename, &head)?
+    };
+    let key = state.keys.generate(file_id);
+
+    let mut transaction = state.db.begin().await?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE files
+        SET size = $1, last_modified = now(), video_status = $2, video_duration = NULL,
+            mime_type = $3, key_id = $4, data_key = $5, sha256 = $6,
+            indexed_at = CASE WHEN $7 THEN now() END, thumbnailed_at = CASE WHEN $7 THEN now() END,
+            has_thumbnail = false, verified_at = NULL, corrupt = false, missing = false
+        WHERE id = $8 AND user_id = $9
+        "#,
+        size as i64,
+        (!file.vault && video::is_video(&file.filename)).then_some("pending"),
+        mime_type,
+        key.as_ref().map(|key| key.key_id.as_str()),
+        key.as_ref().map(|key| key.wrapped.as_slice()),
+        checksum.as_slice(),
+        file.vault,
+        file_id,
+        drive_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    sqlx::query!(
+        r#"
+        DELETE FROM file_contents WHERE file_id = $1
+        "#,
+        file_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM photo_metadata WHERE file_id = $1
+        "#,
+        file_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let usage = quota::usage(&mut *transaction, &state.uploads, drive_id).await?;
+    if usage.available.is_some_and(|available| available < 0) {
+        return Err(Error::QuotaExceeded);
+    }
+
+    // the old contents stay readable until the new ones are complete
+    let upload_path = state.blob_path(drive_id, file_id);
+    let new_path = upload_path.with_extension("new");
+    let contents = head.as_slice().chain(contents);
+    let written =
+        encryption::write(&new_path, contents, size, key.as_ref().map(|key| &key.key)).await;
+    if let Err(err) = written.and(tokio::fs::rename(&new_path, &upload_path).await) {
+        let _ = tokio::fs::remove_file(&new_path).await;
+        return Err(err.into());
+    }
+    files::remove_derived(state, drive_id, file_id).await;
+
+    transaction.commit().await?;
+    state.metrics.uploaded(size as usize);
+    state.indexer.notify_one();
+    state.thumbnailer.notify_one();
+    state.transcoder.notify_one();
+
+    Ok(UploadResponse {
+        id: file_id,
+        original_filename: file.filename,
+        folder_id: file.folder_id,
+        folder_path: path::folder_path(&state.db, drive_id, file.folder_id).await?,
+        size: size as i64,
+        mime_type,
+        sha256: integrity::to_hex(&checksum),
+    })
+}
+
+/// Reads as much of the start of `contents` as [`content_type::check`] looks at.
+async fn read_head(contents: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(content_type::SNIFF_LEN);
+    contents
+        .take(content_type::SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(head)
 }
@@ -0,0 +1,546 @@
+//! End-to-end encrypted vault folders.
+//!
+//! Clients encrypt the names and contents of files in vaults before uploading
+//! them, with a folder key the server only ever sees wrapped for the public key
+//! of each member. See `common::vault` for the client side. The server merely
+//! stores these keys, and checks membership before handing out a vault's files.
+//!
+//! Keys travel as standard base64 in JSON.
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{Scope, User},
+    client::Client,
+    encryption::Blob,
+    state::App,
+};
+
+use super::{Error, folder};
+
+/// Length of an X25519 public key.
+const PUBLIC_KEY_LEN: usize = 32;
+
+fn decode(name: &str, value: &str) -> Result<Vec<u8>, Error> {
+    STANDARD
+        .decode(value)
+        .ok()
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or_else(|| Error::BadRequest(format!("Invalid {name}")))
+}
+
+fn decode_public_key(value: &str) -> Result<Vec<u8>, Error> {
+    let key = decode("public key", value)?;
+    if key.len() != PUBLIC_KEY_LEN {
+        return Err(Error::BadRequest("Invalid public key".to_string()));
+    }
+    Ok(key)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyPairBody {
+    pub public_key: String,
+    /// The secret key, encrypted with the user's vault passphrase.
+    pub locked_secret: String,
+}
+
+/// `GET /account/vault-key`: the user's key pair, to be unlocked by the client.
+pub async fn get_key_pair(
+    State(state): State<App>,
+    user: User,
+) -> Result<Json<KeyPairBody>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let keys = sqlx::query!(
+        r#"
+        SELECT public_key, locked_secret FROM user_keys WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(KeyPairBody {
+        public_key: STANDARD.encode(keys.public_key),
+        locked_secret: STANDARD.encode(keys.locked_secret),
+    }))
+}
+
+/// `PUT /account/vault-key`: sets up the user's key pair, or stores the secret key
+/// locked with a new passphrase.
+///
+/// A different key pair is only accepted while the user is not a member of any
+/// vault, since the folder keys wrapped for the old one would be lost.
+pub async fn set_key_pair(
+    State(state): State<App>,
+    user: User,
+    Json(input): Json<KeyPairBody>,
+) -> Result<StatusCode, Error> {
+    user.require_session()?;
+    let user_id = user.id;
+
+    let public_key = decode_public_key(&input.public_key)?;
+    let locked_secret = decode("locked secret", &input.locked_secret)?;
+
+    let mut transaction = state.db.begin().await?;
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT public_key FROM user_keys WHERE user_id = $1 FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    if existing.is_some_and(|existing| existing != public_key) {
+        let memberships = sqlx::query_scalar!(
+            r#"
+            SELECT count(*) AS "count!" FROM vault_members WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if memberships > 0 {
+            return Err(Error::Conflict(
+                "The key pair cannot be replaced while it has access to vaults",
+            ));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_keys (user_id, public_key, locked_secret) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE
+        SET public_key = EXCLUDED.public_key, locked_secret = EXCLUDED.locked_secret
+        "#,
+        user_id,
+        public_key,
+        locked_secret
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublicKeyQuery {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicKeyResponse {
+    pub user_id: Uuid,
+    pub public_key: String,
+}
+
+/// `GET /users/vault-key?email=...`: another user's public key, to share a vault with them.
+pub async fn get_public_key(
+    State(state): State<App>,
+    user: User,
+    Query(query): Query<PublicKeyQuery>,
+) -> Result<Json<PublicKeyResponse>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+
+    let recipient = sqlx::query!(
+        r#"
+        SELECT u.id, k.public_key FROM users u JOIN user_keys k ON k.user_id = u.id
+        WHERE lower(u.email) = lower($1)
+        "#,
+        query.email.trim()
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(PublicKeyResponse {
+        user_id: recipient.id,
+        public_key: STANDARD.encode(recipient.public_key),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub owner_id: Uuid,
+    pub owner_email: String,
+    /// The folder key, wrapped for the requesting user's public key.
+    pub wrapped_key: String,
+}
+
+/// `GET /vaults`: the user's own vaults and those shared with them.
+pub async fn list(State(state): State<App>, user: User) -> Result<Json<Vec<VaultResponse>>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let vaults = sqlx::query!(
+        r#"
+        SELECT f.id, f.name, f.user_id AS owner_id, u.email AS owner_email, m.wrapped_key
+        FROM vault_members m
+        JOIN folders f ON f.id = m.folder_id
+        JOIN users u ON u.id = f.user_id
+        WHERE m.user_id = $1
+        ORDER BY lower(f.name), f.id
+        "#,
+        user_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        vaults
+            .into_iter()
+            .map(|vault| VaultResponse {
+                id: vault.id,
+                name: vault.name,
+                owner_id: vault.owner_id,
+                owner_email: vault.owner_email,
+                wrapped_key: STANDARD.encode(vault.wrapped_key),
+            })
+            .collect(),
+    ))
+}
+
+/// `GET /vaults/{id}`
+pub async fn get(
+    State(state): State<App>,
+    user: User,
+    Path(vault_id): Path<Uuid>,
+) -> Result<Json<VaultResponse>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let vault = sqlx::query!(
+        r#"
+        SELECT f.id, f.name, f.user_id AS owner_id, u.email AS owner_email, m.wrapped_key
+        FROM vault_members m
+        JOIN folders f ON f.id = m.folder_id
+        JOIN users u ON u.id = f.user_id
+        WHERE m.folder_id = $1 AND m.user_id = $2
+        "#,
+        vault_id,
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(VaultResponse {
+        id: vault.id,
+        name: vault.name,
+        owner_id: vault.owner_id,
+        owner_email: vault.owner_email,
+        wrapped_key: STANDARD.encode(vault.wrapped_key),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateVaultRequest {
+    /// The vault's own name is not encrypted.
+    pub name: String,
+    pub parent_id: Option<Uuid>,
+    /// A new folder key, wrapped for the user's own public key.
+    pub wrapped_key: String,
+}
+
+/// `POST /vaults`: creates a vault folder. Files are uploaded into it through
+/// `POST /upload` with their names and contents already encrypted.
+pub async fn create(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Json(input): Json<CreateVaultRequest>,
+) -> Result<(StatusCode, Json<VaultResponse>), Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let wrapped_key = decode("wrapped key", &input.wrapped_key)?;
+    let owner_email = sqlx::query_scalar!(
+        r#"
+        SELECT u.email FROM users u JOIN user_keys k ON k.user_id = u.id WHERE u.id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| Error::BadRequest("Set up a vault key pair first".to_string()))?;
+
+    let folder = folder::create(&state.db, user_id, input.name, input.parent_id, true).await?;
+    let added = add(&state.db, folder.id, user_id, &wrapped_key).await;
+    if let Err(err) = added {
+        // a vault nobody has the key for is of no use
+        let _ = folder::delete(&state.db, user_id, folder.id).await;
+        return Err(err);
+    }
+    let event = Event::vault("vault.create", folder.id)
+        .after(serde_json::json!({ "name": folder.name, "parent_id": folder.parent_id }));
+    audit::record(&state.db, user_id, &client, event).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(VaultResponse {
+            id: folder.id,
+            name: folder.name,
+            owner_id: user_id,
+            owner_email,
+            wrapped_key: input.wrapped_key,
+        }),
+    ))
+}
+
+async fn add(db: &PgPool, vault_id: Uuid, user_id: Uuid, wrapped_key: &[u8]) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO vault_members (folder_id, user_id, wrapped_key) VALUES ($1, $2, $3)
+        ON CONFLICT (folder_id, user_id) DO UPDATE SET wrapped_key = EXCLUDED.wrapped_key
+        "#,
+        vault_id,
+        user_id,
+        wrapped_key
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Returns the owner of a vault the user is a member of.
+async fn owner(db: &PgPool, vault_id: Uuid, user_id: Uuid) -> Result<Uuid, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT f.user_id FROM vault_members m JOIN folders f ON f.id = m.folder_id
+        WHERE m.folder_id = $1 AND m.user_id = $2
+        "#,
+        vault_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Only the owner of a vault can change who has access.
+async fn ensure_owner(db: &PgPool, vault_id: Uuid, user_id: Uuid) -> Result<(), Error> {
+    if owner(db, vault_id, user_id).await? != user_id {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    pub user_id: Uuid,
+    pub email: String,
+    pub added_at: NaiveDateTime,
+}
+
+/// `GET /vaults/{id}/members`
+pub async fn list_members(
+    State(state): State<App>,
+    user: User,
+    Path(vault_id): Path<Uuid>,
+) -> Result<Json<Vec<MemberResponse>>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    owner(&state.db, vault_id, user_id).await?;
+    let members = sqlx::query_as!(
+        MemberResponse,
+        r#"
+        SELECT m.user_id, u.email, m.added_at FROM vault_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.folder_id = $1
+        ORDER BY m.added_at
+        "#,
+        vault_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(members))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMemberRequest {
+    pub user_id: Uuid,
+    /// The folder key, wrapped for the new member's public key.
+    pub wrapped_key: String,
+}
+
+/// `POST /vaults/{id}/members`: shares a vault with another user (read-only).
+pub async fn add_member(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path(vault_id): Path<Uuid>,
+    Json(input): Json<AddMemberRequest>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    ensure_owner(&state.db, vault_id, user_id).await?;
+    let wrapped_key = decode("wrapped key", &input.wrapped_key)?;
+    let has_key = sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM user_keys WHERE user_id = $1
+        "#,
+        input.user_id
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    if has_key.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    add(&state.db, vault_id, input.user_id, &wrapped_key).await?;
+    let event = Event::vault("vault.share", vault_id)
+        .after(serde_json::json!({ "user_id": input.user_id }));
+    audit::record(&state.db, user_id, &client, event).await?;
+
+    Ok(StatusCode::CREATED)
+}
+
+/// `DELETE /vaults/{id}/members/{user_id}`
+///
+/// The removed member can no longer download the files, but may still hold the
+/// folder key; move the files to a new vault to cut them off completely.
+pub async fn remove_member(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path((vault_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, Error> {
+    user.require(Scope::Write)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    ensure_owner(&state.db, vault_id, user_id).await?;
+    if member_id == user_id {
+        return Err(Error::BadRequest(
+            "The owner cannot be removed from a vault".to_string(),
+        ));
+    }
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM vault_members WHERE folder_id = $1 AND user_id = $2
+        "#,
+        vault_id,
+        member_id
+    )
+    .execute(&state.db)
+    .await?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    let event =
+        Event::vault("vault.unshare", vault_id).before(serde_json::json!({ "user_id": member_id }));
+    audit::record(&state.db, user_id, &client, event).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultFile {
+    pub id: Uuid,
+    /// Encrypted with the folder key.
+    pub name: String,
+    /// Size of the ciphertext.
+    pub size: i64,
+    pub last_modified: Option<NaiveDateTime>,
+}
+
+/// `GET /vaults/{id}/files`, for all members of the vault.
+pub async fn list_files(
+    State(state): State<App>,
+    user: User,
+    Path(vault_id): Path<Uuid>,
+) -> Result<Json<Vec<VaultFile>>, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    owner(&state.db, vault_id, user_id).await?;
+    let files = sqlx::query_as!(
+        VaultFile,
+        r#"
+        SELECT id, filename AS name, size, last_modified FROM files
+        WHERE folder_id = $1
+        ORDER BY last_modified DESC, id
+        "#,
+        vault_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(files))
+}
+
+/// `GET /vaults/{id}/files/{file_id}`: the encrypted contents of a file, for all
+/// members of the vault.
+pub async fn download(
+    State(state): State<App>,
+    user: User,
+    client: Client,
+    Path((vault_id, file_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, Error> {
+    user.require(Scope::Read)?;
+    user.require_unrestricted()?;
+    let user_id = user.id;
+
+    let owner_id = owner(&state.db, vault_id, user_id).await?;
+    let in_vault = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM files WHERE id = $1 AND folder_id = $2
+        "#,
+        file_id,
+        vault_id
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    if in_vault.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    let blob = Blob::open(&state, owner_id, file_id).await?;
+    // members other than the owner get here through a share
+    let event = Event::file("file.download", file_id)
+        .after(serde_json::json!({ "vault_id": vault_id, "owner_id": owner_id }));
+    audit::record(&state.db, user_id, &client, event).await?;
+    let size = blob.size();
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            ),
+            (header::CONTENT_LENGTH, HeaderValue::from(size)),
+        ],
+        Body::from_stream(blob.into_stream(0..size)),
+    )
+        .into_response())
+}
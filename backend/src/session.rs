@@ -0,0 +1,123 @@
+//! Browser sessions: a random token in the `cloud_session` cookie, of which the
+//! database only keeps a hash.
+//!
+//! Sessions are created when logging in (see [`routes::login`](crate::routes::login))
+//! and picked up by the [`User`](crate::auth::User) extractor.
+
+use axum::http::{HeaderMap, HeaderValue, header};
+use uuid::Uuid;
+
+use crate::{auth, client::Client, config::SessionConfig};
+
+pub const COOKIE: &str = "cloud_session";
+/// Ties a single sign-on login to the browser that started it, holding its OAuth `state`.
+pub const LOGIN_COOKIE: &str = "cloud_login";
+
+/// The session token sent along with a request, if any.
+pub fn token(headers: &HeaderMap) -> Option<&str> {
+    cookie_value(headers, COOKIE)
+}
+
+/// The OAuth `state` of the single sign-on login this browser started, if any.
+pub fn login_state(headers: &HeaderMap) -> Option<&str> {
+    cookie_value(headers, LOGIN_COOKIE)
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, cookie: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == cookie)
+        .map(|(_, value)| value)
+}
+
+/// Starts a session for `user_id`, returning its token.
+pub async fn create(
+    db: &sqlx::PgPool,
+    config: &SessionConfig,
+    user_id: Uuid,
+    client: &Client,
+) -> Result<String, sqlx::Error> {
+    // a good time to forget old sessions, there's no other cleanup
+    sqlx::query!("DELETE FROM sessions WHERE expires_at < now()")
+        .execute(db)
+        .await?;
+
+    let token = auth::generate_secret();
+    let token_hash = auth::hash_token(&token);
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (user_id, token_hash, expires_at, ip, user_agent)
+        VALUES ($1, $2, now() + make_interval(secs => $3), $4::TEXT::INET, $5)
+        "#,
+        user_id,
+        token_hash.as_slice(),
+        config.lifetime.as_secs_f64(),
+        client.ip.map(|ip| ip.to_string()),
+        client.user_agent
+    )
+    .execute(db)
+    .await?;
+    Ok(token)
+}
+
+/// The user of an unexpired session, unless they were disabled.
+pub async fn authenticate(db: &sqlx::PgPool, token: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    let token_hash = auth::hash_token(token);
+    sqlx::query_scalar!(
+        r#"
+        SELECT s.user_id FROM sessions s JOIN users u ON u.id = s.user_id
+        WHERE s.token_hash = $1 AND s.expires_at > now() AND u.disabled_at IS NULL
+        "#,
+        token_hash.as_slice()
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Ends a session; unknown tokens are ignored.
+pub async fn delete(db: &sqlx::PgPool, token: &str) -> Result<(), sqlx::Error> {
+    let token_hash = auth::hash_token(token);
+    sqlx::query!(
+        "DELETE FROM sessions WHERE token_hash = $1",
+        token_hash.as_slice()
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// `Set-Cookie` value handing `token` to the browser.
+pub fn cookie(config: &SessionConfig, token: &str) -> HeaderValue {
+    set_cookie(config, COOKIE, token, config.lifetime.as_secs())
+}
+
+/// `Set-Cookie` value making the browser forget its session.
+pub fn clear_cookie(config: &SessionConfig) -> HeaderValue {
+    set_cookie(config, COOKIE, "", 0)
+}
+
+/// `Set-Cookie` value remembering the single sign-on login with `state` for `max_age` seconds.
+pub fn login_cookie(config: &SessionConfig, state: &str, max_age: u64) -> HeaderValue {
+    set_cookie(config, LOGIN_COOKIE, state, max_age)
+}
+
+/// `Set-Cookie` value making the browser forget its single sign-on login.
+pub fn clear_login_cookie(config: &SessionConfig) -> HeaderValue {
+    set_cookie(config, LOGIN_COOKIE, "", 0)
+}
+
+fn set_cookie(config: &SessionConfig, name: &str, value: &str, max_age: u64) -> HeaderValue {
+    // `Lax` still sends the cookie when the identity provider redirects back
+    let secure = if config.secure_cookies {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!("{name}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}")
+        .parse()
+        .expect("session tokens are valid header values")
+}
@@ -0,0 +1,816 @@
+//! Embedded SSH server exposing each user's folder tree over SFTP.
+//!
+//! Users log in with their email address and either their password or one of
+//! their registered public keys. All changes go through the same functions as
+//! the HTTP routes, so files uploaded over SFTP look exactly like web uploads.
+//!
+//! Only the user's own drive is served; team drives are reachable over HTTP.
+
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use russh::{
+    Channel, ChannelId,
+    keys::{Algorithm, HashAlg, PrivateKey, PublicKey, ssh_key::LineEnding},
+    server::{Auth, ChannelOpenHandle, Msg, Server as _, Session},
+};
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, Take};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    client::Client,
+    config::SftpConfig,
+    encryption::{Blob, CHUNK_SIZE},
+    integrity::{Checksum, Hasher},
+    ratelimit::{Class, Key},
+    routes::{
+        self, files, folder, login,
+        path::{self, Node},
+        quota, upload,
+    },
+    state::App,
+    totp,
+};
+
+pub async fn serve(state: App, config: SftpConfig) -> std::io::Result<()> {
+    let host_key = load_or_create_host_key(&config)?;
+
+    let ssh_config = russh::server::Config {
+        auth_rejection_time: Duration::from_secs(3),
+        auth_rejection_time_initial: Some(Duration::from_secs(0)),
+        inactivity_timeout: Some(Duration::from_secs(3600)),
+        keys: vec![host_key],
+        ..Default::default()
+    };
+
+    tracing::info!("SFTP server listening on {}", config.listen_addr);
+    Server { state }
+        .run_on_address(Arc::new(ssh_config), config.listen_addr.as_str())
+        .await
+}
+
+fn load_or_create_host_key(config: &SftpConfig) -> std::io::Result<PrivateKey> {
+    let path = &config.host_key_path;
+    if path.exists() {
+        return russh::keys::load_secret_key(path, None).map_err(std::io::Error::other);
+    }
+
+    tracing::info!("Generating new SFTP host key at {}", path.display());
+    let key =
+        PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519).map_err(std::io::Error::other)?;
+    key.write_openssh_file(path, LineEnding::LF)
+        .map_err(std::io::Error::other)?;
+    Ok(key)
+}
+
+#[derive(Clone)]
+struct Server {
+    state: App,
+}
+
+impl russh::server::Server for Server {
+    type Handler = SshSession;
+
+    fn new_client(&mut self, peer_addr: Option<SocketAddr>) -> SshSession {
+        tracing::debug!("New SSH connection from {:?}", peer_addr);
+        SshSession {
+            state: self.state.clone(),
+            ip: peer_addr.map(|addr| addr.ip()),
+            user_id: None,
+            channels: HashMap::new(),
+        }
+    }
+}
+
+struct SshSession {
+    state: App,
+    ip: Option<IpAddr>,
+    /// Set once the client has authenticated.
+    user_id: Option<Uuid>,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl SshSession {
+    fn accept(&mut self, user_id: Option<Uuid>) -> Auth {
+        match user_id {
+            Some(user_id) => {
+                self.user_id = Some(user_id);
+                Auth::Accept
+            }
+            None => Auth::reject(),
+        }
+    }
+
+    /// Counts a login attempt for `user`; guessing passwords or keys costs from
+    /// the same budget as logging in over HTTP.
+    fn throttled(&self, user: &str) -> bool {
+        let mut keys: Vec<Key> = self.ip.map(Key::Ip).into_iter().collect();
+        keys.push(Key::login(user));
+        let throttled = self.state.limits.check(Class::Auth, &keys).is_err();
+        if throttled {
+            tracing::warn!("Too many SFTP login attempts for {user} from {:?}", self.ip);
+        }
+        throttled
+    }
+}
+
+impl russh::server::Handler for SshSession {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        if self.throttled(user) {
+            return Ok(Auth::reject());
+        }
+
+        // checked against the LDAP directory too, if there is one
+        let client = Client {
+            ip: self.ip,
+            user_agent: None,
+        };
+        let user_id = login::check_password(&self.state, user, password, &client)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::info!("SFTP password login of {user} failed: {err:?}");
+                None
+            });
+
+        // there is no room for a code, the password alone isn't enough
+        if let Some(user_id) = user_id {
+            let second_factor = match totp::enabled(&self.state.db, user_id).await {
+                Ok(true) => Ok(true),
+                Ok(false) => totp::required(&self.state.db, &self.state.totp, user_id).await,
+                Err(err) => Err(err.into()),
+            };
+            if !matches!(second_factor, Ok(false)) {
+                tracing::info!("Rejected SFTP password login of {user}, who uses two factors");
+                return Ok(Auth::reject());
+            }
+        }
+
+        Ok(self.accept(user_id))
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        if self.throttled(user) {
+            return Ok(Auth::reject());
+        }
+
+        let fingerprint = public_key.fingerprint(HashAlg::Sha256).to_string();
+        let user_id = sqlx::query_scalar!(
+            r#"
+            SELECT u.id FROM ssh_keys k
+            JOIN users u ON u.id = k.user_id
+            WHERE lower(u.email) = lower($1) AND k.fingerprint = $2 AND u.disabled_at IS NULL
+            "#,
+            user,
+            fingerprint
+        )
+        .fetch_optional(&self.state.db)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("Failed to look up SFTP key: {err}");
+            None
+        });
+
+        Ok(self.accept(user_id))
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        reply.accept().await;
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.close(channel)?;
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let (Some(user_id), "sftp") = (self.user_id, name) else {
+            session.channel_failure(channel_id)?;
+            return Ok(());
+        };
+        let Some(channel) = self.channels.remove(&channel_id) else {
+            session.channel_failure(channel_id)?;
+            return Ok(());
+        };
+
+        session.channel_success(channel_id)?;
+        let sftp = SftpSession {
+            state: self.state.clone(),
+            user_id,
+            // the client's version string, e.g. `SSH-2.0-OpenSSH_9.6`
+            client: Client {
+                ip: self.ip,
+                user_agent: Some(String::from_utf8_lossy(session.remote_sshid()).into_owned()),
+            },
+            handles: HashMap::new(),
+            next_handle: 0,
+        };
+        tokio::spawn(russh_sftp::server::run(channel.into_stream(), sftp));
+
+        Ok(())
+    }
+}
+
+enum OpenHandle {
+    /// Directory entries, taken on the first `readdir`.
+    Dir(Option<Vec<File>>),
+    Read {
+        // the key schedule of encrypted blobs is large
+        blob: Box<Blob>,
+        attrs: FileAttributes,
+    },
+    /// Uploads are spooled to disk and stored through [`upload::store_file`] on close,
+    /// or [`upload::replace_file`] if they overwrite a file.
+    Write {
+        folder_id: Uuid,
+        filename: String,
+        replaces: Option<Uuid>,
+        spool: Spool,
+    },
+}
+
+/// The contents of an upload received so far, in `<upload_dir>/<drive_id>/<id>.part`.
+///
+/// Removed when dropped; if the server dies first, `fsck` finds it as an orphan.
+struct Spool {
+    path: PathBuf,
+    file: tokio::fs::File,
+    len: u64,
+    /// What was left of the quota when the file was opened, `None` is unlimited.
+    limit: Option<u64>,
+    /// A write was rejected, the file is not stored on close.
+    failed: bool,
+}
+
+impl Spool {
+    /// `reclaimed` is the size of the file the upload replaces, if any.
+    async fn create(state: &App, user_id: Uuid, reclaimed: u64) -> Result<Self, StatusCode> {
+        let usage = quota::usage(&state.db, &state.uploads, user_id)
+            .await
+            .map_err(api_error)?;
+        let path = state
+            .blob_path(user_id, Uuid::new_v4())
+            .with_extension("part");
+        if let Some(user_dir) = path.parent() {
+            tokio::fs::create_dir_all(user_dir)
+                .await
+                .map_err(io_error)?;
+        }
+        let file = tokio::fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .map_err(io_error)?;
+
+        Ok(Self {
+            path,
+            file,
+            len: 0,
+            failed: false,
+            limit: usage
+                .available
+                .map(|available| (available + reclaimed as i64).max(0) as u64),
+        })
+    }
+
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), StatusCode> {
+        let written = self.try_write_at(offset, data).await;
+        self.failed |= written.is_err();
+        written
+    }
+
+    async fn try_write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), StatusCode> {
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or(StatusCode::BadMessage)?;
+        // no holes: a single write far past the end would claim any amount of disk
+        if offset > self.len {
+            return Err(StatusCode::BadMessage);
+        }
+        if self.limit.is_some_and(|limit| end > limit) {
+            tracing::info!("SFTP upload exceeds the quota");
+            return Err(StatusCode::Failure);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(io_error)?;
+        self.file.write_all(data).await.map_err(io_error)?;
+        self.len = self.len.max(end);
+        Ok(())
+    }
+
+    /// The SHA-256 of what was received and a reader for it, read from disk a chunk
+    /// at a time; writes may come in any order, so the file is hashed as a whole.
+    async fn contents(&mut self) -> Result<(Checksum, Take<&mut tokio::fs::File>), StatusCode> {
+        if self.failed {
+            return Err(StatusCode::Failure);
+        }
+        self.file.flush().await.map_err(io_error)?;
+
+        self.file.rewind().await.map_err(io_error)?;
+        let mut hasher = Hasher::default();
+        let mut chunk = vec![0; CHUNK_SIZE as usize];
+        let mut reader = (&mut self.file).take(self.len);
+        loop {
+            match reader.read(&mut chunk).await.map_err(io_error)? {
+                0 => break,
+                read => hasher.update(&chunk[..read]),
+            }
+        }
+
+        self.file.rewind().await.map_err(io_error)?;
+        Ok((hasher.finish(), (&mut self.file).take(self.len)))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct SftpSession {
+    state: App,
+    user_id: Uuid,
+    client: Client,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+impl SftpSession {
+    async fn record(&self, event: Event) -> Result<(), StatusCode> {
+        audit::record(&self.state.db, self.user_id, &self.client, event)
+            .await
+            .map_err(db_error)
+    }
+
+    fn insert_handle(&mut self, handle: OpenHandle) -> String {
+        self.next_handle += 1;
+        let key = self.next_handle.to_string();
+        self.handles.insert(key.clone(), handle);
+        key
+    }
+
+    async fn resolve(&self, path: &str) -> Result<Node, StatusCode> {
+        path::resolve(&self.state.db, self.user_id, &path::split(path))
+            .await
+            .map_err(api_error)
+    }
+
+    async fn file_attrs(&self, file_id: Uuid) -> Result<FileAttributes, StatusCode> {
+        let file = sqlx::query!(
+            r#"
+            SELECT size, last_modified FROM files WHERE id = $1 AND user_id = $2
+            "#,
+            file_id,
+            self.user_id
+        )
+        .fetch_optional(&self.state.db)
+        .await
+        .map_err(db_error)?
+        .ok_or(StatusCode::NoSuchFile)?;
+
+        Ok(file_attrs(file.size, file.last_modified))
+    }
+
+    /// Resolves the parent of `path` to a folder and returns it with the last path component.
+    async fn resolve_parent(&self, path: &str) -> Result<(Uuid, String), StatusCode> {
+        let mut components = path::split(path);
+        let name = components.pop().ok_or(StatusCode::PermissionDenied)?;
+        let node = path::resolve(&self.state.db, self.user_id, &components)
+            .await
+            .map_err(api_error)?;
+        match node {
+            Node::Folder(id) => Ok((id, name)),
+            Node::File(_) => Err(StatusCode::NoSuchFile),
+        }
+    }
+
+    async fn list(&self, folder_id: Uuid) -> Result<Vec<File>, StatusCode> {
+        let folders = sqlx::query_scalar!(
+            r#"
+            SELECT name FROM folders
+            WHERE user_id = $1 AND parent_id = $2
+            ORDER BY name
+            "#,
+            self.user_id,
+            folder_id
+        )
+        .fetch_all(&self.state.db)
+        .await
+        .map_err(db_error)?;
+
+        let files = sqlx::query!(
+            r#"
+            SELECT filename, size, last_modified FROM files
+            WHERE user_id = $1 AND folder_id = $2
+            ORDER BY filename
+            "#,
+            self.user_id,
+            folder_id
+        )
+        .fetch_all(&self.state.db)
+        .await
+        .map_err(db_error)?;
+
+        let mut entries = vec![File::new(".", dir_attrs()), File::new("..", dir_attrs())];
+        entries.extend(folders.into_iter().map(|name| File::new(name, dir_attrs())));
+        entries.extend(
+            files
+                .into_iter()
+                .map(|f| File::new(f.filename, file_attrs(f.size, f.last_modified))),
+        );
+        Ok(entries)
+    }
+}
+
+impl russh_sftp::server::Handler for SftpSession {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        Ok(Name {
+            id,
+            files: vec![File::dummy(format!("/{}", path::split(&path).join("/")))],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let attrs = match self.resolve(&path).await? {
+            Node::Folder(_) => dir_attrs(),
+            Node::File(file_id) => self.file_attrs(file_id).await?,
+        };
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat(id, path).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let attrs = match self.handles.get(&handle) {
+            Some(OpenHandle::Dir(_)) => dir_attrs(),
+            Some(OpenHandle::Read { attrs, .. }) => attrs.clone(),
+            Some(OpenHandle::Write { spool, .. }) => file_attrs(spool.len as i64, None),
+            None => return Err(StatusCode::Failure),
+        };
+        Ok(Attrs { id, attrs })
+    }
+
+    // Timestamps and permissions are managed by the server; accept and ignore
+    // changes so clients that set them after an upload don't report errors.
+    async fn setstat(
+        &mut self,
+        id: u32,
+        _path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        _handle: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let folder_id = match self.resolve(&path).await? {
+            Node::Folder(id) => id,
+            Node::File(_) => return Err(StatusCode::Failure),
+        };
+        let entries = self.list(folder_id).await?;
+        let handle = self.insert_handle(OpenHandle::Dir(Some(entries)));
+        Ok(Handle { id, handle })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir(entries)) => match entries.take() {
+                Some(files) => Ok(Name { id, files }),
+                None => Err(StatusCode::Eof),
+            },
+            _ => Err(StatusCode::Failure),
+        }
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let handle = if pflags.contains(OpenFlags::WRITE) {
+            if pflags.contains(OpenFlags::APPEND) {
+                return Err(StatusCode::OpUnsupported);
+            }
+            // existing files are only ever replaced as a whole, like `put` does
+            let replaces = match self.resolve(&filename).await {
+                Ok(Node::File(_)) if pflags.contains(OpenFlags::EXCLUDE) => {
+                    return Err(StatusCode::Failure);
+                }
+                Ok(Node::File(file_id)) if pflags.contains(OpenFlags::TRUNCATE) => Some(file_id),
+                Ok(Node::File(_)) => return Err(StatusCode::OpUnsupported),
+                Ok(Node::Folder(_)) => return Err(StatusCode::Failure),
+                Err(StatusCode::NoSuchFile) if pflags.contains(OpenFlags::CREATE) => None,
+                Err(err) => return Err(err),
+            };
+            let reclaimed = match replaces {
+                Some(file_id) => self.file_attrs(file_id).await?.size.unwrap_or_default(),
+                None => 0,
+            };
+            let (folder_id, filename) = self.resolve_parent(&filename).await?;
+            OpenHandle::Write {
+                folder_id,
+                filename,
+                replaces,
+                spool: Spool::create(&self.state, self.user_id, reclaimed).await?,
+            }
+        } else {
+            let Node::File(file_id) = self.resolve(&filename).await? else {
+                return Err(StatusCode::Failure);
+            };
+            let attrs = self.file_attrs(file_id).await?;
+            let blob = Blob::open(&self.state, self.user_id, file_id)
+                .await
+                .map_err(io_error)?;
+            let event = Event::file("file.download", file_id)
+                .after(serde_json::json!({ "path": filename }));
+            self.record(event).await?;
+            OpenHandle::Read {
+                blob: Box::new(blob),
+                attrs,
+            }
+        };
+
+        let handle = self.insert_handle(handle);
+        Ok(Handle { id, handle })
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let Some(OpenHandle::Read { blob, .. }) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+
+        let data = blob.read_at(offset, len.into()).await.map_err(io_error)?;
+        if data.is_empty() {
+            return Err(StatusCode::Eof);
+        }
+
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let Some(OpenHandle::Write { spool, .. }) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+
+        spool.write_at(offset, &data).await?;
+        Ok(ok(id))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        if let Some(OpenHandle::Write {
+            folder_id,
+            filename,
+            replaces,
+            mut spool,
+        }) = self.handles.remove(&handle)
+        {
+            let size = spool.len;
+            let (checksum, contents) = spool.contents().await?;
+            let stored = match replaces {
+                Some(file_id) => {
+                    upload::replace_file(
+                        &self.state,
+                        self.user_id,
+                        file_id,
+                        contents,
+                        size,
+                        checksum,
+                    )
+                    .await
+                }
+                None => {
+                    upload::store_file(
+                        &self.state,
+                        self.user_id,
+                        Some(folder_id),
+                        &filename,
+                        contents,
+                        size,
+                        checksum,
+                    )
+                    .await
+                }
+            };
+            let file = stored.map_err(api_error)?;
+            let action = match replaces {
+                Some(_) => "file.replace",
+                None => "file.upload",
+            };
+            self.record(Event::file(action, file.id).after(&file))
+                .await?;
+        }
+
+        Ok(ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let (parent_id, name) = self.resolve_parent(&path).await?;
+        let folder = folder::create(&self.state.db, self.user_id, name, Some(parent_id), false)
+            .await
+            .map_err(api_error)?;
+        let event = Event::folder("folder.create", folder.id)
+            .after(serde_json::json!({ "name": folder.name, "parent_id": folder.parent_id }));
+        self.record(event).await?;
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let Node::Folder(folder_id) = self.resolve(&path).await? else {
+            return Err(StatusCode::Failure);
+        };
+        let deleted = folder::delete(&self.state.db, self.user_id, folder_id)
+            .await
+            .map_err(api_error)?;
+        self.state.jobs.notify_one();
+        self.record(Event::folder("folder.delete", folder_id).before(deleted))
+            .await?;
+        Ok(ok(id))
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        let Node::File(file_id) = self.resolve(&filename).await? else {
+            return Err(StatusCode::Failure);
+        };
+        let deleted = files::delete(&self.state, self.user_id, file_id)
+            .await
+            .map_err(api_error)?;
+        self.record(Event::file("file.delete", file_id).before(deleted))
+            .await?;
+        Ok(ok(id))
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        let (old_parent_id, old_name) = self.resolve_parent(&oldpath).await?;
+        let (new_parent_id, new_name) = self.resolve_parent(&newpath).await?;
+
+        match self.resolve(&oldpath).await? {
+            Node::Folder(folder_id) => {
+                folder::relocate(
+                    &self.state.db,
+                    self.user_id,
+                    folder_id,
+                    new_parent_id,
+                    &new_name,
+                )
+                .await
+                .map_err(api_error)?;
+                if old_parent_id != new_parent_id {
+                    let event = Event::folder("folder.move", folder_id)
+                        .before(serde_json::json!({ "parent_id": old_parent_id }))
+                        .after(serde_json::json!({ "parent_id": new_parent_id }));
+                    self.record(event).await?;
+                }
+                if old_name != new_name {
+                    let event = Event::folder("folder.rename", folder_id)
+                        .before(serde_json::json!({ "name": old_name }))
+                        .after(serde_json::json!({ "name": new_name }));
+                    self.record(event).await?;
+                }
+            }
+            Node::File(file_id) => {
+                let old = files::rename(
+                    &self.state.db,
+                    self.user_id,
+                    file_id,
+                    new_parent_id,
+                    &new_name,
+                )
+                .await
+                .map_err(api_error)?;
+                let event = Event::file("file.rename", file_id)
+                    .before(old)
+                    .after(serde_json::json!({ "filename": new_name, "folder_id": new_parent_id }));
+                self.record(event).await?;
+            }
+        }
+
+        Ok(ok(id))
+    }
+}
+
+fn dir_attrs() -> FileAttributes {
+    FileAttributes {
+        permissions: Some(0o40755),
+        ..Default::default()
+    }
+}
+
+fn file_attrs(size: i64, last_modified: Option<NaiveDateTime>) -> FileAttributes {
+    let mtime = last_modified.map(|t| t.and_utc().timestamp() as u32);
+    FileAttributes {
+        size: Some(size as u64),
+        permissions: Some(0o100644),
+        atime: mtime,
+        mtime,
+        ..Default::default()
+    }
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn api_error(err: routes::Error) -> StatusCode {
+    match err {
+        routes::Error::NotFound => StatusCode::NoSuchFile,
+        routes::Error::Io(err) => io_error(err),
+        routes::Error::Database(err) => db_error(err),
+        _ => StatusCode::Failure,
+    }
+}
+
+fn io_error(err: std::io::Error) -> StatusCode {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        return StatusCode::NoSuchFile;
+    }
+    tracing::error!("SFTP I/O error: {err}");
+    StatusCode::Failure
+}
+
+fn db_error(err: sqlx::Error) -> StatusCode {
+    tracing::error!("SFTP database error: {err}");
+    StatusCode::Failure
+}
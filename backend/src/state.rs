@@ -1,14 +1,82 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, SessionConfig, TotpConfig, UploadConfig},
+    encryption::MasterKeys,
+    ldap::Directory,
+    metrics::Metrics,
+    oidc::Oidc,
+    ratelimit::RateLimiter,
+};
 
 pub type App = Arc<AppState>;
 
 pub struct AppState {
     pub upload_dir: String,
+    pub uploads: UploadConfig,
     pub db: sqlx::PgPool,
+    pub keys: MasterKeys,
+    /// See [`Config::trust_proxy`](crate::config::Config::trust_proxy).
+    pub trust_proxy: bool,
+    /// Wakes up the background [indexer](crate::indexer) after files were added.
+    pub indexer: Notify,
+    /// Wakes up the background [thumbnailer](crate::thumbnails) after files were added.
+    pub thumbnailer: Notify,
+    /// Wakes up the background [transcoder](crate::video) after videos were added.
+    pub transcoder: Notify,
+    /// Wakes up the [job runner](crate::jobs) after jobs were enqueued.
+    pub jobs: Notify,
+    pub metrics: Metrics,
+    pub limits: RateLimiter,
+    pub sessions: SessionConfig,
+    pub totp: TotpConfig,
+    /// `None` unless single sign-on is configured.
+    pub oidc: Option<Oidc>,
+    /// `None` unless an LDAP directory is configured.
+    pub ldap: Option<Directory>,
 }
 
 impl AppState {
-    pub fn new(upload_dir: String, db: sqlx::PgPool) -> Self {
-        Self { upload_dir, db }
+    pub fn new(config: &Config, db: sqlx::PgPool) -> Self {
+        Self {
+            upload_dir: config.upload_dir.clone(),
+            uploads: config.uploads.clone(),
+            db,
+            keys: MasterKeys::new(&config.master_keys),
+            trust_proxy: config.trust_proxy,
+            indexer: Notify::new(),
+            thumbnailer: Notify::new(),
+            transcoder: Notify::new(),
+            jobs: Notify::new(),
+            metrics: Metrics::default(),
+            limits: RateLimiter::new(config.rate_limits.clone()),
+            sessions: config.sessions.clone(),
+            totp: config.totp.clone(),
+            oidc: config.oidc.clone().map(Oidc::new),
+            ldap: config.ldap.clone().map(Directory::new),
+        }
+    }
+
+    /// Location of a file's contents on disk: `<upload_dir>/<drive_id>/<file_id>`.
+    ///
+    /// The contents may be encrypted, read them through an [`encryption::Blob`](crate::encryption::Blob).
+    pub fn blob_path(&self, drive_id: Uuid, file_id: Uuid) -> PathBuf {
+        PathBuf::from(&self.upload_dir)
+            .join(drive_id.to_string())
+            .join(file_id.to_string())
+    }
+
+    /// Location of a file's thumbnail, next to its contents: `<upload_dir>/<drive_id>/<file_id>.<size>.jpg`.
+    pub fn thumbnail_path(&self, drive_id: Uuid, file_id: Uuid, size: u32) -> PathBuf {
+        self.blob_path(drive_id, file_id)
+            .with_extension(format!("{size}.jpg"))
+    }
+
+    /// Directory with a video's HLS playlists, segments and poster: `<upload_dir>/<drive_id>/<file_id>.hls`.
+    pub fn hls_dir(&self, drive_id: Uuid, file_id: Uuid) -> PathBuf {
+        self.blob_path(drive_id, file_id).with_extension("hls")
     }
 }
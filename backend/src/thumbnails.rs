@@ -0,0 +1,204 @@
+use std::{io::Cursor, path::Path, time::Duration};
+
+use image::{DynamicImage, ImageDecoder, ImageReader, RgbImage, codecs::jpeg::JpegEncoder};
+use uuid::Uuid;
+
+use crate::{
+    encryption::{Blob, PlainFile},
+    photos,
+    state::App,
+};
+
+/// Edge lengths (in pixels) thumbnails are generated with; requests are served
+/// from the smallest one that is at least as large as asked for.
+pub const SIZES: [u32; 3] = [128, 256, 512];
+
+/// Files picked up per round.
+const BATCH_SIZE: i64 = 10;
+/// Larger files are not decoded at all.
+const MAX_FILE_SIZE: i64 = 100 * 1024 * 1024;
+/// How often to look for work when no upload wakes the thumbnailer up.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+const JPEG_QUALITY: u8 = 80;
+
+/// Generates thumbnails for images and first-page previews for PDFs, and records
+/// the [EXIF metadata](crate::photos) of images while they are decoded anyway.
+///
+/// Files are pending while `thumbnailed_at` is NULL. Uploads wake the thumbnailer
+/// up through `AppState::thumbnailer`. PDFs are rendered with `pdftoppm` (poppler),
+/// and simply get no preview when it is not installed.
+pub async fn run(state: App, pdftoppm: String) {
+    loop {
+        match generate_pending(&state, &pdftoppm).await {
+            // there may be more waiting
+            Ok(n) if n as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(err) => tracing::error!("Thumbnailer failed: {err}"),
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, state.thumbnailer.notified()).await;
+    }
+}
+
+async fn generate_pending(state: &App, pdftoppm: &str) -> Result<usize, sqlx::Error> {
+    let pending = sqlx::query!(
+        r#"
+        SELECT id, user_id, filename, size FROM files
+        WHERE thumbnailed_at IS NULL
+        ORDER BY last_modified
+        LIMIT $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    for file in &pending {
+        let generated = if file.size <= MAX_FILE_SIZE {
+            match generate(state, pdftoppm, file.user_id, file.id, &file.filename).await {
+                Ok(generated) => generated,
+                Err(err) => {
+                    tracing::warn!("Could not generate a thumbnail for {}: {err}", file.id);
+                    false
+                }
+            }
+        } else {
+            false
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE files SET thumbnailed_at = now(), has_thumbnail = $1 WHERE id = $2
+            "#,
+            generated,
+            file.id
+        )
+        .execute(&state.db)
+        .await?;
+    }
+
+    Ok(pending.len())
+}
+
+/// Writes all [`SIZES`] of a file's thumbnail; returns `false` for unsupported formats.
+async fn generate(
+    state: &App,
+    pdftoppm: &str,
+    drive_id: Uuid,
+    file_id: Uuid,
+    filename: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+
+    let image = match extension.as_str() {
+        "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "tif" | "tiff" => {
+            let data = Blob::open(state, drive_id, file_id)
+                .await?
+                .read_all()
+                .await?;
+            let (image, exif) = tokio::task::spawn_blocking(move || {
+                Ok::<_, image::ImageError>((decode(&data)?, photos::read_exif(&data)))
+            })
+            .await??;
+            photos::store(
+                &state.db,
+                drive_id,
+                file_id,
+                exif,
+                image.width(),
+                image.height(),
+            )
+            .await?;
+            image
+        }
+        "pdf" => {
+            let plain = PlainFile::new(state, drive_id, file_id).await?;
+            let prefix = state.blob_path(drive_id, file_id).with_extension("preview");
+            let Some(image) = render_pdf(pdftoppm, plain.path(), &prefix).await? else {
+                return Ok(false);
+            };
+            image
+        }
+        _ => return Ok(false),
+    };
+
+    let paths: Vec<_> = SIZES
+        .iter()
+        .map(|&size| (size, state.thumbnail_path(drive_id, file_id, size)))
+        .collect();
+    tokio::task::spawn_blocking(move || {
+        for (size, path) in paths {
+            let thumbnail = flatten(image.thumbnail(size, size));
+            let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+            thumbnail.write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?;
+        }
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    })
+    .await??;
+
+    Ok(true)
+}
+
+/// Decodes an image, turned upright according to its EXIF orientation.
+fn decode(data: &[u8]) -> Result<DynamicImage, image::ImageError> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Renders the first page of a PDF, or returns `None` if `pdftoppm` is not available.
+///
+/// The page is rendered to `<prefix>.png`, which is removed again afterwards.
+async fn render_pdf(
+    pdftoppm: &str,
+    pdf: &Path,
+    prefix: &Path,
+) -> Result<Option<DynamicImage>, Box<dyn std::error::Error + Send + Sync>> {
+    let output = tokio::process::Command::new(pdftoppm)
+        .args(["-f", "1", "-l", "1", "-singlefile", "-png", "-scale-to"])
+        .arg(SIZES[SIZES.len() - 1].to_string())
+        .arg(pdf)
+        .arg(prefix)
+        .output()
+        .await;
+
+    match output {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!("{pdftoppm} is not installed, skipping PDF preview");
+            return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+        Ok(output) if !output.status.success() => {
+            return Err(String::from_utf8_lossy(&output.stderr).into_owned().into());
+        }
+        Ok(_) => {}
+    }
+
+    let png = prefix.with_extension("png");
+    let image = tokio::task::spawn_blocking({
+        let png = png.clone();
+        move || image::open(png)
+    })
+    .await?;
+    let _ = tokio::fs::remove_file(&png).await;
+    Ok(Some(image?))
+}
+
+/// Drops the alpha channel by drawing the image onto a white background (JPEG has no transparency).
+fn flatten(image: DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
@@ -0,0 +1,485 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238), as
+//! shown by authenticator apps, and one-time recovery codes for when the app is
+//! lost.
+//!
+//! Password logins ask for a code once TOTP is enabled, see
+//! [`routes::login`](crate::routes::login). Logins through the identity
+//! provider leave second factors to the provider.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    config::{TotpConfig, TotpPolicy},
+    routes::Error,
+};
+
+/// Seconds a code is valid for; what authenticator apps assume.
+const STEP: u64 = 30;
+const DIGITS: usize = 6;
+/// Codes from this many steps before or after now are accepted, for clocks
+/// that are a little off.
+const SKEW: u64 = 1;
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODES: usize = 10;
+/// Characters of a recovery code, without the dashes.
+const RECOVERY_CODE_LEN: usize = 16;
+/// Wrong codes in a row after which a user's second factor is locked.
+const MAX_FAILURES: i32 = 10;
+/// How long the second factor stays locked.
+const LOCKOUT_SECS: f64 = 15.0 * 60.0;
+
+/// How a second factor was proven.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Factor {
+    Totp,
+    RecoveryCode,
+}
+
+/// What an authenticator app needs to be set up.
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    /// Base32, for typing into the app.
+    pub secret: String,
+    /// `otpauth://` URI, to be shown as a QR code.
+    pub uri: String,
+}
+
+/// Whether the user must use TOTP: their own setting, or else the server's policy.
+pub async fn required(db: &PgPool, config: &TotpConfig, user_id: Uuid) -> Result<bool, Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT totp_required, is_admin FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::NotFound)?;
+    Ok(user.totp_required.unwrap_or(match config.required {
+        TotpPolicy::Nobody => false,
+        TotpPolicy::Admins => user.is_admin,
+        TotpPolicy::Everyone => true,
+    }))
+}
+
+/// Whether the user has set up TOTP.
+pub async fn enabled(db: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL
+        ) AS "enabled!"
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Starts setting up TOTP with a new secret, replacing an unconfirmed one. It is
+/// only used once [`confirm`]ed.
+pub async fn enroll(db: &PgPool, config: &TotpConfig, user_id: Uuid) -> Result<Enrollment, Error> {
+    let secret: [u8; SECRET_LEN] = rand::random();
+    let email = sqlx::query_scalar!(
+        r#"
+        INSERT INTO user_totp (user_id, secret)
+        SELECT id, $2 FROM users WHERE id = $1
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, created_at = now()
+        WHERE user_totp.confirmed_at IS NULL
+        RETURNING (SELECT email FROM users WHERE id = $1) AS "email!"
+        "#,
+        user_id,
+        &secret[..]
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::Conflict(
+        "Two-factor authentication is already enabled",
+    ))?;
+
+    let totp = totp(secret.to_vec(), Some(config.issuer.clone()), email);
+    Ok(Enrollment {
+        secret: totp.get_secret_base32(),
+        uri: totp.get_url(),
+    })
+}
+
+/// Enables TOTP once the user entered a code from the app, returning fresh
+/// recovery codes.
+pub async fn confirm(db: &PgPool, user_id: Uuid, code: &str) -> Result<Vec<String>, Error> {
+    let mut tx = db.begin().await?;
+    let secret = sqlx::query_scalar!(
+        r#"
+        SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NULL FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::Conflict("Set up two-factor authentication first"))?;
+    let step = matching_step(&secret, code).ok_or(Error::Unauthorized)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_totp SET confirmed_at = now(), last_step = $2 WHERE user_id = $1
+        "#,
+        user_id,
+        step
+    )
+    .execute(&mut *tx)
+    .await?;
+    let codes = replace_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+    Ok(codes)
+}
+
+/// Checks a code from the app or an unused recovery code, which is used up.
+/// [`MAX_FAILURES`] wrong codes in a row lock the user out for a while.
+pub async fn verify(db: &PgPool, user_id: Uuid, code: &str) -> Result<Option<Factor>, Error> {
+    let locked = sqlx::query_scalar!(
+        r#"
+        SELECT EXTRACT(EPOCH FROM totp_locked_until - now())::FLOAT8 AS "seconds!" FROM users
+        WHERE id = $1 AND totp_locked_until > now()
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+    if let Some(seconds) = locked {
+        return Err(Error::TooManyRequests(Duration::from_secs_f64(seconds)));
+    }
+
+    let factor = check(db, user_id, code).await?;
+    // a lockout starts over with a clean count
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_failures = CASE WHEN $2 AND totp_failures + 1 < $3 THEN totp_failures + 1 ELSE 0 END,
+            totp_locked_until = CASE WHEN $2 AND totp_failures + 1 >= $3
+                THEN now() + make_interval(secs => $4) ELSE totp_locked_until END
+        WHERE id = $1
+        "#,
+        user_id,
+        factor.is_none(),
+        MAX_FAILURES,
+        LOCKOUT_SECS
+    )
+    .execute(db)
+    .await?;
+    Ok(factor)
+}
+
+/// [`verify`], without counting failures.
+async fn check(db: &PgPool, user_id: Uuid, code: &str) -> Result<Option<Factor>, Error> {
+    let code = code.trim();
+    if code.len() == DIGITS {
+        let secret = sqlx::query_scalar!(
+            r#"
+            SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL
+            "#,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?;
+        let Some(step) = secret.and_then(|secret| matching_step(&secret, code)) else {
+            return Ok(None);
+        };
+        // a code that was seen before might have been watched being typed
+        let fresh = sqlx::query!(
+            r#"
+            UPDATE user_totp SET last_step = $2
+            WHERE user_id = $1 AND (last_step IS NULL OR last_step < $2)
+            "#,
+            user_id,
+            step
+        )
+        .execute(db)
+        .await?
+        .rows_affected()
+            == 1;
+        return Ok(fresh.then_some(Factor::Totp));
+    }
+
+    let code_hash = auth::hash_token(&normalize_recovery_code(code));
+    let used = sqlx::query!(
+        r#"
+        UPDATE recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        code_hash.as_slice()
+    )
+    .execute(db)
+    .await?
+    .rows_affected()
+        > 0;
+    Ok(used.then_some(Factor::RecoveryCode))
+}
+
+/// Turns TOTP off and forgets the recovery codes; returns whether it was set up.
+pub async fn disable(db: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let removed = sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        > 0;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(removed)
+}
+
+/// New recovery codes, invalidating the old ones.
+pub async fn regenerate_recovery_codes(db: &PgPool, user_id: Uuid) -> Result<Vec<String>, Error> {
+    let mut tx = db.begin().await?;
+    let codes = replace_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+    Ok(codes)
+}
+
+pub async fn recovery_codes_left(db: &PgPool, user_id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+async fn replace_recovery_codes(
+    tx: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+    let hashes: Vec<Vec<u8>> = codes
+        .iter()
+        .map(|code| auth::hash_token(&normalize_recovery_code(code)).to_vec())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::BYTEA[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(codes)
+}
+
+fn totp(secret: Vec<u8>, issuer: Option<String>, account_name: String) -> TOTP {
+    TOTP::new_unchecked(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP,
+        secret,
+        issuer,
+        account_name,
+    )
+}
+
+/// The time step `code` belongs to, if it is valid around now.
+fn matching_step(secret: &[u8], code: &str) -> Option<i64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    matching_step_at(secret, code, now)
+}
+
+/// The time step `code` belongs to, if it is valid around `time` (in Unix seconds).
+fn matching_step_at(secret: &[u8], code: &str, time: u64) -> Option<i64> {
+    let totp = totp(secret.to_vec(), None, String::new());
+    let now = time / STEP;
+    (now.saturating_sub(SKEW)..=now + SKEW)
+        .find(|step| totp.check(code, step * STEP))
+        .map(|step| step as i64)
+}
+
+/// Like `abcd-efgh-2345-6789`, from the base32 alphabet.
+fn recovery_code() -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let bytes: [u8; RECOVERY_CODE_LEN] = rand::random();
+    let chars: Vec<char> = bytes
+        .iter()
+        .map(|byte| ALPHABET[(byte & 31) as usize] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Recovery codes are accepted with or without dashes, in any case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA1 secret of the RFC 6238 test vectors.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_at(secret: &[u8], time: u64) -> String {
+        totp(secret.to_vec(), None, String::new()).generate(time)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    async fn user_with_totp(db: &PgPool, secret: &[u8]) -> Uuid {
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (email) VALUES ('totp@example.com') RETURNING id"
+        )
+        .fetch_one(db)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO user_totp (user_id, secret, confirmed_at) VALUES ($1, $2, now())",
+            user_id,
+            secret
+        )
+        .execute(db)
+        .await
+        .unwrap();
+        user_id
+    }
+
+    #[test]
+    fn rfc_6238_vectors() {
+        // the appendix B codes have eight digits, apps show the last six
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, expected) in vectors {
+            assert_eq!(code_at(RFC_SECRET, time), expected[2..], "at {time}");
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew() {
+        let time = 1234567890;
+        let step = (time / STEP) as i64;
+        for offset in [-1, 0, 1] {
+            let code = code_at(RFC_SECRET, (step + offset) as u64 * STEP);
+            assert_eq!(
+                matching_step_at(RFC_SECRET, &code, time),
+                Some(step + offset)
+            );
+        }
+        for offset in [-2, 2] {
+            let code = code_at(RFC_SECRET, (step + offset) as u64 * STEP);
+            assert_eq!(matching_step_at(RFC_SECRET, &code, time), None);
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let code = recovery_code();
+        assert_eq!(code.len(), RECOVERY_CODE_LEN + RECOVERY_CODE_LEN / 4 - 1);
+        let normalized = normalize_recovery_code(&code);
+        assert_eq!(normalize_recovery_code(&code.to_uppercase()), normalized);
+        assert_eq!(normalize_recovery_code(&code.replace('-', "")), normalized);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn rejects_replayed_codes(db: PgPool) {
+        let user_id = user_with_totp(&db, RFC_SECRET).await;
+        let code = code_at(RFC_SECRET, now());
+
+        let first = verify(&db, user_id, &code).await.unwrap();
+        assert!(matches!(first, Some(Factor::Totp)));
+        assert!(verify(&db, user_id, &code).await.unwrap().is_none());
+
+        // nor one from before the last code used
+        let earlier = code_at(RFC_SECRET, now() - STEP);
+        assert!(verify(&db, user_id, &earlier).await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn recovery_codes_are_used_up(db: PgPool) {
+        let user_id = user_with_totp(&db, RFC_SECRET).await;
+        let codes = regenerate_recovery_codes(&db, user_id).await.unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+
+        let used = verify(&db, user_id, &codes[0].to_uppercase())
+            .await
+            .unwrap();
+        assert!(matches!(used, Some(Factor::RecoveryCode)));
+        assert!(verify(&db, user_id, &codes[0]).await.unwrap().is_none());
+        assert_eq!(
+            recovery_codes_left(&db, user_id).await.unwrap(),
+            RECOVERY_CODES as i64 - 1
+        );
+
+        // new codes replace the old ones
+        regenerate_recovery_codes(&db, user_id).await.unwrap();
+        assert!(verify(&db, user_id, &codes[1]).await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn locks_after_too_many_wrong_codes(db: PgPool) {
+        let user_id = user_with_totp(&db, RFC_SECRET).await;
+        let wrong = format!(
+            "{:06}",
+            (code_at(RFC_SECRET, now()).parse::<u32>().unwrap() + 500_000) % 1_000_000
+        );
+        for _ in 0..MAX_FAILURES {
+            assert!(verify(&db, user_id, &wrong).await.unwrap().is_none());
+        }
+
+        // not even the right code gets through now, whichever challenge it comes with
+        let code = code_at(RFC_SECRET, now());
+        assert!(matches!(
+            verify(&db, user_id, &code).await,
+            Err(Error::TooManyRequests(_))
+        ));
+
+        sqlx::query!(
+            "UPDATE users SET totp_locked_until = now() - interval '1 second' WHERE id = $1",
+            user_id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert!(verify(&db, user_id, &code).await.unwrap().is_some());
+        let failures =
+            sqlx::query_scalar!("SELECT totp_failures FROM users WHERE id = $1", user_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(failures, 0);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{path::Path, time::Duration};
+
+use tokio::process::Command;
+
+use crate::{config::VideoConfig, encryption::PlainFile, state::App};
+
+/// Extensions treated as videos; [`store_file`](crate::routes::upload::store_file)
+/// marks them as pending.
+const EXTENSIONS: [&str; 11] = [
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "mpg", "mpeg", "3gp",
+];
+
+/// Rendition ladder: output height and video bitrate. Renditions taller than the
+/// source are skipped, except for the smallest one.
+const RENDITIONS: [(u32, &str); 4] = [
+    (1080, "5000k"),
+    (720, "2800k"),
+    (480, "1400k"),
+    (360, "800k"),
+];
+
+/// Length of an HLS segment in seconds.
+const SEGMENT_SECONDS: u32 = 6;
+/// How often to look for work when no upload wakes the transcoder up.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Name of the master playlist inside a file's HLS directory.
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+/// Name of the poster frame inside a file's HLS directory.
+pub const POSTER: &str = "poster.jpg";
+
+pub fn is_video(filename: &str) -> bool {
+    filename
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Transcodes pending videos into an adaptive HLS rendition set plus a poster
+/// frame, one video at a time.
+///
+/// Uploads wake the transcoder up through `AppState::transcoder`. When ffmpeg is
+/// not installed, videos simply stay pending until it is.
+pub async fn run(state: App, config: VideoConfig) {
+    // videos that were being transcoded when the server stopped start over
+    if let Err(err) = sqlx::query!(
+        r#"
+        UPDATE files SET video_status = 'pending' WHERE video_status = 'processing'
+        "#
+    )
+    .execute(&state.db)
+    .await
+    {
+        tracing::error!("Transcoder failed: {err}");
+    }
+
+    loop {
+        match transcode_next(&state, &config).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(Error::Missing(program)) => {
+                tracing::warn!("{program} is not installed, videos will not be transcoded");
+                return;
+            }
+            Err(err) => tracing::error!("Transcoder failed: {err}"),
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, state.transcoder.notified()).await;
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    /// ffmpeg or ffprobe could not be started.
+    Missing(String),
+    /// ffmpeg or ffprobe ran, but failed.
+    Failed(String),
+    Io(std::io::Error),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Missing(program) => write!(f, "{program} not found"),
+            Error::Failed(message) => f.write_str(message),
+            Error::Io(err) => err.fmt(f),
+            Error::Database(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+/// Transcodes the oldest pending video; returns `false` if there was none.
+async fn transcode_next(state: &App, config: &VideoConfig) -> Result<bool, Error> {
+    let Some(file) = sqlx::query!(
+        r#"
+        UPDATE files SET video_status = 'processing'
+        WHERE id = (
+            SELECT id FROM files WHERE video_status = 'pending'
+            ORDER BY last_modified LIMIT 1
+        )
+        RETURNING id, user_id
+        "#
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(false);
+    };
+
+    let out_dir = state.hls_dir(file.user_id, file.id);
+    let result = match PlainFile::new(state, file.user_id, file.id).await {
+        Ok(input) => transcode(config, input.path(), &out_dir).await,
+        Err(err) => Err(Error::Failed(format!("cannot read the video: {err}"))),
+    };
+
+    let (status, duration) = match result {
+        Ok(duration) => ("ready", Some(duration)),
+        Err(Error::Failed(message)) => {
+            tracing::warn!("Could not transcode {}: {message}", file.id);
+            let _ = tokio::fs::remove_dir_all(&out_dir).await;
+            ("failed", None)
+        }
+        Err(err) => {
+            // try again later, e.g. once ffmpeg is installed
+            let _ = tokio::fs::remove_dir_all(&out_dir).await;
+            sqlx::query!(
+                r#"
+                UPDATE files SET video_status = 'pending' WHERE id = $1
+                "#,
+                file.id
+            )
+            .execute(&state.db)
+            .await?;
+            return Err(err);
+        }
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE files SET video_status = $1, video_duration = $2 WHERE id = $3
+        "#,
+        status,
+        duration,
+        file.id
+    )
+    .execute(&state.db)
+    .await?;
+
+    Ok(true)
+}
+
+/// What ffprobe found out about a video.
+struct Probe {
+    height: u32,
+    duration: f64,
+    has_audio: bool,
+}
+
+async fn probe(ffprobe: &str, input: &Path) -> Result<Probe, Error> {
+    let output = run_program(
+        Command::new(ffprobe)
+            .args(["-v", "error", "-show_entries"])
+            .arg("stream=codec_type,height:format=duration")
+            .args(["-of", "json"])
+            .arg(input),
+        ffprobe,
+    )
+    .await?;
+
+    let json: serde_json::Value = serde_json::from_slice(&output)
+        .map_err(|err| Error::Failed(format!("invalid ffprobe output: {err}")))?;
+    let streams = json["streams"].as_array().cloned().unwrap_or_default();
+    let height = streams
+        .iter()
+        .find(|stream| stream["codec_type"] == "video")
+        .and_then(|stream| stream["height"].as_u64())
+        .ok_or_else(|| Error::Failed("no video stream".to_string()))?;
+    let has_audio = streams.iter().any(|stream| stream["codec_type"] == "audio");
+    let duration = json["format"]["duration"]
+        .as_str()
+        .and_then(|duration| duration.parse().ok())
+        .unwrap_or(0.0);
+
+    Ok(Probe {
+        height: height as u32,
+        duration,
+        has_audio,
+    })
+}
+
+/// Writes the renditions, master playlist and poster to `out_dir` and returns the duration.
+async fn transcode(config: &VideoConfig, input: &Path, out_dir: &Path) -> Result<f64, Error> {
+    let probe = probe(&config.ffprobe, input).await?;
+
+    let renditions: Vec<_> = RENDITIONS
+        .iter()
+        .enumerate()
+        .filter(|&(i, &(height, _))| height <= probe.height || i == RENDITIONS.len() - 1)
+        .map(|(_, rendition)| *rendition)
+        .collect();
+
+    let _ = tokio::fs::remove_dir_all(out_dir).await;
+    tokio::fs::create_dir_all(out_dir).await?;
+
+    let outputs: String = (0..renditions.len()).map(|i| format!("[v{i}]")).collect();
+    let mut filter = format!("[0:v]split={}{outputs}", renditions.len());
+    for (i, (height, _)) in renditions.iter().enumerate() {
+        filter.push_str(&format!(";[v{i}]scale=-2:{height}[v{i}out]"));
+    }
+
+    let mut ffmpeg = Command::new(&config.ffmpeg);
+    ffmpeg
+        .args(["-v", "error", "-y", "-i"])
+        .arg(input)
+        .args(["-filter_complex", &filter]);
+    let mut stream_map = Vec::new();
+    for (i, (_, bitrate)) in renditions.iter().enumerate() {
+        ffmpeg
+            .args(["-map", &format!("[v{i}out]")])
+            .arg(format!("-b:v:{i}"))
+            .arg(bitrate);
+        if probe.has_audio {
+            ffmpeg.args(["-map", "0:a:0"]);
+            stream_map.push(format!("v:{i},a:{i}"));
+        } else {
+            stream_map.push(format!("v:{i}"));
+        }
+    }
+    ffmpeg
+        .args([
+            "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+        ])
+        // keyframes on segment boundaries, so every rendition switches cleanly
+        .args(["-force_key_frames"])
+        .arg(format!("expr:gte(t,n_forced*{SEGMENT_SECONDS})"))
+        .args(["-c:a", "aac", "-b:a", "128k", "-ac", "2"])
+        .args(["-f", "hls", "-hls_time", &SEGMENT_SECONDS.to_string()])
+        .args(["-hls_playlist_type", "vod"])
+        .arg("-hls_segment_filename")
+        .arg(out_dir.join("v%v/segment%04d.ts"))
+        .args(["-master_pl_name", MASTER_PLAYLIST])
+        .args(["-var_stream_map", &stream_map.join(" ")])
+        .arg(out_dir.join("v%v/index.m3u8"));
+    run_program(&mut ffmpeg, &config.ffmpeg).await?;
+
+    // a frame from early on, but not the (often black) very first one
+    let poster_at = (probe.duration / 10.0).min(5.0);
+    run_program(
+        Command::new(&config.ffmpeg)
+            .args(["-v", "error", "-y", "-ss", &format!("{poster_at:.2}"), "-i"])
+            .arg(input)
+            .args([
+                "-frames:v",
+                "1",
+                "-vf",
+                "scale=-2:min(720\\,ih)",
+                "-q:v",
+                "3",
+            ])
+            .arg(out_dir.join(POSTER)),
+        &config.ffmpeg,
+    )
+    .await?;
+
+    Ok(probe.duration)
+}
+
+async fn run_program(command: &mut Command, program: &str) -> Result<Vec<u8>, Error> {
+    let output = match command.output().await {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Missing(program.to_string()));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if !output.status.success() {
+        return Err(Error::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -1,3 +1,5 @@
+pub mod vault;
+
 pub struct Entry {
     pub name: String,
     pub path: String,
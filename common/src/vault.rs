@@ -0,0 +1,323 @@
+//! Client-side encryption of vault folders.
+//!
+//! Every user has an X25519 key pair. The secret key is stored on the server
+//! encrypted with a key derived from the user's passphrase (Argon2id), so only
+//! clients that know the passphrase can unlock it. Every vault has a random
+//! folder key that encrypts the names and contents of its files (AES-256-GCM),
+//! and is wrapped for the public key of each member. Sharing a vault wraps the
+//! folder key for one more public key; nothing has to be re-encrypted.
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{Aead, Payload},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use curve25519_dalek::MontgomeryPoint;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+pub const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// What can go wrong; deliberately vague about why decryption failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The system has no randomness to offer.
+    Random,
+    /// Wrong passphrase, wrong key or tampered data.
+    Decrypt,
+    /// A key or ciphertext has the wrong length or encoding.
+    Malformed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Random => f.write_str("no randomness available"),
+            Error::Decrypt => f.write_str("decryption failed"),
+            Error::Malformed => f.write_str("malformed key or ciphertext"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn random<const N: usize>() -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|_| Error::Random)?;
+    Ok(bytes)
+}
+
+/// Encrypts with a random nonce, which is put in front of the ciphertext.
+fn seal(key: &[u8; KEY_LEN], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce: [u8; NONCE_LEN] = random()?;
+    let cipher = Aes256Gcm::new(&(*key).into());
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(&nonce.into(), Payload { msg, aad })
+            .map_err(|_| Error::Malformed)?,
+    );
+    Ok(sealed)
+}
+
+fn open(key: &[u8; KEY_LEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    let (nonce, ciphertext) = sealed.split_at_checked(NONCE_LEN).ok_or(Error::Malformed)?;
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().unwrap();
+    let cipher = Aes256Gcm::new(&(*key).into());
+    cipher
+        .decrypt(
+            &nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Decrypt)
+}
+
+fn key(bytes: &[u8]) -> Result<[u8; KEY_LEN], Error> {
+    bytes.try_into().map_err(|_| Error::Malformed)
+}
+
+/// A user's X25519 key pair.
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+}
+
+impl KeyPair {
+    pub fn generate() -> Result<Self, Error> {
+        Ok(Self::from_secret(random()?))
+    }
+
+    fn from_secret(secret: [u8; KEY_LEN]) -> Self {
+        Self {
+            secret,
+            public: MontgomeryPoint::mul_base_clamped(secret).to_bytes(),
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; KEY_LEN] {
+        self.public
+    }
+
+    /// Encrypts the secret key with the passphrase, to be stored on the server:
+    /// salt, nonce and ciphertext.
+    pub fn lock(&self, passphrase: &str) -> Result<Vec<u8>, Error> {
+        let salt: [u8; SALT_LEN] = random()?;
+        let mut locked = salt.to_vec();
+        locked.extend(seal(
+            &passphrase_key(passphrase, &salt)?,
+            b"secret key",
+            &self.secret,
+        )?);
+        Ok(locked)
+    }
+
+    /// Decrypts a secret key locked with [`KeyPair::lock`]; fails with
+    /// [`Error::Decrypt`] for a wrong passphrase.
+    pub fn unlock(locked: &[u8], passphrase: &str) -> Result<Self, Error> {
+        let (salt, sealed) = locked.split_at_checked(SALT_LEN).ok_or(Error::Malformed)?;
+        let secret = open(&passphrase_key(passphrase, salt)?, b"secret key", sealed)?;
+        Ok(Self::from_secret(key(&secret)?))
+    }
+
+    /// Unwraps a folder key wrapped for this key pair's public key.
+    pub fn unwrap_folder_key(&self, wrapped: &[u8]) -> Result<FolderKey, Error> {
+        let (ephemeral, sealed) = wrapped.split_at_checked(KEY_LEN).ok_or(Error::Malformed)?;
+        let ephemeral = key(ephemeral)?;
+        let shared = MontgomeryPoint(ephemeral).mul_clamped(self.secret);
+        let wrapping_key = wrapping_key(&shared, &ephemeral, &self.public)?;
+        Ok(FolderKey(key(&open(&wrapping_key, b"folder key", sealed)?)?))
+    }
+}
+
+/// Stretches a passphrase into a key with Argon2id.
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], Error> {
+    let mut key = [0; KEY_LEN];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| Error::Malformed)?;
+    Ok(key)
+}
+
+/// Derives the key a folder key is wrapped with from an X25519 shared secret,
+/// bound to both public keys involved.
+fn wrapping_key(
+    shared: &MontgomeryPoint,
+    ephemeral: &[u8; KEY_LEN],
+    recipient: &[u8; KEY_LEN],
+) -> Result<[u8; KEY_LEN], Error> {
+    // an all-zero shared secret means the public key was a low-order point
+    if shared.to_bytes() == [0; KEY_LEN] {
+        return Err(Error::Malformed);
+    }
+    let salt = [ephemeral.as_slice(), recipient.as_slice()].concat();
+    let mut key = [0; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(b"cloud vault folder key", &mut key)
+        .map_err(|_| Error::Malformed)?;
+    Ok(key)
+}
+
+/// The key of one vault, encrypting the names and contents of its files.
+#[derive(Clone)]
+pub struct FolderKey([u8; KEY_LEN]);
+
+impl FolderKey {
+    pub fn generate() -> Result<Self, Error> {
+        Ok(Self(random()?))
+    }
+
+    /// Wraps the folder key for a member's public key, with a fresh ephemeral
+    /// key pair: the ephemeral public key followed by the sealed folder key.
+    pub fn wrap_for(&self, public_key: &[u8]) -> Result<Vec<u8>, Error> {
+        let recipient = key(public_key)?;
+        let ephemeral = KeyPair::generate()?;
+        let shared = MontgomeryPoint(recipient).mul_clamped(ephemeral.secret);
+        let wrapping_key = wrapping_key(&shared, &ephemeral.public, &recipient)?;
+        let mut wrapped = ephemeral.public.to_vec();
+        wrapped.extend(seal(&wrapping_key, b"folder key", &self.0)?);
+        Ok(wrapped)
+    }
+
+    /// Encrypts the contents of a file.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        seal(&self.0, b"contents", data)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        open(&self.0, b"contents", data)
+    }
+
+    /// Encrypts a file name into something the server accepts as a file name
+    /// (URL-safe base64).
+    pub fn encrypt_name(&self, name: &str) -> Result<String, Error> {
+        Ok(URL_SAFE_NO_PAD.encode(seal(&self.0, b"name", name.as_bytes())?))
+    }
+
+    pub fn decrypt_name(&self, name: &str) -> Result<String, Error> {
+        let sealed = URL_SAFE_NO_PAD
+            .decode(name)
+            .map_err(|_| Error::Malformed)?;
+        String::from_utf8(open(&self.0, b"name", &sealed)?).map_err(|_| Error::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flips a bit in the last byte, which is part of the authentication tag.
+    fn tamper(data: &[u8]) -> Vec<u8> {
+        let mut tampered = data.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        tampered
+    }
+
+    #[test]
+    fn unlocks_with_the_passphrase_only() {
+        let pair = KeyPair::generate().unwrap();
+        let locked = pair.lock("correct horse").unwrap();
+
+        let unlocked = KeyPair::unlock(&locked, "correct horse").unwrap();
+        assert_eq!(unlocked.public_key(), pair.public_key());
+        assert_eq!(
+            KeyPair::unlock(&locked, "wrong horse").err(),
+            Some(Error::Decrypt)
+        );
+        assert_eq!(
+            KeyPair::unlock(&tamper(&locked), "correct horse").err(),
+            Some(Error::Decrypt)
+        );
+        assert_eq!(
+            KeyPair::unlock(&locked[..SALT_LEN], "correct horse").err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn folder_keys_unwrap_for_their_member_only() {
+        let (member, other) = (KeyPair::generate().unwrap(), KeyPair::generate().unwrap());
+        let folder_key = FolderKey::generate().unwrap();
+        let wrapped = folder_key.wrap_for(&member.public_key()).unwrap();
+        let sealed = folder_key.encrypt(b"contents").unwrap();
+
+        let unwrapped = member.unwrap_folder_key(&wrapped).unwrap();
+        assert_eq!(unwrapped.decrypt(&sealed).unwrap(), b"contents");
+        assert_eq!(
+            other.unwrap_folder_key(&wrapped).err(),
+            Some(Error::Decrypt)
+        );
+        assert_eq!(
+            member.unwrap_folder_key(&tamper(&wrapped)).err(),
+            Some(Error::Decrypt)
+        );
+    }
+
+    #[test]
+    fn rejects_low_order_public_keys() {
+        let folder_key = FolderKey::generate().unwrap();
+        assert_eq!(
+            folder_key.wrap_for(&[0; KEY_LEN]).err(),
+            Some(Error::Malformed)
+        );
+        assert_eq!(
+            folder_key.wrap_for(&[1; KEY_LEN - 1]).err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn contents_round_trip_and_detect_tampering() {
+        let folder_key = FolderKey::generate().unwrap();
+        for data in [&b""[..], b"hello", &[0xab; 100_000]] {
+            let sealed = folder_key.encrypt(data).unwrap();
+            assert_eq!(folder_key.decrypt(&sealed).unwrap(), data);
+            assert_eq!(
+                folder_key.decrypt(&tamper(&sealed)).err(),
+                Some(Error::Decrypt)
+            );
+        }
+
+        let other = FolderKey::generate().unwrap();
+        let sealed = folder_key.encrypt(b"secret").unwrap();
+        assert_eq!(other.decrypt(&sealed).err(), Some(Error::Decrypt));
+        assert_eq!(
+            folder_key.decrypt(&sealed[..4]).err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn names_round_trip_and_stay_apart_from_contents() {
+        let folder_key = FolderKey::generate().unwrap();
+        let name = folder_key.encrypt_name("Tax return 2025.pdf").unwrap();
+        assert!(
+            name.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        );
+        assert_eq!(
+            folder_key.decrypt_name(&name).unwrap(),
+            "Tax return 2025.pdf"
+        );
+        assert_eq!(
+            folder_key.decrypt_name("not base64!").err(),
+            Some(Error::Malformed)
+        );
+
+        // sealed contents can't pass for a name, nor the other way around
+        let sealed = folder_key.encrypt(b"Tax return 2025.pdf").unwrap();
+        assert_eq!(
+            folder_key
+                .decrypt_name(&URL_SAFE_NO_PAD.encode(&sealed))
+                .err(),
+            Some(Error::Decrypt)
+        );
+        let name = URL_SAFE_NO_PAD.decode(&name).unwrap();
+        assert_eq!(folder_key.decrypt(&name).err(), Some(Error::Decrypt));
+    }
+}
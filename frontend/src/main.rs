@@ -1,7 +1,9 @@
 use std::sync::Arc;
 
+use base64::{Engine, engine::general_purpose::STANDARD};
+use common::vault::{FolderKey, KeyPair};
 use dioxus::{html::{FileEngine, HasFileData}, prelude::*};
-use reqwest::multipart::{self, Part};
+use reqwest::multipart::Part;
 
 #[derive(Debug, Clone, Routable, PartialEq)]
 #[rustfmt::skip]
@@ -9,6 +11,18 @@ enum Route {
     #[layout(MainLayout)]
     #[route("/")]
     Home {},
+    #[route("/photos")]
+    Photos {},
+    #[route("/vaults")]
+    Vaults {},
+    #[route("/vaults/:id")]
+    Vault { id: String },
+    #[route("/teams")]
+    Teams {},
+    #[route("/teams/:id")]
+    Team { id: String },
+    #[route("/admin")]
+    Admin {},
 }
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
@@ -21,6 +35,9 @@ fn main() {
 
 #[component]
 fn App() -> Element {
+    // the unlocked vault key pair, kept in memory only
+    use_context_provider(|| Signal::new(None::<KeyPair>));
+
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
         document::Link { rel: "stylesheet", href: "https://fonts.googleapis.com/icon?family=Material+Icons" }
@@ -55,65 +72,108 @@ fn MainLayout() -> Element {
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
-struct File {
+struct Entry {
+    kind: String,
     id: String,
     name: String,
-    size: u64,
-    last_modified: String,
+    size: Option<u64>,
+    last_modified: Option<String>,
+    thumbnail: bool,
+    video_status: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
-struct Folder {
-    id: String,
-    name: String,
-    parent_id: Option<String>,
-}
-
-#[derive(serde::Deserialize, serde::Serialize)]
-struct Entries {
-    folders: Vec<Folder>,
-    files: Vec<File>
+struct Listing {
+    entries: Vec<Entry>,
+    next_cursor: Option<String>,
+    total_files: u64,
+    total_folders: u64,
 }
 
 /// Home page
 #[component]
 fn Home() -> Element {
     // get files from the server
-    let mut files = use_resource(|| async move {
+    let files = use_resource(|| async move {
         reqwest::Client::new()
             .post("http://localhost:8000/api/v1/files")
             .json(&serde_json::json!({}))
             .send()
             .await
             .unwrap()
-            .json::<Entries>()
+            .json::<Listing>()
             .await
     });
+    let mut grid = use_signal(|| false);
 
     rsx! {
         div {
             class: "",
-            h1 { class: "text-3xl", "My Cloud Drive" }
-            // a list of files (full width, separated by lines)
             div {
-                class: "mt-4 flex flex-col divide-y-1 divide-neutral-700",
-                // for each file, create a div with a description icon and the file name
+                class: "flex items-center justify-between",
+                h1 { class: "text-3xl", "My Cloud Drive" }
+                // switch between the list and the grid of thumbnails
+                button {
+                    class: "p-2 rounded-lg hover:bg-neutral-700",
+                    onclick: move |_| grid.toggle(),
+                    i {
+                        class: "material-icons",
+                        if grid() { "view_list" } else { "grid_view" }
+                    }
+                }
+            }
+            // a list of files (full width, separated by lines), or a grid of tiles
+            div {
+                class: if grid() {
+                    "mt-4 grid grid-cols-[repeat(auto-fill,minmax(10rem,1fr))] gap-4"
+                } else {
+                    "mt-4 flex flex-col divide-y-1 divide-neutral-700"
+                },
                 // use the future from above to get the files
                 match &*files.read_unchecked() {
                     Some(Ok(response)) => rsx! {
-                        for file in &response.files {
-                            div {
-                                class: "flex px-2 p-1 items-center",
+                        for entry in &response.entries {
+                            if grid() {
                                 div {
-                                    class: "w-4 h-4 flex items-center justify-center text-neutral-400",
-                                    i { class: "material-icons", "description" }
+                                    class: "flex flex-col rounded-lg bg-neutral-900 overflow-hidden",
+                                    div {
+                                        class: "h-32 flex items-center justify-center text-neutral-400",
+                                        if entry.thumbnail {
+                                            img {
+                                                class: "w-full h-full object-cover",
+                                                loading: "lazy",
+                                                src: "http://localhost:8000/api/v1/files/{entry.id}/thumbnail?size=256",
+                                            }
+                                        } else {
+                                            i {
+                                                class: "material-icons !text-5xl",
+                                                if entry.kind == "folder" { "folder" } else if entry.video_status.is_some() { "movie" } else { "description" }
+                                            }
+                                        }
+                                    }
+                                    h2 { class: "px-2 py-1 truncate", "{entry.name}" }
                                 }
+                            } else {
                                 div {
-                                    class: "ml-2",
-                                    h2 { class: "", "{file.name}" }
+                                    class: "flex px-2 p-1 items-center",
+                                    div {
+                                        class: "w-4 h-4 flex items-center justify-center text-neutral-400",
+                                        i {
+                                            class: "material-icons",
+                                            if entry.kind == "folder" { "folder" } else if entry.video_status.is_some() { "movie" } else { "description" }
+                                        }
+                                    }
+                                    div {
+                                        class: "ml-2",
+                                        h2 { class: "", "{entry.name}" }
+                                    }
                                 }
                             }
                         }
+                        div {
+                            class: "col-span-full px-2 pt-2 text-sm text-neutral-400",
+                            "{response.total_folders} folders, {response.total_files} files"
+                        }
                     },
                     Some(Err(_)) => rsx! {
                         div { "Loading dogs failed" }
@@ -145,9 +205,1162 @@ fn Home() -> Element {
     }
 }
 
+#[derive(serde::Deserialize)]
+struct Photo {
+    id: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Day {
+    date: String,
+    photos: Vec<Photo>,
+}
+
+#[derive(serde::Deserialize)]
+struct Timeline {
+    days: Vec<Day>,
+    next_cursor: Option<String>,
+}
+
+/// All photos across folders, newest first, grouped by the day they were taken
+#[component]
+fn Photos() -> Element {
+    let mut days = use_signal(Vec::<Day>::new);
+    let mut cursor = use_signal(|| None::<String>);
+    let mut done = use_signal(|| false);
+    let mut loading = use_signal(|| false);
+
+    // fetch the next page and append it; a day can continue from the previous page
+    let load_more = move || async move {
+        if loading() || done() {
+            return;
+        }
+        loading.set(true);
+        let url = match cursor() {
+            Some(cursor) => format!("http://localhost:8000/api/v1/photos?cursor={cursor}"),
+            None => "http://localhost:8000/api/v1/photos".to_string(),
+        };
+        let page = match reqwest::get(url).await {
+            Ok(response) => response.json::<Timeline>().await.ok(),
+            Err(_) => None,
+        };
+        if let Some(page) = page {
+            let mut days = days.write();
+            for day in page.days {
+                match days.last_mut() {
+                    Some(last) if last.date == day.date => last.photos.extend(day.photos),
+                    _ => days.push(day),
+                }
+            }
+            done.set(page.next_cursor.is_none());
+            cursor.set(page.next_cursor);
+        }
+        loading.set(false);
+    };
+
+    use_future(load_more);
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            h1 { class: "text-3xl", "Photos" }
+            for day in days.read().iter() {
+                h2 { class: "mt-4 mb-2 text-neutral-400", "{day.date}" }
+                div {
+                    class: "grid grid-cols-[repeat(auto-fill,minmax(8rem,1fr))] gap-1",
+                    for photo in day.photos.iter() {
+                        img {
+                            class: "w-full aspect-square object-cover bg-neutral-900",
+                            loading: "lazy",
+                            title: "{photo.name}",
+                            src: "http://localhost:8000/api/v1/files/{photo.id}/thumbnail?size=256",
+                        }
+                    }
+                }
+            }
+            // infinite scroll: load the next page once this comes into view
+            if !done() {
+                button {
+                    class: "w-full p-4 text-neutral-400",
+                    onvisible: move |evt| async move {
+                        if evt.data().is_intersecting().unwrap_or(false) {
+                            load_more().await;
+                        }
+                    },
+                    onclick: move |_| load_more(),
+                    if loading() { "Loading..." } else { "Load more" }
+                }
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct VaultKeyPair {
+    public_key: String,
+    locked_secret: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+struct VaultInfo {
+    id: String,
+    name: String,
+    owner_email: String,
+    wrapped_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct VaultFile {
+    id: String,
+    name: String,
+    size: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct PublicKey {
+    user_id: String,
+    public_key: String,
+}
+
+/// Vaults: folders encrypted in the browser, the server only sees ciphertext
+#[component]
+fn Vaults() -> Element {
+    let mut keys = use_context::<Signal<Option<KeyPair>>>();
+    let mut passphrase = use_signal(String::new);
+    let mut name = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+
+    let key_pair = use_resource(|| async move {
+        let response = reqwest::get("http://localhost:8000/api/v1/account/vault-key").await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        response.json::<VaultKeyPair>().await.map(Some)
+    });
+    let mut vaults = use_resource(move || async move {
+        reqwest::get("http://localhost:8000/api/v1/vaults")
+            .await?
+            .json::<Vec<VaultInfo>>()
+            .await
+    });
+
+    // unlocks the existing key pair, or sets up a new one locked with the passphrase
+    let unlock = move |_| async move {
+        let existing = match &*key_pair.read() {
+            Some(Ok(existing)) => existing.as_ref().map(|k| k.locked_secret.clone()),
+            _ => return,
+        };
+        let unlocked = match existing {
+            Some(locked) => STANDARD
+                .decode(locked)
+                .map_err(|_| common::vault::Error::Malformed)
+                .and_then(|locked| KeyPair::unlock(&locked, &passphrase())),
+            None => {
+                let Ok(generated) = KeyPair::generate() else { return };
+                let Ok(locked) = generated.lock(&passphrase()) else { return };
+                let body = VaultKeyPair {
+                    public_key: STANDARD.encode(generated.public_key()),
+                    locked_secret: STANDARD.encode(locked),
+                };
+                let saved = reqwest::Client::new()
+                    .put("http://localhost:8000/api/v1/account/vault-key")
+                    .json(&body)
+                    .send()
+                    .await;
+                if !saved.is_ok_and(|r| r.status().is_success()) {
+                    error.set(Some("Saving the key pair failed".to_string()));
+                    return;
+                }
+                Ok(generated)
+            }
+        };
+        match unlocked {
+            Ok(unlocked) => {
+                keys.set(Some(unlocked));
+                passphrase.set(String::new());
+                error.set(None);
+            }
+            Err(_) => error.set(Some("Wrong passphrase".to_string())),
+        }
+    };
+
+    let create = move |_| async move {
+        let Some(key_pair) = keys() else { return };
+        let Ok(wrapped) = FolderKey::generate().and_then(|key| key.wrap_for(&key_pair.public_key())) else {
+            return;
+        };
+        let created = reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/vaults")
+            .json(&serde_json::json!({ "name": name(), "wrapped_key": STANDARD.encode(wrapped) }))
+            .send()
+            .await;
+        if created.is_ok_and(|r| r.status().is_success()) {
+            name.set(String::new());
+            vaults.restart();
+        } else {
+            error.set(Some("Creating the vault failed".to_string()));
+        }
+    };
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            h1 { class: "text-3xl", "Vaults" }
+            if let Some(message) = error() {
+                p { class: "mt-2 text-red-400", "{message}" }
+            }
+            if keys.read().is_none() {
+                // the passphrase never leaves the browser
+                div {
+                    class: "mt-4 flex gap-2 items-center",
+                    match &*key_pair.read() {
+                        Some(Ok(Some(_))) => rsx! { span { "Enter your vault passphrase" } },
+                        Some(Ok(None)) => rsx! { span { "Choose a vault passphrase" } },
+                        _ => rsx! { span { "Loading..." } },
+                    }
+                    input {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        r#type: "password",
+                        value: passphrase,
+                        oninput: move |evt| passphrase.set(evt.value()),
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                        onclick: unlock,
+                        "Unlock"
+                    }
+                }
+            } else {
+                div {
+                    class: "mt-4 flex flex-col divide-y-1 divide-neutral-700",
+                    if let Some(Ok(vaults)) = &*vaults.read() {
+                        for vault in vaults {
+                            Link {
+                                to: Route::Vault { id: vault.id.clone() },
+                                class: "flex px-2 p-1 items-center hover:bg-neutral-800",
+                                i { class: "material-icons text-neutral-400", "lock" }
+                                span { class: "ml-2", "{vault.name}" }
+                                span { class: "ml-auto text-sm text-neutral-400", "{vault.owner_email}" }
+                            }
+                        }
+                    }
+                }
+                div {
+                    class: "mt-4 flex gap-2",
+                    input {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        placeholder: "New vault",
+                        value: name,
+                        oninput: move |evt| name.set(evt.value()),
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                        onclick: create,
+                        "Create"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The files of one vault, decrypted in the browser
+#[component]
+fn Vault(id: String) -> Element {
+    let keys = use_context::<Signal<Option<KeyPair>>>();
+    let mut email = use_signal(String::new);
+    let mut status = use_signal(|| None::<String>);
+
+    let vault = use_resource({
+        let id = id.clone();
+        move || {
+            let id = id.clone();
+            async move {
+                reqwest::get(format!("http://localhost:8000/api/v1/vaults/{id}"))
+                    .await?
+                    .json::<VaultInfo>()
+                    .await
+            }
+        }
+    });
+    let mut files = use_resource({
+        let id = id.clone();
+        move || {
+            let id = id.clone();
+            async move {
+                reqwest::get(format!("http://localhost:8000/api/v1/vaults/{id}/files"))
+                    .await?
+                    .json::<Vec<VaultFile>>()
+                    .await
+            }
+        }
+    });
+
+    // the folder key, unwrapped with the unlocked key pair
+    let folder_key = move || {
+        let keys = keys.read();
+        let vault = vault.read();
+        let (Some(keys), Some(Ok(vault))) = (keys.as_ref(), vault.as_ref()) else {
+            return None;
+        };
+        let wrapped = STANDARD.decode(&vault.wrapped_key).ok()?;
+        keys.unwrap_folder_key(&wrapped).ok()
+    };
+
+    let upload = {
+        let id = id.clone();
+        move |evt: FormEvent| {
+            let id = id.clone();
+            async move {
+                let (Some(key), Some(file_engine)) = (folder_key(), evt.files()) else { return };
+                let mut multipart = reqwest::multipart::Form::new().text("folder_id", id);
+                for file_name in file_engine.files() {
+                    let Some(contents) = file_engine.read_file(&file_name).await else { continue };
+                    let (Ok(name), Ok(contents)) = (key.encrypt_name(&file_name), key.encrypt(&contents)) else {
+                        continue;
+                    };
+                    multipart = multipart.part(name.clone(), Part::bytes(contents).file_name(name));
+                }
+                let uploaded = reqwest::Client::new()
+                    .post("http://localhost:8000/api/v1/upload")
+                    .multipart(multipart)
+                    .send()
+                    .await;
+                status.set(Some(if uploaded.is_ok_and(|r| r.status().is_success()) {
+                    "Uploaded".to_string()
+                } else {
+                    "Upload failed".to_string()
+                }));
+                files.restart();
+            }
+        }
+    };
+
+    // decrypts a file and hands it to the browser as a download
+    let download = {
+        let id = id.clone();
+        move |file_id: String, name: String| {
+            let id = id.clone();
+            async move {
+                let Some(key) = folder_key() else { return };
+                let url = format!("http://localhost:8000/api/v1/vaults/{id}/files/{file_id}");
+                let Ok(response) = reqwest::get(url).await else { return };
+                let Ok(ciphertext) = response.bytes().await else { return };
+                let Ok(contents) = key.decrypt(&ciphertext) else {
+                    status.set(Some("Decrypting the file failed".to_string()));
+                    return;
+                };
+                let href = format!("data:application/octet-stream;base64,{}", STANDARD.encode(contents));
+                let name = serde_json::to_string(&name).unwrap();
+                document::eval(&format!(
+                    "const a = document.createElement('a'); a.href = '{href}'; a.download = {name}; a.click();"
+                ));
+            }
+        }
+    };
+
+    // shares the vault by wrapping the folder key for the other user's public key
+    let share = {
+        let id = id.clone();
+        move |_| {
+            let id = id.clone();
+            async move {
+                let Some(key) = folder_key() else { return };
+                let client = reqwest::Client::new();
+                let found = match client
+                    .get("http://localhost:8000/api/v1/users/vault-key")
+                    .query(&[("email", email())])
+                    .send()
+                    .await
+                {
+                    Ok(response) if response.status().is_success() => response.json::<PublicKey>().await.ok(),
+                    _ => None,
+                };
+                let Some(found) = found else {
+                    status.set(Some("No user with a vault key pair found".to_string()));
+                    return;
+                };
+                let Some(wrapped) = STANDARD
+                    .decode(&found.public_key)
+                    .ok()
+                    .and_then(|public_key| key.wrap_for(&public_key).ok())
+                else {
+                    return;
+                };
+                let shared = client
+                    .post(format!("http://localhost:8000/api/v1/vaults/{id}/members"))
+                    .json(&serde_json::json!({ "user_id": found.user_id, "wrapped_key": STANDARD.encode(wrapped) }))
+                    .send()
+                    .await;
+                status.set(Some(if shared.is_ok_and(|r| r.status().is_success()) {
+                    email.set(String::new());
+                    "Shared".to_string()
+                } else {
+                    "Sharing failed".to_string()
+                }));
+            }
+        }
+    };
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            match &*vault.read() {
+                Some(Ok(vault)) => rsx! { h1 { class: "text-3xl", "{vault.name}" } },
+                Some(Err(_)) => rsx! { h1 { class: "text-3xl", "Vault not found" } },
+                None => rsx! { h1 { class: "text-3xl", "Loading..." } },
+            }
+            if let Some(message) = status() {
+                p { class: "mt-2 text-neutral-400", "{message}" }
+            }
+            if let Some(key) = folder_key() {
+                div {
+                    class: "mt-4 flex flex-col divide-y-1 divide-neutral-700",
+                    if let Some(Ok(files)) = &*files.read() {
+                        for file in files {
+                            div {
+                                class: "flex px-2 p-1 items-center",
+                                i { class: "material-icons text-neutral-400", "description" }
+                                span {
+                                    class: "ml-2",
+                                    {key.decrypt_name(&file.name).unwrap_or_else(|_| "(unreadable name)".to_string())}
+                                }
+                                span { class: "ml-auto text-sm text-neutral-400", "{format_size(file.size)}" }
+                                button {
+                                    class: "ml-2 p-1 rounded-lg hover:bg-neutral-700",
+                                    onclick: {
+                                        let download = download.clone();
+                                        let file_id = file.id.clone();
+                                        let name = key.decrypt_name(&file.name).unwrap_or_else(|_| "file".to_string());
+                                        move |_| download(file_id.clone(), name.clone())
+                                    },
+                                    i { class: "material-icons", "download" }
+                                }
+                            }
+                        }
+                    }
+                }
+                div {
+                    class: "mt-4",
+                    input { r#type: "file", multiple: true, onchange: upload }
+                }
+                div {
+                    class: "mt-4 flex gap-2",
+                    input {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        placeholder: "Share with (email)",
+                        value: email,
+                        oninput: move |evt| email.set(evt.value()),
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                        onclick: share,
+                        "Share"
+                    }
+                }
+            } else {
+                p {
+                    class: "mt-4 text-neutral-400",
+                    Link { to: Route::Vaults {}, class: "underline", "Unlock your vaults" }
+                    " to see the files."
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, serde::Deserialize)]
+struct AdminUser {
+    id: String,
+    email: String,
+    name: Option<String>,
+    is_admin: bool,
+    disabled_at: Option<String>,
+    used_bytes: i64,
+    quota_bytes: Option<i64>,
+    quota: Option<i64>,
+    files: i64,
+    sessions: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct AdminSession {
+    id: String,
+    email: String,
+    created_at: String,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct AdminShare {
+    vault_name: String,
+    owner_email: String,
+    member_email: String,
+    added_at: String,
+}
+
+#[derive(serde::Deserialize)]
+struct AdminJob {
+    id: i64,
+    kind: String,
+    attempts: i32,
+    max_attempts: i32,
+    locked_until: Option<String>,
+    last_error: Option<String>,
+    failed_at: Option<String>,
+}
+
+/// Server administration: users and their storage, sessions, shares and background jobs
+#[component]
+fn Admin() -> Element {
+    let mut status = use_signal(|| None::<String>);
+    let mut email = use_signal(String::new);
+    let mut name = use_signal(String::new);
+    let mut password = use_signal(String::new);
+
+    let mut users = use_resource(|| async move {
+        let response = reqwest::get("http://localhost:8000/api/v1/admin/users").await?;
+        if response.status() == reqwest::StatusCode::FORBIDDEN {
+            return Ok(None);
+        }
+        response.json::<Vec<AdminUser>>().await.map(Some)
+    });
+    let mut sessions = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/admin/sessions")
+            .await?
+            .json::<Vec<AdminSession>>()
+            .await
+    });
+    let shares = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/admin/shares")
+            .await?
+            .json::<Vec<AdminShare>>()
+            .await
+    });
+    let mut jobs = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/admin/jobs")
+            .await?
+            .json::<Vec<AdminJob>>()
+            .await
+    });
+
+    // sends a change and reloads everything it may have touched
+    let send = move |(request, done): (reqwest::RequestBuilder, &'static str)| async move {
+        status.set(Some(match request.send().await {
+            Ok(response) if response.status().is_success() => done.to_string(),
+            Ok(response) => {
+                let message = response
+                    .json::<serde_json::Value>()
+                    .await
+                    .ok()
+                    .and_then(|body| body["message"].as_str().map(str::to_string));
+                message.unwrap_or_else(|| "Failed".to_string())
+            }
+            Err(_) => "Failed".to_string(),
+        }));
+        users.restart();
+        sessions.restart();
+        jobs.restart();
+    };
+
+    let create = move |_| async move {
+        let body = serde_json::json!({
+            "email": email(),
+            "name": name(),
+            "password": Some(password()).filter(|password| !password.is_empty()),
+        });
+        let request = reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/admin/users")
+            .json(&body);
+        send((request, "User created")).await;
+        email.set(String::new());
+        name.set(String::new());
+        password.set(String::new());
+    };
+
+    let start = move |body: serde_json::Value| async move {
+        let request = reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/admin/jobs")
+            .json(&body);
+        send((request, "Job started, the report goes to the server log")).await;
+    };
+
+    if let Some(Ok(None)) = &*users.read() {
+        return rsx! {
+            h1 { class: "text-3xl", "Admin" }
+            p { class: "mt-4 text-neutral-400", "Only administrators can see this page." }
+        };
+    }
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            h1 { class: "text-3xl", "Admin" }
+            if let Some(message) = status() {
+                p { class: "mt-2 text-neutral-400", "{message}" }
+            }
+
+            h2 { class: "text-xl mt-6", "Users" }
+            div {
+                class: "mt-2 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(Some(users))) = &*users.read() {
+                    for user in users.iter().cloned() {
+                        AdminUserRow { key: "{user.id}", user, send }
+                    }
+                }
+            }
+            div {
+                class: "mt-4 flex gap-2",
+                input {
+                    class: "px-2 py-1 rounded bg-neutral-800",
+                    placeholder: "Email",
+                    value: email,
+                    oninput: move |evt| email.set(evt.value()),
+                }
+                input {
+                    class: "px-2 py-1 rounded bg-neutral-800",
+                    placeholder: "Name",
+                    value: name,
+                    oninput: move |evt| name.set(evt.value()),
+                }
+                input {
+                    class: "px-2 py-1 rounded bg-neutral-800",
+                    r#type: "password",
+                    placeholder: "Password (optional)",
+                    value: password,
+                    oninput: move |evt| password.set(evt.value()),
+                }
+                button {
+                    class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                    onclick: create,
+                    "Create user"
+                }
+            }
+
+            h2 { class: "text-xl mt-6", "Sessions" }
+            div {
+                class: "mt-2 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(sessions)) = &*sessions.read() {
+                    for session in sessions {
+                        div {
+                            class: "flex px-2 p-1 gap-4 items-center",
+                            span { "{session.email}" }
+                            span { class: "text-sm text-neutral-400", "{session.created_at}" }
+                            span { class: "text-sm text-neutral-400", {session.ip.clone().unwrap_or_default()} }
+                            span { class: "text-sm text-neutral-400 truncate", {session.user_agent.clone().unwrap_or_default()} }
+                            button {
+                                class: "ml-auto p-1 rounded-lg hover:bg-neutral-700",
+                                title: "Log out",
+                                onclick: {
+                                    let url = format!("http://localhost:8000/api/v1/admin/sessions/{}", session.id);
+                                    move |_| send((reqwest::Client::new().delete(url.clone()), "Logged out"))
+                                },
+                                i { class: "material-icons", "logout" }
+                            }
+                        }
+                    }
+                }
+            }
+
+            h2 { class: "text-xl mt-6", "Shared vaults" }
+            div {
+                class: "mt-2 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(shares)) = &*shares.read() {
+                    for share in shares {
+                        div {
+                            class: "flex px-2 p-1 gap-4 items-center",
+                            i { class: "material-icons text-neutral-400", "lock" }
+                            span { "{share.vault_name}" }
+                            span { class: "text-sm text-neutral-400", "{share.owner_email} → {share.member_email}" }
+                            span { class: "ml-auto text-sm text-neutral-400", "{share.added_at}" }
+                        }
+                    }
+                }
+            }
+
+            h2 { class: "text-xl mt-6", "Jobs" }
+            div {
+                class: "mt-2 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(jobs)) = &*jobs.read() {
+                    for job in jobs {
+                        div {
+                            class: "flex px-2 p-1 gap-4 items-center",
+                            span { "#{job.id} {job.kind}" }
+                            span {
+                                class: "text-sm text-neutral-400",
+                                if job.failed_at.is_some() {
+                                    "failed"
+                                } else if job.locked_until.is_some() {
+                                    "running"
+                                } else {
+                                    "queued"
+                                }
+                            }
+                            span { class: "text-sm text-neutral-400", "attempt {job.attempts} of {job.max_attempts}" }
+                            span { class: "text-sm text-red-400 truncate", {job.last_error.clone().unwrap_or_default()} }
+                        }
+                    }
+                    if jobs.is_empty() {
+                        p { class: "px-2 text-sm text-neutral-400", "No jobs queued" }
+                    }
+                }
+            }
+            div {
+                class: "mt-4 flex gap-2",
+                button {
+                    class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                    onclick: move |_| start(serde_json::json!({ "kind": "verify" })),
+                    "Verify checksums"
+                }
+                button {
+                    class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                    onclick: move |_| start(serde_json::json!({ "kind": "fsck" })),
+                    "Check files"
+                }
+                button {
+                    class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                    onclick: move |_| start(serde_json::json!({ "kind": "fsck", "repair": true })),
+                    "Check and repair files"
+                }
+                button {
+                    class: "p-1 rounded-lg hover:bg-neutral-700",
+                    title: "Refresh",
+                    onclick: move |_| jobs.restart(),
+                    i { class: "material-icons", "refresh" }
+                }
+            }
+        }
+    }
+}
+
+/// One user in the admin list, with their usage and what can be done to them
+#[component]
+fn AdminUserRow(
+    user: AdminUser,
+    send: Callback<(reqwest::RequestBuilder, &'static str)>,
+) -> Element {
+    // in GB, empty for the server default
+    let mut quota = use_signal(|| {
+        user.quota_bytes
+            .map(|bytes| format!("{}", bytes as f64 / (1024.0 * 1024.0 * 1024.0)))
+            .unwrap_or_default()
+    });
+    let url = format!("http://localhost:8000/api/v1/admin/users/{}", user.id);
+    let disabled = user.disabled_at.is_some();
+
+    rsx! {
+        div {
+            class: "flex px-2 p-1 gap-4 items-center",
+            i {
+                class: "material-icons text-neutral-400",
+                if disabled { "person_off" } else if user.is_admin { "admin_panel_settings" } else { "person" }
+            }
+            div {
+                class: "flex flex-col",
+                span { "{user.email}" }
+                span { class: "text-sm text-neutral-400", {user.name.clone().unwrap_or_default()} }
+            }
+            span {
+                class: "ml-auto text-sm text-neutral-400",
+                match user.quota {
+                    Some(limit) => rsx! { "{format_size(user.used_bytes)} of {format_size(limit)}" },
+                    None => rsx! { "{format_size(user.used_bytes)}" },
+                }
+            }
+            span { class: "text-sm text-neutral-400", "{user.files} files, {user.sessions} sessions" }
+            input {
+                class: "w-24 px-2 py-1 rounded bg-neutral-800",
+                placeholder: "Quota (GB)",
+                value: quota,
+                oninput: move |evt| quota.set(evt.value()),
+            }
+            button {
+                class: "p-1 rounded-lg hover:bg-neutral-700",
+                title: "Set quota",
+                onclick: {
+                    let url = url.clone();
+                    move |_| {
+                        let bytes = quota()
+                            .trim()
+                            .parse::<f64>()
+                            .ok()
+                            .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as i64);
+                        let request = reqwest::Client::new()
+                            .put(format!("{url}/quota"))
+                            .json(&serde_json::json!({ "quota_bytes": bytes }));
+                        send((request, "Quota set"))
+                    }
+                },
+                i { class: "material-icons", "save" }
+            }
+            button {
+                class: "p-1 rounded-lg hover:bg-neutral-700",
+                title: if disabled { "Enable" } else { "Disable" },
+                onclick: {
+                    let url = url.clone();
+                    move |_| {
+                        let request = reqwest::Client::new()
+                            .put(format!("{url}/disabled"))
+                            .json(&serde_json::json!({ "disabled": !disabled }));
+                        send((request, if disabled { "User enabled" } else { "User disabled" }))
+                    }
+                },
+                i { class: "material-icons", if disabled { "check_circle" } else { "block" } }
+            }
+            button {
+                class: "p-1 rounded-lg hover:bg-neutral-700 text-red-400",
+                title: "Delete with all files",
+                onclick: move |_| send((reqwest::Client::new().delete(url.clone()), "User deleted")),
+                i { class: "material-icons", "delete" }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, serde::Deserialize)]
+struct TeamInfo {
+    id: String,
+    name: String,
+    role: String,
+    root_folder_id: String,
+    used_bytes: i64,
+    quota: Option<i64>,
+    members: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct TeamMember {
+    user_id: String,
+    email: String,
+    role: String,
+}
+
+/// The user's teams, and creating new ones
+#[component]
+fn Teams() -> Element {
+    let mut name = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+    let mut teams = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/teams")
+            .await?
+            .json::<Vec<TeamInfo>>()
+            .await
+    });
+
+    let create = move |_| async move {
+        let created = reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/teams")
+            .json(&serde_json::json!({ "name": name() }))
+            .send()
+            .await;
+        if created.is_ok_and(|r| r.status().is_success()) {
+            name.set(String::new());
+            error.set(None);
+            teams.restart();
+        } else {
+            error.set(Some("Creating the team failed".to_string()));
+        }
+    };
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            h1 { class: "text-3xl", "Teams" }
+            if let Some(message) = error() {
+                p { class: "mt-2 text-red-400", "{message}" }
+            }
+            div {
+                class: "mt-4 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(teams)) = &*teams.read() {
+                    for team in teams {
+                        Link {
+                            to: Route::Team { id: team.id.clone() },
+                            class: "flex px-2 p-1 gap-4 items-center hover:bg-neutral-800",
+                            i { class: "material-icons text-neutral-400", "group" }
+                            span { "{team.name}" }
+                            span { class: "ml-auto text-sm text-neutral-400", "{team.members} members" }
+                            span { class: "text-sm text-neutral-400", "{team.role}" }
+                        }
+                    }
+                }
+            }
+            div {
+                class: "mt-4 flex gap-2",
+                input {
+                    class: "px-2 py-1 rounded bg-neutral-800",
+                    placeholder: "New team",
+                    value: name,
+                    oninput: move |evt| name.set(evt.value()),
+                }
+                button {
+                    class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                    onclick: create,
+                    "Create"
+                }
+            }
+        }
+    }
+}
+
+/// A team drive: its files and members, and handing own folders over to it
+#[component]
+fn Team(id: String) -> Element {
+    let mut status = use_signal(|| None::<String>);
+    let mut email = use_signal(String::new);
+    let mut role = use_signal(|| "editor".to_string());
+    let mut folder = use_signal(String::new);
+
+    let mut team = use_resource(use_reactive!(|id| async move {
+        let teams = reqwest::get("http://localhost:8000/api/v1/teams")
+            .await?
+            .json::<Vec<TeamInfo>>()
+            .await?;
+        Ok::<_, reqwest::Error>(teams.into_iter().find(|team| team.id == id))
+    }));
+    let mut files = use_resource(move || async move {
+        let root = match &*team.read() {
+            Some(Ok(Some(team))) => team.root_folder_id.clone(),
+            _ => return None,
+        };
+        let response = reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/files")
+            .json(&serde_json::json!({ "folder_id": root }))
+            .send()
+            .await
+            .ok()?;
+        response.json::<Listing>().await.ok()
+    });
+    let mut members = use_resource(use_reactive!(|id| async move {
+        reqwest::get(format!("http://localhost:8000/api/v1/teams/{id}/members"))
+            .await?
+            .json::<Vec<TeamMember>>()
+            .await
+    }));
+    // the folders in the root of the user's own drive, which can be handed over
+    let mut own_folders = use_resource(|| async move {
+        reqwest::Client::new()
+            .post("http://localhost:8000/api/v1/files")
+            .json(&serde_json::json!({ "kind": "folder" }))
+            .send()
+            .await?
+            .json::<Listing>()
+            .await
+    });
+
+    // sends a change and reloads everything it may have touched
+    let send = move |(request, done): (reqwest::RequestBuilder, &'static str)| async move {
+        status.set(Some(match request.send().await {
+            Ok(response) if response.status().is_success() => done.to_string(),
+            Ok(response) => {
+                let message = response
+                    .json::<serde_json::Value>()
+                    .await
+                    .ok()
+                    .and_then(|body| body["message"].as_str().map(str::to_string));
+                message.unwrap_or_else(|| "Failed".to_string())
+            }
+            Err(_) => "Failed".to_string(),
+        }));
+        team.restart();
+        files.restart();
+        members.restart();
+        own_folders.restart();
+    };
+
+    let url = format!("http://localhost:8000/api/v1/teams/{id}");
+    let add = {
+        let url = url.clone();
+        move |_| {
+            let request = reqwest::Client::new()
+                .post(format!("{url}/members"))
+                .json(&serde_json::json!({ "email": email(), "role": role() }));
+            email.set(String::new());
+            send((request, "Member added"))
+        }
+    };
+    let transfer = {
+        let url = url.clone();
+        move |_| {
+            let request = reqwest::Client::new()
+                .post(format!("{url}/transfer"))
+                .json(&serde_json::json!({ "folder_id": folder() }));
+            folder.set(String::new());
+            send((request, "Folder moved to the team drive"))
+        }
+    };
+    let delete = {
+        let url = url.clone();
+        move |_| {
+            let url = url.clone();
+            async move {
+                let deleted = reqwest::Client::new().delete(url).send().await;
+                if deleted.is_ok_and(|r| r.status().is_success()) {
+                    navigator().push(Route::Teams {});
+                } else {
+                    status.set(Some("Deleting the team failed".to_string()));
+                }
+            }
+        }
+    };
+
+    let info = match &*team.read() {
+        Some(Ok(Some(info))) => Some(info.clone()),
+        _ => None,
+    };
+    let Some(info) = info else {
+        return rsx! {
+            h1 { class: "text-3xl", "Team" }
+            p { class: "mt-4 text-neutral-400", "Loading..." }
+        };
+    };
+    let owner = info.role == "owner";
+    let editor = owner || info.role == "editor";
+
+    rsx! {
+        div {
+            class: "h-full overflow-y-auto",
+            div {
+                class: "flex items-center gap-4",
+                h1 { class: "text-3xl", "{info.name}" }
+                span {
+                    class: "text-sm text-neutral-400",
+                    match info.quota {
+                        Some(quota) => rsx! { "{format_size(info.used_bytes)} of {format_size(quota)} used" },
+                        None => rsx! { "{format_size(info.used_bytes)} used" },
+                    }
+                }
+                span { class: "ml-auto text-sm text-neutral-400", "{info.role}" }
+            }
+            if let Some(message) = status() {
+                p { class: "mt-2 text-neutral-400", "{message}" }
+            }
+
+            div {
+                class: "mt-4 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Some(listing)) = &*files.read() {
+                    for entry in &listing.entries {
+                        div {
+                            class: "flex px-2 p-1 items-center",
+                            i {
+                                class: "material-icons text-neutral-400",
+                                if entry.kind == "folder" { "folder" } else if entry.video_status.is_some() { "movie" } else { "description" }
+                            }
+                            span { class: "ml-2", "{entry.name}" }
+                        }
+                    }
+                    div {
+                        class: "px-2 pt-2 text-sm text-neutral-400",
+                        "{listing.total_folders} folders, {listing.total_files} files"
+                    }
+                }
+            }
+            if editor {
+                // moves a folder with everything in it out of the user's own drive
+                div {
+                    class: "mt-4 flex gap-2",
+                    select {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        value: folder,
+                        onchange: move |evt| folder.set(evt.value()),
+                        option { value: "", "Move one of your folders here..." }
+                        if let Some(Ok(listing)) = &*own_folders.read() {
+                            for entry in &listing.entries {
+                                option { value: "{entry.id}", "{entry.name}" }
+                            }
+                        }
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                        disabled: folder().is_empty(),
+                        onclick: transfer,
+                        "Move to team"
+                    }
+                }
+            }
+
+            h2 { class: "text-xl mt-6", "Members" }
+            div {
+                class: "mt-2 flex flex-col divide-y-1 divide-neutral-700",
+                if let Some(Ok(members)) = &*members.read() {
+                    for member in members {
+                        div {
+                            class: "flex px-2 p-1 gap-4 items-center",
+                            i { class: "material-icons text-neutral-400", "person" }
+                            span { "{member.email}" }
+                            if owner {
+                                select {
+                                    class: "ml-auto px-2 py-1 rounded bg-neutral-800",
+                                    value: "{member.role}",
+                                    onchange: {
+                                        let url = format!("{url}/members/{}", member.user_id);
+                                        move |evt: FormEvent| {
+                                            let request = reqwest::Client::new()
+                                                .put(url.clone())
+                                                .json(&serde_json::json!({ "role": evt.value() }));
+                                            send((request, "Role changed"))
+                                        }
+                                    },
+                                    option { value: "owner", "owner" }
+                                    option { value: "editor", "editor" }
+                                    option { value: "viewer", "viewer" }
+                                }
+                                button {
+                                    class: "p-1 rounded-lg hover:bg-neutral-700 text-red-400",
+                                    title: "Remove from the team",
+                                    onclick: {
+                                        let url = format!("{url}/members/{}", member.user_id);
+                                        move |_| send((reqwest::Client::new().delete(url.clone()), "Member removed"))
+                                    },
+                                    i { class: "material-icons", "person_remove" }
+                                }
+                            } else {
+                                span { class: "ml-auto text-sm text-neutral-400", "{member.role}" }
+                            }
+                        }
+                    }
+                }
+            }
+            if owner {
+                div {
+                    class: "mt-4 flex gap-2",
+                    input {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        placeholder: "Email",
+                        value: email,
+                        oninput: move |evt| email.set(evt.value()),
+                    }
+                    select {
+                        class: "px-2 py-1 rounded bg-neutral-800",
+                        value: role,
+                        onchange: move |evt| role.set(evt.value()),
+                        option { value: "editor", "editor" }
+                        option { value: "viewer", "viewer" }
+                        option { value: "owner", "owner" }
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-lg bg-blue-500/50 hover:bg-blue-500/30",
+                        onclick: add,
+                        "Add member"
+                    }
+                }
+                div {
+                    class: "mt-6",
+                    button {
+                        class: "px-3 py-1 rounded-lg text-red-400 hover:bg-neutral-700",
+                        onclick: delete,
+                        "Delete team with all files"
+                    }
+                }
+            }
+        }
+    }
+}
+
 struct UploadedFile {
     name: String,
-    contents: String,
+    contents: Vec<u8>,
 }
 
 #[component]
@@ -159,7 +1372,7 @@ fn FileUpload() -> Element {
     let read_files = move |file_engine: Arc<dyn FileEngine>| async move {
         let files = file_engine.files();
         for file_name in &files {
-            if let Some(contents) = file_engine.read_file_to_string(file_name).await {
+            if let Some(contents) = file_engine.read_file(file_name).await {
                 files_uploaded.write().push(UploadedFile {
                     name: file_name.clone(),
                     contents,
@@ -178,9 +1391,9 @@ fn FileUpload() -> Element {
         let mut multipart = reqwest::multipart::Form::new();
         
         for file in files_uploaded.read().iter() {
-            let part = Part::text(file.contents.clone())
-                .file_name(file.name.clone())
-                .mime_str("text/plain").unwrap();
+            // the server determines the type from the contents
+            let part = Part::bytes(file.contents.clone())
+                .file_name(file.name.clone());
             multipart = multipart.part(file.name.clone(), part);
         };
 
@@ -266,8 +1479,19 @@ fn Navbar() -> Element {
 
 #[component]
 fn Sidebar() -> Element {
-    let path: Route = use_route();
-    
+    let usage = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/account/usage")
+            .await?
+            .json::<Usage>()
+            .await
+    });
+    let teams = use_resource(|| async move {
+        reqwest::get("http://localhost:8000/api/v1/teams")
+            .await?
+            .json::<Vec<TeamInfo>>()
+            .await
+    });
+
     rsx! {
         div {
             class: "w-64 pt-17 h-full fixed",
@@ -279,6 +1503,36 @@ fn Sidebar() -> Element {
                     active_class: "bg-blue-500/50 hover:bg-blue-500/30",
                     "Home"
                 }
+                Link {
+                    to: Route::Photos {},
+                    class: "flex items-center px-4 p-2 rounded-lg hover:bg-neutral-700",
+                    active_class: "bg-blue-500/50 hover:bg-blue-500/30",
+                    "Photos"
+                }
+                Link {
+                    to: Route::Vaults {},
+                    class: "flex items-center px-4 p-2 rounded-lg hover:bg-neutral-700",
+                    active_class: "bg-blue-500/50 hover:bg-blue-500/30",
+                    "Vaults"
+                }
+                Link {
+                    to: Route::Teams {},
+                    class: "flex items-center px-4 p-2 rounded-lg hover:bg-neutral-700",
+                    active_class: "bg-blue-500/50 hover:bg-blue-500/30",
+                    "Teams"
+                }
+                // the team drives, right below
+                if let Some(Ok(teams)) = &*teams.read() {
+                    for team in teams {
+                        Link {
+                            to: Route::Team { id: team.id.clone() },
+                            class: "flex items-center gap-2 ml-4 px-4 p-1 rounded-lg text-sm hover:bg-neutral-700",
+                            active_class: "bg-blue-500/50 hover:bg-blue-500/30",
+                            i { class: "material-icons !text-base text-neutral-400", "group" }
+                            "{team.name}"
+                        }
+                    }
+                }
                 Link {
                     to: Route::Home {},
                     class: "flex items-center px-4 p-2 rounded-lg hover:bg-neutral-700",
@@ -303,7 +1557,53 @@ fn Sidebar() -> Element {
                     active_class: "bg-blue-500/50 hover:bg-blue-500/30",
                     "Trash"
                 }
+                Link {
+                    to: Route::Admin {},
+                    class: "flex items-center px-4 p-2 rounded-lg hover:bg-neutral-700",
+                    active_class: "bg-blue-500/50 hover:bg-blue-500/30",
+                    "Admin"
+                }
+                // storage usage at the bottom of the sidebar
+                if let Some(Ok(usage)) = &*usage.read() {
+                    div {
+                        class: "mt-auto px-4 flex flex-col gap-1 text-sm text-neutral-400",
+                        if let Some(quota) = usage.quota {
+                            div {
+                                class: "h-1.5 rounded-full bg-neutral-700 overflow-hidden",
+                                div {
+                                    class: if usage.used > quota { "h-full bg-red-500" } else { "h-full bg-blue-500" },
+                                    style: "width: {(usage.used as f64 / quota.max(1) as f64 * 100.0).min(100.0)}%",
+                                }
+                            }
+                            "{format_size(usage.used)} of {format_size(quota)} used"
+                        } else {
+                            "{format_size(usage.used)} used"
+                        }
+                    }
+                }
             }
         }
     }
 }
+
+#[derive(serde::Deserialize)]
+struct Usage {
+    used: i64,
+    quota: Option<i64>,
+}
+
+/// A byte count like `1.5 GB`.
+fn format_size(bytes: i64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KB", "MB", "GB"] {
+        if size < 1024.0 {
+            return if unit == "B" {
+                format!("{bytes} B")
+            } else {
+                format!("{size:.1} {unit}")
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TB")
+}
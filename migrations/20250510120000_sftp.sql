@@ -0,0 +1,12 @@
+-- Password used for SFTP (and later interactive) logins, hashed with argon2
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+-- Public keys a user may authenticate with over SSH/SFTP
+CREATE TABLE ssh_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    fingerprint TEXT UNIQUE NOT NULL,  -- SHA256 fingerprint, used for lookups during authentication
+    created_at TIMESTAMP DEFAULT now()
+);
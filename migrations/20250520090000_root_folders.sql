@@ -0,0 +1,84 @@
+-- Every user has exactly one real root folder: the only folder without a parent.
+-- Top-level folders and files live inside it, so `parent_id`/`folder_id` always
+-- point to an existing row and per-folder uniqueness also applies at the top level.
+
+-- Top-level folders were never unique by name, NULL parents don't compare equal.
+-- All but one of each name get their id appended before they share a parent, as
+-- orphaned files do below; those under the nil UUID folder were unique already
+-- and keep theirs, the nil UUID folder itself goes away.
+UPDATE folders f
+SET name = f.name || ' (' || left(f.id::text, 8) || ')'
+FROM (
+    SELECT id, row_number() OVER (
+        PARTITION BY user_id, name
+        ORDER BY id = '00000000-0000-0000-0000-000000000000', parent_id NULLS LAST, id
+    ) AS n
+    FROM folders
+    WHERE parent_id IS NULL OR parent_id = '00000000-0000-0000-0000-000000000000'
+) d
+WHERE d.id = f.id AND d.n > 1;
+
+-- Create a root folder for every existing user and move their top-level folders into it
+WITH roots AS (
+    INSERT INTO folders (user_id, name, parent_id)
+    SELECT id, '', NULL FROM users
+    RETURNING id, user_id
+)
+UPDATE folders f
+SET parent_id = r.id
+FROM roots r
+WHERE f.user_id = r.user_id AND f.parent_id IS NULL;
+
+-- Files were stored in the "root" with the nil UUID, or lost their folder through
+-- ON DELETE SET NULL; both belong in the root folder now. Files in the nil UUID
+-- folder were unique already and keep their names, all but one orphan of each
+-- name get their id appended.
+UPDATE files f
+SET filename = f.filename || ' (' || left(f.id::text, 8) || ')'
+FROM (
+    SELECT id, row_number() OVER (
+        PARTITION BY user_id, filename
+        ORDER BY folder_id IS NULL, id
+    ) AS n
+    FROM files
+    WHERE folder_id IS NULL OR folder_id = '00000000-0000-0000-0000-000000000000'
+) d
+WHERE d.id = f.id AND d.n > 1;
+
+UPDATE files f
+SET folder_id = r.id
+FROM folders r
+WHERE r.user_id = f.user_id
+  AND r.parent_id IS NULL
+  AND (f.folder_id IS NULL OR f.folder_id = '00000000-0000-0000-0000-000000000000');
+
+-- A placeholder folder row with the nil UUID was the only way to satisfy the
+-- foreign key before; its subfolders move to the real root.
+UPDATE folders f
+SET parent_id = r.id
+FROM folders r
+WHERE f.parent_id = '00000000-0000-0000-0000-000000000000'
+  AND r.user_id = f.user_id
+  AND r.parent_id IS NULL;
+
+DELETE FROM folders WHERE id = '00000000-0000-0000-0000-000000000000';
+
+-- Files always live in a folder
+ALTER TABLE files ALTER COLUMN folder_id SET NOT NULL;
+ALTER TABLE files
+    DROP CONSTRAINT files_folder_id_fkey,
+    ADD CONSTRAINT files_folder_id_fkey FOREIGN KEY (folder_id) REFERENCES folders(id) ON DELETE CASCADE;
+
+CREATE UNIQUE INDEX folders_one_root_per_user ON folders (user_id) WHERE parent_id IS NULL;
+
+-- New users get their root folder on creation, however they sign up
+CREATE FUNCTION create_root_folder() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO folders (user_id, name, parent_id) VALUES (NEW.id, '', NULL);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_create_root_folder
+    AFTER INSERT ON users
+    FOR EACH ROW EXECUTE FUNCTION create_root_folder();
@@ -0,0 +1,34 @@
+-- Full-text search over file and folder names and the text extracted from documents.
+-- The 'simple' configuration is used throughout: names and documents can be in any
+-- language, and prefix queries (`report:*`) take the place of stemming.
+
+-- Words in names are split on anything that is not a letter or digit, so that
+-- `quarterly_report-2025.pdf` matches `quarterly`, `report`, `2025` and `pdf`.
+CREATE FUNCTION search_words(name TEXT) RETURNS TEXT AS $$
+    SELECT regexp_replace(name, '[^[:alnum:]]+', ' ', 'g')
+$$ LANGUAGE sql IMMUTABLE;
+
+ALTER TABLE folders
+    ADD COLUMN name_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', search_words(name))) STORED;
+CREATE INDEX folders_name_tsv ON folders USING GIN (name_tsv);
+
+ALTER TABLE files
+    ADD COLUMN name_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', search_words(filename))) STORED;
+CREATE INDEX files_name_tsv ON files USING GIN (name_tsv);
+
+-- Set by the background indexer once a file's contents have been processed;
+-- NULL means the file is still waiting to be indexed.
+ALTER TABLE files ADD COLUMN indexed_at TIMESTAMP;
+
+-- Text extracted from plain text, Markdown, PDF and Office documents
+CREATE TABLE file_contents (
+    file_id UUID PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED
+);
+CREATE INDEX file_contents_tsv ON file_contents USING GIN (content_tsv);
+
+-- Snippets are returned with <mark> highlights, so the document text around them is escaped
+CREATE FUNCTION html_escape(t TEXT) RETURNS TEXT AS $$
+    SELECT replace(replace(replace(t, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')
+$$ LANGUAGE sql IMMUTABLE;
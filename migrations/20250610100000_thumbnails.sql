@@ -0,0 +1,4 @@
+-- Set by the background thumbnailer once it has processed a file; NULL means pending.
+ALTER TABLE files ADD COLUMN thumbnailed_at TIMESTAMP;
+-- Whether thumbnails were generated (only images and PDFs get one)
+ALTER TABLE files ADD COLUMN has_thumbnail BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- HLS streaming state of uploaded videos: NULL for other files, otherwise
+-- 'pending' -> 'processing' -> 'ready' or 'failed'.
+ALTER TABLE files ADD COLUMN video_status TEXT
+    CHECK (video_status IN ('pending', 'processing', 'ready', 'failed'));
+-- Length in seconds, known once the video has been probed
+ALTER TABLE files ADD COLUMN video_duration DOUBLE PRECISION;
+
+CREATE INDEX files_video_pending ON files (last_modified) WHERE video_status = 'pending';
+
+-- Videos uploaded before streaming existed
+UPDATE files SET video_status = 'pending'
+WHERE lower(substring(filename FROM '\.([^.]+)$')) IN ('mp4', 'm4v', 'mov', 'mkv', 'webm', 'avi', 'wmv', 'flv', 'mpg', 'mpeg', '3gp');
@@ -0,0 +1,19 @@
+-- EXIF metadata of images, recorded by the thumbnailer
+CREATE TABLE photo_metadata (
+    file_id UUID PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    taken_at TIMESTAMP,               -- DateTimeOriginal, in the camera's local time
+    camera_make TEXT,
+    camera_model TEXT,
+    width INTEGER NOT NULL,           -- as displayed, i.e. after applying the orientation
+    height INTEGER NOT NULL,
+    orientation SMALLINT,             -- EXIF orientation tag (1-8)
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION
+);
+
+CREATE INDEX photo_metadata_timeline ON photo_metadata (user_id, taken_at DESC);
+
+-- Images thumbnailed before metadata was extracted are processed again
+UPDATE files SET thumbnailed_at = NULL
+WHERE lower(substring(filename FROM '\.([^.]+)$')) IN ('jpg', 'jpeg', 'png', 'gif', 'webp', 'bmp', 'tif', 'tiff');
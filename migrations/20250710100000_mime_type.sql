@@ -0,0 +1,2 @@
+-- Content type sniffed from the file's contents on upload
+ALTER TABLE files ADD COLUMN mime_type TEXT NOT NULL DEFAULT 'application/octet-stream';
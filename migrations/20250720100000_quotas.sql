@@ -0,0 +1,27 @@
+-- Storage quotas. NULL uses the server-wide default (`CLOUD_DEFAULT_QUOTA`).
+ALTER TABLE users ADD COLUMN quota_bytes BIGINT CHECK (quota_bytes >= 0);
+
+-- Total size of the user's files, kept up to date by the trigger below
+ALTER TABLE users ADD COLUMN used_bytes BIGINT NOT NULL DEFAULT 0;
+
+UPDATE users u
+SET used_bytes = COALESCE((SELECT sum(size) FROM files f WHERE f.user_id = u.id), 0);
+
+-- Every change to files is accounted for in the same transaction, whichever code
+-- path (uploads, deletes, folders deleted with everything in them) makes it.
+-- Updating the user row also serializes concurrent uploads of the same user.
+CREATE FUNCTION account_file_usage() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE users SET used_bytes = used_bytes - OLD.size WHERE id = OLD.user_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        UPDATE users SET used_bytes = used_bytes + NEW.size WHERE id = NEW.user_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_account_usage
+    AFTER INSERT OR DELETE OR UPDATE OF size, user_id ON files
+    FOR EACH ROW EXECUTE FUNCTION account_file_usage();
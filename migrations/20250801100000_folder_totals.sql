@@ -0,0 +1,81 @@
+-- Recursive totals of everything below a folder, kept up to date by the triggers
+-- below so listings never have to walk the tree.
+ALTER TABLE folders ADD COLUMN total_size BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE folders ADD COLUMN file_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE folders ADD COLUMN folder_count BIGINT NOT NULL DEFAULT 0;
+
+WITH RECURSIVE tree AS (
+    SELECT id AS ancestor, id FROM folders
+    UNION ALL
+    SELECT t.ancestor, f.id FROM folders f JOIN tree t ON f.parent_id = t.id
+), direct AS (
+    SELECT folder_id, sum(size) AS size, count(*) AS files FROM files GROUP BY folder_id
+), totals AS (
+    SELECT t.ancestor,
+           COALESCE(sum(d.size), 0) AS size,
+           COALESCE(sum(d.files), 0) AS files,
+           count(*) - 1 AS folders
+    FROM tree t
+    LEFT JOIN direct d ON d.folder_id = t.id
+    GROUP BY t.ancestor
+)
+UPDATE folders
+SET total_size = totals.size, file_count = totals.files, folder_count = totals.folders
+FROM totals
+WHERE folders.id = totals.ancestor;
+
+-- Adds to the totals of `folder` and all of its ancestors. Does nothing if `folder`
+-- is gone, which is the case for everything removed along with a deleted folder:
+-- the deleted folder's own row already carries their totals.
+CREATE FUNCTION add_to_folder_totals(folder UUID, delta_size BIGINT, delta_files BIGINT, delta_folders BIGINT)
+RETURNS void AS $$
+    WITH RECURSIVE ancestors AS (
+        SELECT id, parent_id FROM folders WHERE id = folder
+        UNION ALL
+        SELECT f.id, f.parent_id FROM folders f JOIN ancestors a ON f.id = a.parent_id
+    )
+    UPDATE folders
+    SET total_size = total_size + delta_size,
+        file_count = file_count + delta_files,
+        folder_count = folder_count + delta_folders
+    WHERE id IN (SELECT id FROM ancestors);
+$$ LANGUAGE sql;
+
+CREATE FUNCTION account_file_totals() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        PERFORM add_to_folder_totals(OLD.folder_id, -OLD.size, -1, 0);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        PERFORM add_to_folder_totals(NEW.folder_id, NEW.size, 1, 0);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_account_totals
+    AFTER INSERT OR DELETE OR UPDATE OF size, folder_id ON files
+    FOR EACH ROW EXECUTE FUNCTION account_file_totals();
+
+-- A folder counts as one folder plus everything below it.
+CREATE FUNCTION account_folder_totals() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.parent_id IS NOT NULL THEN
+        PERFORM add_to_folder_totals(
+            OLD.parent_id, -OLD.total_size, -OLD.file_count, -OLD.folder_count - 1);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.parent_id IS NOT NULL THEN
+        PERFORM add_to_folder_totals(
+            NEW.parent_id, NEW.total_size, NEW.file_count, NEW.folder_count + 1);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER folders_account_totals
+    AFTER INSERT OR DELETE OR UPDATE OF parent_id ON folders
+    FOR EACH ROW EXECUTE FUNCTION account_folder_totals();
+
+-- For the largest items report
+CREATE INDEX files_user_id_size ON files (user_id, size DESC);
+CREATE INDEX folders_user_id_total_size ON folders (user_id, total_size DESC);
@@ -0,0 +1,9 @@
+-- Envelope encryption at rest. Encrypted blobs have their own random data key,
+-- stored here wrapped (encrypted) with the master key `key_id`. Files without a
+-- data key are stored in plain text.
+ALTER TABLE files ADD COLUMN key_id TEXT;
+ALTER TABLE files ADD COLUMN data_key BYTEA;
+ALTER TABLE files ADD CONSTRAINT files_data_key_check CHECK ((key_id IS NULL) = (data_key IS NULL));
+
+-- Finds the keys still wrapped with an old master key after a rotation
+CREATE INDEX files_key_id ON files (key_id) WHERE key_id IS NOT NULL;
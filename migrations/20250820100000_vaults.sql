@@ -0,0 +1,26 @@
+-- End-to-end encrypted vault folders. Everything here is encrypted by the client;
+-- the server never sees a plain text key, file name or file.
+
+-- A user's X25519 key pair: the public key in the clear, the secret key encrypted
+-- with a key derived from the user's vault passphrase.
+CREATE TABLE user_keys (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    public_key BYTEA NOT NULL,
+    locked_secret BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Files in vault folders have encrypted names and contents.
+ALTER TABLE folders ADD COLUMN vault BOOLEAN NOT NULL DEFAULT false;
+
+-- The folder key of a vault, wrapped for each member's public key. The owner is
+-- a member too.
+CREATE TABLE vault_members (
+    folder_id UUID NOT NULL REFERENCES folders(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wrapped_key BYTEA NOT NULL,
+    added_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (folder_id, user_id)
+);
+
+CREATE INDEX vault_members_user_id ON vault_members (user_id);
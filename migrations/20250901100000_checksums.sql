@@ -0,0 +1,8 @@
+-- SHA-256 of each file's plain text, computed on upload. Files uploaded before
+-- have none until `backend verify` hashes them.
+ALTER TABLE files ADD COLUMN sha256 BYTEA CHECK (octet_length(sha256) = 32);
+-- When the blob was last re-hashed, and whether it no longer matched.
+ALTER TABLE files ADD COLUMN verified_at TIMESTAMP;
+ALTER TABLE files ADD COLUMN corrupt BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX files_corrupt ON files (id) WHERE corrupt;
@@ -0,0 +1,2 @@
+-- Set by `backend fsck --repair` for files whose blob is gone from disk.
+ALTER TABLE files ADD COLUMN missing BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,19 @@
+-- Persistent background jobs, see `backend/src/jobs.rs`. Finished jobs are
+-- deleted; jobs that ran out of attempts stay with `failed_at` set.
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    -- not before
+    run_at TIMESTAMP NOT NULL DEFAULT now(),
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL DEFAULT 5,
+    -- set while a runner works on the job; if the runner dies, the job is picked
+    -- up again once this has passed
+    locked_until TIMESTAMP,
+    last_error TEXT,
+    failed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX jobs_due ON jobs (run_at) WHERE failed_at IS NULL;
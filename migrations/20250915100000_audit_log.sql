@@ -0,0 +1,38 @@
+-- Who did what, see `backend/src/audit.rs`. Rows are never changed or removed:
+-- the triggers below reject it, even for the table's owner.
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMP NOT NULL DEFAULT now(),
+    -- no foreign key, the log outlives deleted users
+    actor_id UUID NOT NULL,
+    -- e.g. `file.delete`
+    action TEXT NOT NULL,
+    -- `file`, `folder` or `vault`
+    target_type TEXT NOT NULL,
+    target_id UUID NOT NULL,
+    old_value JSONB,
+    new_value JSONB,
+    ip INET,
+    user_agent TEXT
+);
+
+CREATE INDEX audit_log_at ON audit_log (at);
+CREATE INDEX audit_log_actor ON audit_log (actor_id, at);
+CREATE INDEX audit_log_action ON audit_log (action, at);
+CREATE INDEX audit_log_target ON audit_log (target_id);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_update_or_delete
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
+
+-- may query the audit log, see `GET /admin/audit`
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,21 @@
+-- Personal access tokens for scripts, see `backend/src/auth.rs`.
+CREATE TABLE api_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token, which is only shown once, when it is created
+    token_hash BYTEA NOT NULL UNIQUE CHECK (octet_length(token_hash) = 32),
+    -- start of the token, to tell tokens apart
+    prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL CHECK (
+        cardinality(scopes) > 0 AND scopes <@ ARRAY['read', 'write', 'upload']
+    ),
+    -- the token only reaches this folder and what is below it
+    folder_id UUID REFERENCES folders(id) ON DELETE CASCADE,
+    expires_at TIMESTAMP,
+    last_used_at TIMESTAMP,
+    last_used_ip INET,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX api_tokens_user ON api_tokens (user_id);
@@ -0,0 +1,24 @@
+-- Browser sessions, created by single sign-on; see `backend/src/session.rs`.
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA-256 of the session cookie
+    token_hash BYTEA NOT NULL UNIQUE CHECK (octet_length(token_hash) = 32),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    ip INET,
+    user_agent TEXT
+);
+
+CREATE INDEX sessions_user ON sessions (user_id);
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
+
+-- Logins on their way through the identity provider, keyed by the OAuth `state`.
+CREATE TABLE oidc_logins (
+    state TEXT PRIMARY KEY,
+    pkce_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    -- where to send the browser once logged in
+    return_to TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
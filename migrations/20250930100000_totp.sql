@@ -0,0 +1,36 @@
+-- Two-factor authentication with time-based one-time passwords, see `backend/src/totp.rs`.
+
+-- Whether the user must use TOTP; NULL follows `CLOUD_TOTP_REQUIRED`.
+ALTER TABLE users ADD COLUMN totp_required BOOLEAN;
+
+CREATE TABLE user_totp (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret BYTEA NOT NULL,
+    -- NULL until the user entered a first code, proving the app is set up
+    confirmed_at TIMESTAMP,
+    -- time step of the last accepted code, so no code is accepted twice
+    last_step BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- One-time codes for when the authenticator app is lost.
+CREATE TABLE recovery_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA-256 of the code
+    code_hash BYTEA NOT NULL CHECK (octet_length(code_hash) = 32),
+    used_at TIMESTAMP
+);
+
+CREATE INDEX recovery_codes_user ON recovery_codes (user_id);
+
+-- Password logins waiting for the second factor.
+CREATE TABLE login_challenges (
+    -- SHA-256 of the challenge handed to the client
+    token_hash BYTEA PRIMARY KEY CHECK (octet_length(token_hash) = 32),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- `enroll` if the user has to set up TOTP before they may log in
+    kind TEXT NOT NULL CHECK (kind IN ('verify', 'enroll')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- Groups of users from the identity provider or LDAP directory, by name, as of
+-- their last login; see `backend/src/ldap.rs`.
+ALTER TABLE users ADD COLUMN groups TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- Disabled users can't log in or use their API tokens, but keep their files; see
+-- `PUT /admin/users/{id}/disabled`.
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMP;
@@ -0,0 +1,79 @@
+-- Teams own drives, so their files survive members leaving; see
+-- `backend/src/routes/team.rs`.
+CREATE TABLE teams (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    -- as for users: NULL uses the server-wide default, `used_bytes` is kept up to
+    -- date by `account_file_usage()`
+    quota_bytes BIGINT CHECK (quota_bytes >= 0),
+    used_bytes BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE team_members (
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- owners manage the team, editors change its files, viewers only read them
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    added_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (team_id, user_id)
+);
+
+CREATE INDEX team_members_user_id ON team_members (user_id);
+
+-- Folders and files belong to a user or, in a team drive, to a team. `user_id` is
+-- the owner either way, so it can no longer reference `users`; the triggers below
+-- take over what the foreign keys did.
+ALTER TABLE folders DROP CONSTRAINT folders_user_id_fkey;
+ALTER TABLE files DROP CONSTRAINT files_user_id_fkey;
+ALTER TABLE photo_metadata DROP CONSTRAINT photo_metadata_user_id_fkey;
+
+CREATE FUNCTION ensure_drive_owner() RETURNS trigger AS $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.user_id)
+       AND NOT EXISTS (SELECT 1 FROM teams WHERE id = NEW.user_id) THEN
+        RAISE foreign_key_violation USING MESSAGE = 'no user or team ' || NEW.user_id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER folders_ensure_owner
+    BEFORE INSERT OR UPDATE OF user_id ON folders
+    FOR EACH ROW EXECUTE FUNCTION ensure_drive_owner();
+
+-- Deleting a user or team deletes their folders, and the files in them along with
+-- the folders. Their contents on disk have to be removed by whoever deletes them.
+CREATE FUNCTION delete_drive() RETURNS trigger AS $$
+BEGIN
+    DELETE FROM folders WHERE user_id = OLD.id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_delete_drive
+    AFTER DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION delete_drive();
+CREATE TRIGGER teams_delete_drive
+    AFTER DELETE ON teams
+    FOR EACH ROW EXECUTE FUNCTION delete_drive();
+
+-- Teams get their root folder like users do
+CREATE TRIGGER teams_create_root_folder
+    AFTER INSERT ON teams
+    FOR EACH ROW EXECUTE FUNCTION create_root_folder();
+
+-- Usage is accounted to whoever owns the file
+CREATE OR REPLACE FUNCTION account_file_usage() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE users SET used_bytes = used_bytes - OLD.size WHERE id = OLD.user_id;
+        UPDATE teams SET used_bytes = used_bytes - OLD.size WHERE id = OLD.user_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        UPDATE users SET used_bytes = used_bytes + NEW.size WHERE id = NEW.user_id;
+        UPDATE teams SET used_bytes = used_bytes + NEW.size WHERE id = NEW.user_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
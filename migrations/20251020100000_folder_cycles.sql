@@ -0,0 +1,15 @@
+-- `add_to_folder_totals` walked up `parent_id` with UNION ALL, which never ends
+-- if folders somehow form a cycle. UNION stops at the first row seen twice.
+CREATE OR REPLACE FUNCTION add_to_folder_totals(folder UUID, delta_size BIGINT, delta_files BIGINT, delta_folders BIGINT)
+RETURNS void AS $$
+    WITH RECURSIVE ancestors AS (
+        SELECT id, parent_id FROM folders WHERE id = folder
+        UNION
+        SELECT f.id, f.parent_id FROM folders f JOIN ancestors a ON f.id = a.parent_id
+    )
+    UPDATE folders
+    SET total_size = total_size + delta_size,
+        file_count = file_count + delta_files,
+        folder_count = folder_count + delta_folders
+    WHERE id IN (SELECT id FROM ancestors);
+$$ LANGUAGE sql;
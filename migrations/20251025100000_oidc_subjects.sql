@@ -0,0 +1,11 @@
+-- Who users are at the identity provider, which unlike their email address never
+-- changes; see `backend/src/routes/login.rs`. Users are matched by these, an
+-- account with the same email is only linked by logging in to it first.
+ALTER TABLE users
+    ADD COLUMN oidc_issuer TEXT,
+    ADD COLUMN oidc_subject TEXT,
+    ADD CONSTRAINT users_oidc_subject UNIQUE (oidc_issuer, oidc_subject),
+    ADD CONSTRAINT users_oidc_subject_issuer CHECK ((oidc_issuer IS NULL) = (oidc_subject IS NULL));
+
+-- The account to link the identity to, for logins started from a session.
+ALTER TABLE oidc_logins ADD COLUMN link_user_id UUID REFERENCES users(id) ON DELETE CASCADE;
@@ -0,0 +1,4 @@
+-- The directory entry of users from LDAP; see `backend/src/routes/login.rs`.
+-- Users are matched by it rather than by the entry's email address, which
+-- whoever can edit the directory could set to anyone's.
+ALTER TABLE users ADD COLUMN ldap_dn TEXT UNIQUE;
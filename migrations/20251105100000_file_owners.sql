@@ -0,0 +1,30 @@
+-- Files belong to whoever owns their folder and photo metadata to whoever owns the
+-- file. Since drives may belong to teams, no foreign key says so; see
+-- `20251015100000_teams.sql`. Moving a drive's contents updates folders first.
+CREATE FUNCTION ensure_file_owner() RETURNS trigger AS $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM folders WHERE id = NEW.folder_id AND user_id = NEW.user_id) THEN
+        RAISE foreign_key_violation
+            USING MESSAGE = 'folder ' || NEW.folder_id || ' is not owned by ' || NEW.user_id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_ensure_owner
+    BEFORE INSERT OR UPDATE OF user_id, folder_id ON files
+    FOR EACH ROW EXECUTE FUNCTION ensure_file_owner();
+
+CREATE FUNCTION ensure_photo_owner() RETURNS trigger AS $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM files WHERE id = NEW.file_id AND user_id = NEW.user_id) THEN
+        RAISE foreign_key_violation
+            USING MESSAGE = 'file ' || NEW.file_id || ' is not owned by ' || NEW.user_id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER photo_metadata_ensure_owner
+    BEFORE INSERT OR UPDATE OF user_id, file_id ON photo_metadata
+    FOR EACH ROW EXECUTE FUNCTION ensure_photo_owner();
@@ -0,0 +1,5 @@
+-- Wrong second factors in a row; after too many, nobody can try again for a while,
+-- however many new login challenges they start. See `backend/src/totp.rs`.
+ALTER TABLE users
+    ADD COLUMN totp_failures INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN totp_locked_until TIMESTAMP;